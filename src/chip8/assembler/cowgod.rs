@@ -0,0 +1,346 @@
+//! Assembler for Cowgod-style sources (`LD V3, 0x10`), the syntax `disasm` emits
+//!
+//! Assembly takes two passes: the first lays out the labels, the second evaluates
+//! operands, so labels can be used in expressions before they're defined. Besides
+//! instructions, `DB` and `DW` emit bytes and words and `ORG` moves the output.
+//! Listings from `disasm` assemble as is, their address and bytes columns being skipped.
+use std::collections::BTreeMap;
+
+use super::{Emitter, Program};
+use crate::chip8::debugger::expr::evaluate;
+use crate::chip8::memory::ROM_BASE_ADDR;
+
+/// An instruction operand
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI, // [I]
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Value(String), // An expression, evaluated in the second pass
+}
+
+fn operand(text: &str) -> Operand {
+    match text.to_ascii_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        upper => match upper.as_bytes() {
+            [b'V', digit] if digit.is_ascii_hexdigit() => {
+                Operand::V((*digit as char).to_digit(16).expect("Hex digit") as u8)
+            }
+            _ => Operand::Value(text.to_string()),
+        },
+    }
+}
+
+/// A source line, split into its parts
+struct Line {
+    number: usize,
+    label: Option<String>,
+    mnemonic: Option<String>, // Upper case
+    operands: Vec<Operand>,
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_line(number: usize, text: &str) -> Result<Line, String> {
+    let mut text = text.split(';').next().unwrap_or_default().trim();
+    // `disasm` listing lines: `0x200: 63 10  LD V3, 0x10`
+    if let Some((addr, rest)) = text.split_once(": ") {
+        if addr.starts_with("0x") && number_literal(addr).is_some() {
+            text = rest
+                .split_once("  ")
+                .map_or("", |(_, mnemonic)| mnemonic)
+                .trim();
+        }
+    }
+    let mut label = None;
+    if let Some((name, rest)) = text.split_once(':') {
+        if !is_label(name.trim()) {
+            return Err(format!("Invalid label: {}", name.trim()));
+        }
+        label = Some(name.trim().to_string());
+        text = rest.trim();
+    }
+    let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, operands)) => (
+            mnemonic,
+            operands.split(',').map(|op| operand(op.trim())).collect(),
+        ),
+        None => (text, Vec::new()),
+    };
+    Ok(Line {
+        number,
+        label,
+        mnemonic: (!mnemonic.is_empty()).then(|| mnemonic.to_ascii_uppercase()),
+        operands,
+    })
+}
+
+/// Decimal, 0x-prefixed hexadecimal or 0b-prefixed binary number
+fn number_literal(text: &str) -> Option<i64> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Evaluates an operand over numbers, labels and `$` (the current address)
+fn eval(text: &str, labels: &BTreeMap<String, u16>, here: u16) -> Result<i64, String> {
+    evaluate(text, &|name| match name {
+        "$" => Ok(here as i64),
+        _ => labels
+            .get(name)
+            .map(|addr| *addr as i64)
+            .ok_or_else(|| format!("Undefined label: {}", name)),
+    })
+}
+
+/// Assembles a Cowgod-style source into a rom
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(idx, text)| {
+            parse_line(idx + 1, text).map_err(|err| format!("Line {}: {}", idx + 1, err))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // First pass: where each line goes
+    let mut out = Emitter::new();
+    for line in &lines {
+        let at_line = |err| format!("Line {}: {}", line.number, err);
+        if let Some(label) = &line.label {
+            out.define(label, out.here).map_err(at_line)?;
+        }
+        out.here = match line.mnemonic.as_deref() {
+            None => out.here,
+            Some("ORG") => origin(line, &out.labels, out.here).map_err(at_line)?,
+            Some("DB") => out.here + line.operands.len() as u16,
+            Some("DW") => out.here + 2 * line.operands.len() as u16,
+            Some(_) => out.here + 2,
+        };
+    }
+
+    // Second pass: the bytes themselves
+    out.here = ROM_BASE_ADDR as u16;
+    for line in &lines {
+        let at_line = |err| format!("Line {}: {}", line.number, err);
+        out.line = line.number;
+        let assembler = LineAssembler {
+            labels: &out.labels,
+            here: out.here,
+        };
+        match line.mnemonic.as_deref() {
+            None => (),
+            Some("ORG") => out.here = origin(line, &out.labels, out.here).map_err(at_line)?,
+            Some("DB") => {
+                let bytes = line
+                    .operands
+                    .iter()
+                    .map(|op| assembler.byte(op))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(at_line)?;
+                for byte in bytes {
+                    out.emit(byte).map_err(at_line)?;
+                }
+            }
+            Some("DW") => {
+                let words = line
+                    .operands
+                    .iter()
+                    .map(|op| assembler.word(op))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(at_line)?;
+                for word in words {
+                    out.emit_word(word).map_err(at_line)?;
+                }
+            }
+            Some(mnemonic) => {
+                let word = assembler
+                    .encode(mnemonic, &line.operands)
+                    .map_err(at_line)?;
+                out.emit_word(word).map_err(at_line)?;
+            }
+        }
+    }
+    out.finish()
+}
+
+fn origin(line: &Line, labels: &BTreeMap<String, u16>, here: u16) -> Result<u16, String> {
+    match line.operands.as_slice() {
+        [Operand::Value(addr)] => match eval(addr, labels, here)? {
+            addr @ 0..=0xFFF => Ok(addr as u16),
+            addr => Err(format!("Address out of range: 0x{:X}", addr)),
+        },
+        _ => Err(String::from("ORG takes an address")),
+    }
+}
+
+/// Evaluates the operands of a line in the second pass
+struct LineAssembler<'a> {
+    labels: &'a BTreeMap<String, u16>,
+    here: u16, // Address of the line
+}
+
+impl LineAssembler<'_> {
+    fn value(&self, op: &Operand, min: i64, max: i64) -> Result<i64, String> {
+        match op {
+            Operand::Value(text) => match eval(text, self.labels, self.here)? {
+                value if (min..=max).contains(&value) => Ok(value),
+                _ => Err(format!("Value out of range: {}", text)),
+            },
+            _ => Err(format!("Expected a value, found {:?}", op)),
+        }
+    }
+
+    fn byte(&self, op: &Operand) -> Result<u8, String> {
+        Ok(self.value(op, -128, 0xFF)? as u8)
+    }
+
+    fn word(&self, op: &Operand) -> Result<u16, String> {
+        Ok(self.value(op, -0x8000, 0xFFFF)? as u16)
+    }
+
+    fn addr(&self, op: &Operand) -> Result<u16, String> {
+        Ok(self.value(op, 0, 0xFFF)? as u16)
+    }
+
+    fn encode(&self, mnemonic: &str, ops: &[Operand]) -> Result<u16, String> {
+        use Operand::*;
+        let xy = |x: &u8, y: &u8| (*x as u16) << 8 | (*y as u16) << 4;
+        let x = |x: &u8| (*x as u16) << 8;
+        Ok(match (mnemonic, ops) {
+            ("CLS", []) => 0x00E0,
+            ("RET", []) => 0x00EE,
+            ("SYS", [nnn]) => self.addr(nnn)?,
+            ("JP", [V(0), nnn]) => 0xB000 | self.addr(nnn)?,
+            ("JP", [nnn]) => 0x1000 | self.addr(nnn)?,
+            ("CALL", [nnn]) => 0x2000 | self.addr(nnn)?,
+            ("SE", [V(vx), V(vy)]) => 0x5000 | xy(vx, vy),
+            ("SE", [V(vx), kk]) => 0x3000 | x(vx) | self.byte(kk)? as u16,
+            ("SNE", [V(vx), V(vy)]) => 0x9000 | xy(vx, vy),
+            ("SNE", [V(vx), kk]) => 0x4000 | x(vx) | self.byte(kk)? as u16,
+            ("LD", [V(vx), V(vy)]) => 0x8000 | xy(vx, vy),
+            ("LD", [V(vx), Dt]) => 0xF007 | x(vx),
+            ("LD", [V(vx), K]) => 0xF00A | x(vx),
+            ("LD", [V(vx), IndirectI]) => 0xF065 | x(vx),
+            ("LD", [V(vx), kk]) => 0x6000 | x(vx) | self.byte(kk)? as u16,
+            ("LD", [I, nnn]) => 0xA000 | self.addr(nnn)?,
+            ("LD", [Dt, V(vx)]) => 0xF015 | x(vx),
+            ("LD", [St, V(vx)]) => 0xF018 | x(vx),
+            ("LD", [F, V(vx)]) => 0xF029 | x(vx),
+            ("LD", [B, V(vx)]) => 0xF033 | x(vx),
+            ("LD", [IndirectI, V(vx)]) => 0xF055 | x(vx),
+            ("ADD", [V(vx), V(vy)]) => 0x8004 | xy(vx, vy),
+            ("ADD", [V(vx), kk]) => 0x7000 | x(vx) | self.byte(kk)? as u16,
+            ("ADD", [I, V(vx)]) => 0xF01E | x(vx),
+            ("OR", [V(vx), V(vy)]) => 0x8001 | xy(vx, vy),
+            ("AND", [V(vx), V(vy)]) => 0x8002 | xy(vx, vy),
+            ("XOR", [V(vx), V(vy)]) => 0x8003 | xy(vx, vy),
+            ("SUB", [V(vx), V(vy)]) => 0x8005 | xy(vx, vy),
+            ("SHR", [V(vx), V(vy)]) => 0x8006 | xy(vx, vy),
+            ("SHR", [V(vx)]) => 0x8006 | x(vx),
+            ("SUBN", [V(vx), V(vy)]) => 0x8007 | xy(vx, vy),
+            ("SHL", [V(vx), V(vy)]) => 0x800E | xy(vx, vy),
+            ("SHL", [V(vx)]) => 0x800E | x(vx),
+            ("RND", [V(vx), kk]) => 0xC000 | x(vx) | self.byte(kk)? as u16,
+            ("DRW", [V(vx), V(vy), n]) => 0xD000 | xy(vx, vy) | self.value(n, 0, 0xF)? as u16,
+            ("SKP", [V(vx)]) => 0xE09E | x(vx),
+            ("SKNP", [V(vx)]) => 0xE0A1 | x(vx),
+            _ => return Err(format!("Invalid instruction: {} {:?}", mnemonic, ops)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use crate::chip8::assembler::octo;
+    use crate::chip8::disasm::{disassemble, disassemble_recursive, Syntax};
+    use crate::chip8::loader::bundled_roms;
+    use std::fs;
+
+    #[test]
+    fn labels_and_expressions() {
+        let source = "
+            ; Forward references are resolved in the second pass
+            start:  LD I, sprite + 1
+                    CALL draw
+                    JP start
+            draw:   DRW V0, V1, (end - sprite) - 1
+                    RET
+            sprite: DB 0xF0, 0b1001, -1
+                    DW $, start
+            end:
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program.rom,
+            [
+                0xA2, 0x0B, 0x22, 0x06, 0x12, 0x00, 0xD0, 0x16, 0x00, 0xEE, 0xF0, 0x09, 0xFF, 0x02,
+                0x0D, 0x02, 0x00
+            ]
+        );
+        let symbols = program.symbols("game.asm");
+        assert_eq!(symbols.describe(0x208), "draw+0x2 (game.asm:7)");
+        assert_eq!(symbols.describe(0x20C), "sprite+0x2 (game.asm:8)");
+        assert_eq!(
+            assemble("ORG 0x204\nDB 1 << 4 | 2").unwrap().rom,
+            [0, 0, 0, 0, 0x12]
+        );
+        assert_eq!(
+            assemble("JP nowhere").unwrap_err(),
+            "Line 1: Undefined label: nowhere"
+        );
+        assert!(assemble("LD V0, 0x100").is_err());
+        assert_eq!(
+            assemble("DB 1 << 70").unwrap_err(),
+            "Line 1: Value overflows"
+        );
+        assert_eq!(
+            assemble("DW 0x7FFFFFFFFFFFFFFF * 2").unwrap_err(),
+            "Line 1: Value overflows"
+        );
+        assert!(assemble("a: CLS\na: CLS").is_err());
+        assert!(assemble("DRW V0, 5").is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        let roms = bundled_roms();
+        assert!(roms.len() >= 9);
+        for path in roms {
+            let rom = fs::read(&path).unwrap();
+            let listing = disassemble(&rom, Syntax::Cowgod, None);
+            assert_eq!(assemble(&listing).unwrap().rom, rom, "{}", path.display());
+            let source = disassemble_recursive(&rom, Syntax::Cowgod, None);
+            assert_eq!(assemble(&source).unwrap().rom, rom, "{}", path.display());
+            let source = disassemble_recursive(&rom, Syntax::Octo, None);
+            assert_eq!(
+                octo::assemble(&source).unwrap().rom,
+                rom,
+                "{}",
+                path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Assemblers, building roms back from Octo or Cowgod-style source
+//!
+//! Roms are laid out from `ROM_BASE_ADDR` by an `Emitter`, which also keeps the
+//! labels: instructions referring to labels defined further down get patched once
+//! the whole source has been read. The rom comes back as a `Program`, along with the
+//! debug symbols of the source.
+pub mod cowgod;
+pub mod octo;
+
+use std::collections::BTreeMap;
+
+use super::memory::{RAM_SIZE, ROM_BASE_ADDR};
+use super::symbols::Symbols;
+
+/// How a label's address gets patched into the rom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Patch {
+    Nnn,    // Low 12 bits of the instruction at the address
+    Unpack, // Octo's `:unpack`, the `v0 := kk` and `v1 := kk` pair at the address
+    Word,   // 16 bits at the address
+}
+
+/// A reference to a label that wasn't defined yet
+struct Fixup {
+    addr: u16,
+    name: String,
+    patch: Patch,
+    line: usize,
+}
+
+/// An assembled rom, with where its bytes come from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+    pub lines: BTreeMap<u16, usize>, // Source line of the bytes from each address on
+    pub breakpoints: BTreeMap<u16, String>,
+}
+
+impl Program {
+    /// Debug symbols, file naming the source in line locations
+    pub fn symbols(&self, file: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, addr) in &self.labels {
+            symbols.add_label(*addr, name);
+        }
+        for (addr, line) in &self.lines {
+            symbols.add_line(*addr, &format!("{}:{}", file, line));
+        }
+        for (addr, name) in &self.breakpoints {
+            symbols.add_breakpoint(*addr, name);
+        }
+        symbols
+    }
+}
+
+/// Rom being assembled, with its labels
+struct Emitter {
+    rom: Vec<u8>,
+    here: u16,   // Address of the next byte
+    line: usize, // Source line being assembled
+    labels: BTreeMap<String, u16>,
+    lines: BTreeMap<u16, usize>,
+    breakpoints: BTreeMap<u16, String>,
+    fixups: Vec<Fixup>,
+}
+
+impl Emitter {
+    fn new() -> Self {
+        Self {
+            rom: Vec::new(),
+            here: ROM_BASE_ADDR as u16,
+            line: 0,
+            labels: BTreeMap::new(),
+            lines: BTreeMap::new(),
+            breakpoints: BTreeMap::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    fn offset(addr: u16) -> Result<usize, String> {
+        match (addr as usize).checked_sub(ROM_BASE_ADDR) {
+            Some(offset) if (addr as usize) < RAM_SIZE => Ok(offset),
+            _ => Err(format!("Address 0x{:03X} is outside of the rom", addr)),
+        }
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        let offset = Self::offset(self.here)?;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        if self.line > 0
+            && self
+                .lines
+                .range(..self.here)
+                .next_back()
+                .map(|(_, line)| *line)
+                != Some(self.line)
+        {
+            self.lines.insert(self.here, self.line);
+        }
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_word(&mut self, word: u16) -> Result<(), String> {
+        self.emit((word >> 8) as u8)?;
+        self.emit(word as u8)
+    }
+
+    /// Defines a label at addr
+    fn define(&mut self, name: &str, addr: u16) -> Result<(), String> {
+        if self.labels.insert(name.to_string(), addr).is_some() {
+            return Err(format!("Label defined twice: {}", name));
+        }
+        Ok(())
+    }
+
+    /// Stops the debugger before the instruction at addr
+    fn breakpoint(&mut self, name: &str, addr: u16) -> Result<(), String> {
+        if self.breakpoints.insert(addr, name.to_string()).is_some() {
+            return Err(format!("Breakpoint defined twice at 0x{:03X}", addr));
+        }
+        Ok(())
+    }
+
+    fn patch(&mut self, addr: u16, patch: Patch, value: u16) -> Result<(), String> {
+        let offset = Self::offset(addr)?;
+        let len = if patch == Patch::Unpack { 4 } else { 2 };
+        let bytes = self
+            .rom
+            .get_mut(offset..offset + len)
+            .ok_or_else(|| format!("Nothing to patch at 0x{:03X}", addr))?;
+        match patch {
+            Patch::Nnn if value > 0xFFF => {
+                return Err(format!("Address out of range: 0x{:X}", value))
+            }
+            Patch::Nnn => {
+                bytes[0] = (bytes[0] & 0xF0) | (value >> 8) as u8;
+                bytes[1] = value as u8;
+            }
+            Patch::Unpack if value > 0xFFF => {
+                return Err(format!("Address out of range: 0x{:X}", value))
+            }
+            Patch::Unpack => {
+                bytes[1] = (bytes[1] & 0xF0) | (value >> 8) as u8;
+                bytes[3] = value as u8;
+            }
+            Patch::Word => bytes.copy_from_slice(&value.to_be_bytes()),
+        }
+        Ok(())
+    }
+
+    /// Patches the label's address at addr, now or once the label gets defined
+    fn refer(&mut self, addr: u16, name: &str, patch: Patch, line: usize) -> Result<(), String> {
+        match self.labels.get(name) {
+            Some(value) => self.patch(addr, patch, *value),
+            None => {
+                self.fixups.push(Fixup {
+                    addr,
+                    name: name.to_string(),
+                    patch,
+                    line,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Resolves the remaining references, returning the program
+    fn finish(mut self) -> Result<Program, String> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let value = *self
+                .labels
+                .get(&fixup.name)
+                .ok_or_else(|| format!("Line {}: Undefined label: {}", fixup.line, fixup.name))?;
+            self.patch(fixup.addr, fixup.patch, value)
+                .map_err(|err| format!("Line {}: {}", fixup.line, err))?;
+        }
+        Ok(Program {
+            rom: self.rom,
+            labels: self.labels,
+            lines: self.lines,
+            breakpoints: self.breakpoints,
+        })
+    }
+}
@@ -0,0 +1,834 @@
+//! Assembler for Octo sources (`.8o`), producing the same roms as Octo does
+//!
+//! The CHIP-8 subset of the language is supported: labels, `:alias`, `:const`,
+//! `:calc`, `:macro`, `:stringmode`, `:next`, `:org`, `:byte`, `:pointer`, `:unpack`,
+//! `:call` and the structured `if`/`then`/`begin`/`else`/`end` and
+//! `loop`/`while`/`again`. Like Octo, the rom starts with a `jump main` unless `main`
+//! is the first thing defined, and `:calc` expressions are evaluated right to left
+//! without operator precedence.
+use std::collections::{HashMap, VecDeque};
+
+use super::{Emitter, Patch, Program};
+use crate::chip8::memory::ROM_BASE_ADDR;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String, // Strings keep their quotes, with escapes already replaced
+    line: usize,
+}
+
+fn tokenize(source: &str) -> Result<VecDeque<Token>, String> {
+    let mut tokens = VecDeque::new();
+    for (idx, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let mut text = String::new();
+            match chars.next() {
+                None | Some('#') => break,
+                Some('"') => {
+                    text.push('"');
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => text.push(match chars.next() {
+                                Some('n') => '\n',
+                                Some('t') => '\t',
+                                Some('r') => '\r',
+                                Some('0') => '\0',
+                                Some(c) => c,
+                                None => {
+                                    return Err(format!("Line {}: Unterminated string", idx + 1))
+                                }
+                            }),
+                            Some(c) => text.push(c),
+                            None => return Err(format!("Line {}: Unterminated string", idx + 1)),
+                        }
+                    }
+                    text.push('"');
+                }
+                Some(c) => {
+                    text.push(c);
+                    while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                        text.push(c);
+                    }
+                }
+            }
+            tokens.push_back(Token {
+                text,
+                line: idx + 1,
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Decimal, 0x-prefixed hexadecimal or 0b-prefixed binary number, possibly negative
+fn number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i64>().ok()?
+    };
+    Some(if negative { -value } else { value } as f64)
+}
+
+// None for unknown operators, and shifts by a negative amount or past 63 bits
+fn binary_op(op: &str, lhs: f64, rhs: f64) -> Option<f64> {
+    let (l, r) = (lhs as i64, rhs as i64);
+    let truth = |holds: bool| if holds { 1.0 } else { 0.0 };
+    Some(match op {
+        "+" => lhs + rhs,
+        "-" => lhs - rhs,
+        "*" => lhs * rhs,
+        "/" => lhs / rhs,
+        "%" => lhs % rhs,
+        "&" => (l & r) as f64,
+        "|" => (l | r) as f64,
+        "^" => (l ^ r) as f64,
+        "<<" => l.checked_shl(u32::try_from(r).ok()?)? as f64,
+        ">>" => l.checked_shr(u32::try_from(r).ok()?)? as f64,
+        "pow" => lhs.powf(rhs),
+        "min" => lhs.min(rhs),
+        "max" => lhs.max(rhs),
+        "<" => truth(lhs < rhs),
+        "<=" => truth(lhs <= rhs),
+        "==" => truth(lhs == rhs),
+        "!=" => truth(lhs != rhs),
+        ">=" => truth(lhs >= rhs),
+        ">" => truth(lhs > rhs),
+        _ => return None,
+    })
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>, // Still to assemble, macro expansions included
+    line: usize,             // Line of the last token read
+    out: Emitter,
+    main_jump: bool, // Whether the rom starts with a `jump main`
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    string_modes: HashMap<String, HashMap<char, (usize, Vec<Token>)>>, // Index and body per char
+    branches: Vec<u16>,          // Jumps of the open `begin`/`else` blocks
+    loops: Vec<(u16, Vec<u16>)>, // Start of the open loops, with their `while` jumps
+}
+
+/// Assembles an Octo source into a rom
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let mut asm = Assembler {
+        tokens: tokenize(source)?,
+        line: 0,
+        out: Emitter::new(),
+        main_jump: true,
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        string_modes: HashMap::new(),
+        branches: Vec::new(),
+        loops: Vec::new(),
+    };
+    // Room for the `jump main`, taken back if main comes first
+    asm.out.emit_word(0x1000)?;
+    while let Some(token) = asm.tokens.pop_front() {
+        asm.line = token.line;
+        asm.out.line = token.line;
+        asm.statement(&token.text)
+            .map_err(|err| format!("Line {}: {}", asm.line, err))?;
+    }
+    asm.finish()
+}
+
+impl Assembler {
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.pop_front().ok_or("Unexpected end of source")?;
+        self.line = token.line;
+        Ok(token)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(format!("Expected `{}`, found `{}`", text, token.text));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        if number(&token.text).is_some() || token.text.starts_with('"') {
+            return Err(format!("Invalid name: {}", token.text));
+        }
+        Ok(token.text)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        match token.text.strip_prefix('"') {
+            Some(text) => Ok(text[..text.len() - 1].to_string()),
+            None => Err(format!("Expected a string, found `{}`", token.text)),
+        }
+    }
+
+    fn register_index(&self, text: &str) -> Option<u8> {
+        if let Some(reg) = self.aliases.get(text) {
+            return Some(*reg);
+        }
+        match text.as_bytes() {
+            [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|x| x as u8),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register_index(&token.text)
+            .ok_or_else(|| format!("Expected a register, found `{}`", token.text))
+    }
+
+    /// Value of a number, constant or already defined label
+    fn value_of(&self, text: &str) -> Option<f64> {
+        number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.out.labels.get(text).map(|addr| *addr as f64))
+    }
+
+    /// A value known right away: a number, a name or a `{ }` expression
+    fn immediate(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        if token.text == "{" {
+            return self.calc();
+        }
+        self.value_of(&token.text)
+            .ok_or_else(|| format!("Undefined name: {}", token.text))
+    }
+
+    fn byte_of(&self, text: &str) -> Result<u8, String> {
+        let value = self
+            .value_of(text)
+            .ok_or_else(|| format!("Undefined name: {}", text))?;
+        match value as i64 {
+            value @ -128..=255 => Ok(value as u8),
+            _ => Err(format!("Byte value out of range: {}", text)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.byte_of(&token.text)
+    }
+
+    fn nibble(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        match self.value_of(&token.text).map(|value| value as i64) {
+            Some(value @ 0..=15) => Ok(value as u8),
+            Some(_) => Err(format!("Nibble value out of range: {}", token.text)),
+            None => Err(format!("Undefined name: {}", token.text)),
+        }
+    }
+
+    /// Patches the address named by text into the rom at addr, once it's known
+    fn address(&mut self, addr: u16, text: &str, patch: Patch) -> Result<(), String> {
+        match number(text).or_else(|| self.constants.get(text).copied()) {
+            Some(value) if (0.0..=65535.0).contains(&value) => {
+                self.out.patch(addr, patch, value as u16)
+            }
+            Some(_) => Err(format!("Address out of range: {}", text)),
+            None => self.out.refer(addr, text, patch, self.line),
+        }
+    }
+
+    /// Emits an instruction taking an address operand
+    fn address_op(&mut self, op: u16, target: &str) -> Result<(), String> {
+        let addr = self.out.here;
+        self.out.emit_word(op)?;
+        self.address(addr, target, Patch::Nnn)
+    }
+
+    /// Tokens up to the `}` closing an already read `{`
+    fn block(&mut self) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// Evaluates the expression up to the `}` closing an already read `{`
+    fn calc(&mut self) -> Result<f64, String> {
+        let tokens = self.block()?;
+        let mut pos = 0;
+        let value = self.expression(&tokens, &mut pos)?;
+        match tokens.get(pos) {
+            Some(token) => Err(format!("Unexpected `{}` in expression", token.text)),
+            None => Ok(value),
+        }
+    }
+
+    // Right to left, without precedence: `1 + 2 * 3` is `1 + (2 * 3)`, `2 * 3 + 1` is 8
+    fn expression(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let lhs = self.term(tokens, pos)?;
+        match tokens.get(*pos) {
+            Some(op) if binary_op(&op.text, 0.0, 1.0).is_some() => {
+                *pos += 1;
+                let rhs = self.expression(tokens, pos)?;
+                binary_op(&op.text, lhs, rhs).ok_or_else(|| String::from("Value overflows"))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn term(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let token = tokens.get(*pos).ok_or("Incomplete expression")?;
+        *pos += 1;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|value| -value),
+            "~" => Some(|value| !(value as i64) as f64),
+            "!" => Some(|value| if value == 0.0 { 1.0 } else { 0.0 }),
+            "abs" => Some(f64::abs),
+            "floor" => Some(f64::floor),
+            "ceil" => Some(f64::ceil),
+            "sqrt" => Some(f64::sqrt),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.term(tokens, pos)?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, pos)?;
+                match tokens.get(*pos) {
+                    Some(token) if token.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(String::from("Missing `)` in expression")),
+                }
+            }
+            "@" => {
+                let addr = self.term(tokens, pos)? as usize;
+                let offset = addr.checked_sub(ROM_BASE_ADDR);
+                Ok(offset
+                    .and_then(|offset| self.out.rom.get(offset))
+                    .copied()
+                    .unwrap_or(0) as f64)
+            }
+            "HERE" => Ok(self.out.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => self
+                .value_of(text)
+                .ok_or_else(|| format!("Undefined name in expression: {}", text)),
+        }
+    }
+
+    /// Queues tokens to be assembled next
+    fn expand(&mut self, tokens: Vec<Token>) {
+        for token in tokens.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+    }
+
+    fn statement(&mut self, text: &str) -> Result<(), String> {
+        if number(text).is_some() {
+            let byte = self.byte_of(text)?;
+            return self.out.emit(byte);
+        }
+        match text {
+            ":" => {
+                let name = self.name()?;
+                // `jump main` isn't needed when main would be right after it
+                if name == "main"
+                    && self.out.here == ROM_BASE_ADDR as u16 + 2
+                    && self.out.rom.len() == 2
+                {
+                    self.out.rom.clear();
+                    self.out.here = ROM_BASE_ADDR as u16;
+                    self.main_jump = false;
+                }
+                self.out.define(&name, self.out.here)
+            }
+            ":next" => {
+                // Second byte of the next instruction, i.e. its operand
+                let name = self.name()?;
+                self.out.define(&name, self.out.here + 1)
+            }
+            ":breakpoint" => {
+                let name = self.name()?;
+                self.out.breakpoint(&name, self.out.here)
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.register()?;
+                self.aliases.insert(name, reg);
+                Ok(())
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.immediate()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":byte" => match self.immediate()? as i64 {
+                value @ -128..=255 => self.out.emit(value as u8),
+                value => Err(format!("Byte value out of range: {}", value)),
+            },
+            ":org" => {
+                let addr = self.immediate()?;
+                self.out.here = addr as u16;
+                Ok(())
+            }
+            ":pointer" => {
+                let target = self.next()?;
+                let addr = self.out.here;
+                self.out.emit_word(0)?;
+                self.address(addr, &target.text, Patch::Word)
+            }
+            ":unpack" => {
+                // v0 := nibble and the high bits of target, v1 := its low bits
+                let nibble = self.nibble()?;
+                let target = self.next()?;
+                let addr = self.out.here;
+                self.out.emit_word(0x6000 | (nibble as u16) << 4)?;
+                self.out.emit_word(0x6100)?;
+                self.address(addr, &target.text, Patch::Unpack)
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.address_op(0x2000, &target.text)
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+                loop {
+                    let token = self.next()?;
+                    if token.text == "{" {
+                        break;
+                    }
+                    params.push(token.text);
+                }
+                let body = self.block()?;
+                self.macros.insert(name, Macro { params, body });
+                Ok(())
+            }
+            ":stringmode" => {
+                let name = self.name()?;
+                let alphabet = self.string()?;
+                self.expect("{")?;
+                let body = self.block()?;
+                let mode = self.string_modes.entry(name).or_default();
+                for (idx, c) in alphabet.chars().enumerate() {
+                    mode.insert(c, (idx, body.clone()));
+                }
+                Ok(())
+            }
+            "clear" => self.out.emit_word(0x00E0),
+            "return" | ";" => self.out.emit_word(0x00EE),
+            "jump" | "jump0" | "native" => {
+                let op = match text {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                let target = self.next()?;
+                self.address_op(op, &target.text)
+            }
+            "bcd" | "save" | "load" => {
+                let kk = match text {
+                    "bcd" => 0x33,
+                    "save" => 0x55,
+                    _ => 0x65,
+                };
+                let x = self.register()?;
+                self.out.emit_word(0xF000 | (x as u16) << 8 | kk)
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.out
+                    .emit_word(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n as u16)
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let kk = if text == "delay" { 0x15 } else { 0x18 };
+                self.out.emit_word(0xF000 | (x as u16) << 8 | kk)
+            }
+            "i" => self.i_statement(),
+            "if" => {
+                // Key conditions have no right-hand side
+                let keyword_at = match self.tokens.get(1).map(|token| token.text.as_str()) {
+                    Some("key" | "-key") => 2,
+                    _ => 3,
+                };
+                let keyword = self.tokens.get(keyword_at).map(|token| token.text.clone());
+                match keyword.as_deref() {
+                    Some("then") => {
+                        self.condition(false)?;
+                        self.expect("then")
+                    }
+                    Some("begin") => {
+                        self.condition(true)?;
+                        self.expect("begin")?;
+                        self.branches.push(self.out.here);
+                        self.out.emit_word(0x1000)
+                    }
+                    _ => Err(String::from("Expected `then` or `begin` after `if`")),
+                }
+            }
+            "else" => {
+                let branch = self.branches.pop().ok_or("`else` without `if ... begin`")?;
+                self.branches.push(self.out.here);
+                self.out.emit_word(0x1000)?;
+                self.out.patch(branch, Patch::Nnn, self.out.here)
+            }
+            "end" => {
+                let branch = self.branches.pop().ok_or("`end` without `if ... begin`")?;
+                self.out.patch(branch, Patch::Nnn, self.out.here)
+            }
+            "loop" => {
+                self.loops.push((self.out.here, Vec::new()));
+                Ok(())
+            }
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(String::from("`while` outside of a loop"));
+                }
+                self.condition(true)?;
+                let addr = self.out.here;
+                self.loops.last_mut().expect("Checked above").1.push(addr);
+                self.out.emit_word(0x1000)
+            }
+            "again" => {
+                let (start, whiles) = self.loops.pop().ok_or("`again` without `loop`")?;
+                self.out.emit_word(0x1000 | start)?;
+                for addr in whiles {
+                    self.out.patch(addr, Patch::Nnn, self.out.here)?;
+                }
+                Ok(())
+            }
+            _ => {
+                if let Some(x) = self.register_index(text) {
+                    self.register_statement(x)
+                } else if let Some(m) = self.macros.get(text) {
+                    let params = m.params.clone();
+                    let body = m.body.clone();
+                    let mut args = Vec::new();
+                    for _ in 0..params.len() {
+                        args.push(self.next()?.text);
+                    }
+                    let line = self.line;
+                    let expansion = body
+                        .into_iter()
+                        .map(|token| Token {
+                            text: match params.iter().position(|param| *param == token.text) {
+                                Some(idx) => args[idx].clone(),
+                                None => token.text,
+                            },
+                            line,
+                        })
+                        .collect();
+                    self.expand(expansion);
+                    Ok(())
+                } else if self.string_modes.contains_key(text) {
+                    self.string_mode(text)
+                } else {
+                    // A bare name calls a subroutine
+                    self.address_op(0x2000, text)
+                }
+            }
+        }
+    }
+
+    fn string_mode(&mut self, name: &str) -> Result<(), String> {
+        let text = self.string()?;
+        let line = self.line;
+        let mode = &self.string_modes[name];
+        let mut expansion = Vec::new();
+        for (idx, c) in text.chars().enumerate() {
+            let (value, body) = mode
+                .get(&c)
+                .ok_or_else(|| format!("String mode {} has no `{}` character", name, c))?;
+            let constant = |text: &str| match text {
+                "VALUE" => Some(value.to_string()),
+                "CHAR" => Some((c as u32).to_string()),
+                "INDEX" => Some(idx.to_string()),
+                _ => None,
+            };
+            expansion.extend(body.iter().map(|token| Token {
+                text: constant(&token.text).unwrap_or_else(|| token.text.clone()),
+                line,
+            }));
+        }
+        self.expand(expansion);
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<(), String> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {
+                let target = self.next()?;
+                if target.text == "hex" {
+                    let x = self.register()?;
+                    self.out.emit_word(0xF029 | (x as u16) << 8)
+                } else {
+                    self.address_op(0xA000, &target.text)
+                }
+            }
+            "+=" => {
+                let x = self.register()?;
+                self.out.emit_word(0xF01E | (x as u16) << 8)
+            }
+            _ => Err(format!("Unknown operator for i: {}", op.text)),
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), String> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let x16 = (x as u16) << 8;
+        let y = self.register_index(&rhs.text).map(|y| (y as u16) << 4);
+        let word = match (op.text.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | x16 | y,
+            (":=", None) => match rhs.text.as_str() {
+                "random" => 0xC000 | x16 | self.byte()? as u16,
+                "key" => 0xF00A | x16,
+                "delay" => 0xF007 | x16,
+                _ => 0x6000 | x16 | self.byte_of(&rhs.text)? as u16,
+            },
+            ("+=", Some(y)) => 0x8004 | x16 | y,
+            ("+=", None) => 0x7000 | x16 | self.byte_of(&rhs.text)? as u16,
+            ("-=", Some(y)) => 0x8005 | x16 | y,
+            ("-=", None) => 0x7000 | x16 | self.byte_of(&rhs.text)?.wrapping_neg() as u16,
+            ("|=", Some(y)) => 0x8001 | x16 | y,
+            ("&=", Some(y)) => 0x8002 | x16 | y,
+            ("^=", Some(y)) => 0x8003 | x16 | y,
+            (">>=", Some(y)) => 0x8006 | x16 | y,
+            ("=-", Some(y)) => 0x8007 | x16 | y,
+            ("<<=", Some(y)) => 0x800E | x16 | y,
+            (op, _) => return Err(format!("Invalid operation: v{:x} {} {}", x, op, rhs.text)),
+        };
+        self.out.emit_word(word)
+    }
+
+    /// Emits the skip over the next instruction when the condition doesn't hold
+    ///
+    /// Negated conditions are used by `begin` and `while`, which skip a jump instead.
+    fn condition(&mut self, negated: bool) -> Result<(), String> {
+        let x = self.register()?;
+        let op = self.next()?.text;
+        let op = match (op.as_str(), negated) {
+            (op, false) => op,
+            ("==", true) => "!=",
+            ("!=", true) => "==",
+            ("key", true) => "-key",
+            ("-key", true) => "key",
+            (">", true) => "<=",
+            ("<", true) => ">=",
+            (">=", true) => "<",
+            ("<=", true) => ">",
+            (op, true) => op,
+        };
+        let x16 = (x as u16) << 8;
+        match op {
+            "key" => return self.out.emit_word(0xE0A1 | x16),
+            "-key" => return self.out.emit_word(0xE09E | x16),
+            _ => (),
+        }
+        let rhs = self.next()?;
+        let y = self.register_index(&rhs.text).map(|y| (y as u16) << 4);
+        match (op, y) {
+            ("==", Some(y)) => self.out.emit_word(0x9000 | x16 | y),
+            ("==", None) => self
+                .out
+                .emit_word(0x4000 | x16 | self.byte_of(&rhs.text)? as u16),
+            ("!=", Some(y)) => self.out.emit_word(0x5000 | x16 | y),
+            ("!=", None) => self
+                .out
+                .emit_word(0x3000 | x16 | self.byte_of(&rhs.text)? as u16),
+            (">" | "<" | ">=" | "<=", _) => {
+                // Compared through vF: vF := rhs, then subtract and test the borrow flag
+                match y {
+                    Some(y) => self.out.emit_word(0x8F00 | y)?,
+                    None => self
+                        .out
+                        .emit_word(0x6F00 | self.byte_of(&rhs.text)? as u16)?,
+                }
+                let sub = if matches!(op, ">" | "<=") {
+                    0x8F05
+                } else {
+                    0x8F07
+                };
+                self.out.emit_word(sub | (x as u16) << 4)?;
+                self.out.emit_word(if matches!(op, ">" | "<") {
+                    0x4F00
+                } else {
+                    0x3F00
+                })
+            }
+            _ => Err(format!("Unknown comparison: {}", op)),
+        }
+    }
+
+    fn finish(mut self) -> Result<Program, String> {
+        if !self.branches.is_empty() {
+            return Err(String::from("Missing `end` for an `if ... begin`"));
+        }
+        if !self.loops.is_empty() {
+            return Err(String::from("Missing `again` for a `loop`"));
+        }
+        let main = *self
+            .out
+            .labels
+            .get("main")
+            .ok_or("The program has no main label")?;
+        if self.main_jump {
+            self.out.patch(ROM_BASE_ADDR as u16, Patch::Nnn, main)?;
+        }
+        self.out.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+
+    #[test]
+    fn structured_code() {
+        let source = "
+            :alias counter v3
+            :const LIMIT 10
+            : main
+              counter := 0
+              loop
+                counter += 1
+                if counter == LIMIT begin
+                  i := sprite
+                else
+                  i := hex counter
+                end
+                while counter < LIMIT
+              again
+              :unpack 0xA sprite
+              if v0 key then jump main
+            : sprite
+              :byte { 1 << 7 }
+              :pointer main
+        ";
+        assert_eq!(
+            assemble(source).unwrap().rom,
+            [
+                0x63, 0x00, // 0x200: counter := 0
+                0x73, 0x01, // 0x202: counter += 1
+                0x33, 0x0A, 0x12, 0x0C, // 0x204: skip to the body when counter == 10
+                0xA2, 0x20, 0x12, 0x0E, // 0x208: i := sprite, then skip the else
+                0xF3, 0x29, // 0x20C: i := hex counter
+                0x6F, 0x0A, 0x8F, 0x37, 0x3F, 0x00, 0x12, 0x18, // 0x20E: while counter < 10
+                0x12, 0x02, // 0x216: again
+                0x60, 0xA2, 0x61, 0x20, // 0x218: :unpack 0xA sprite
+                0xE0, 0xA1, 0x12, 0x00, // 0x21C: if v0 key then jump main
+                0x80, 0x02, 0x00, // 0x220: sprite
+            ]
+        );
+    }
+
+    #[test]
+    fn macros_and_errors() {
+        let source = "
+            :stringmode text \"AB\" { :byte { INDEX + VALUE } }
+            :macro twice X { X X }
+            : sub return
+            : main
+              twice sub
+              text \"BAB\"
+        ";
+        assert_eq!(
+            assemble(source).unwrap().rom,
+            [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02, 0x22, 0x02, 0x01, 0x01, 0x03]
+        );
+        assert_eq!(
+            assemble(": main\n  jump nowhere").unwrap_err(),
+            "Line 2: Undefined label: nowhere"
+        );
+        assert!(assemble(": main\n  v0 := 256").is_err());
+        assert_eq!(
+            assemble(":calc x { 1 << 70 }\n: main\n  clear").unwrap_err(),
+            "Line 1: Value overflows"
+        );
+        assert!(assemble(":calc x { 1 >> -1 }\n: main\n  clear").is_err());
+        assert!(assemble(": start\n  clear").is_err());
+    }
+
+    #[test]
+    fn debug_symbols() {
+        let source = ": main\n  v0 := 1\n  :breakpoint check\n  sub\n: sub\n  v1 += 2\n  return\n";
+        let program = assemble(source).unwrap();
+        assert_eq!(program.breakpoints[&0x202], "check");
+        let symbols = program.symbols("game.8o");
+        assert_eq!(symbols.describe(0x202), "main+0x2 (game.8o:4)");
+        assert_eq!(symbols.describe(0x206), "sub+0x2 (game.8o:7)");
+        assert!(assemble(": main\n  :breakpoint a\n  :breakpoint b\n  clear").is_err());
+    }
+
+    #[test]
+    fn bundled_test_roms() {
+        let roms: [(&str, &[u8]); 6] = [
+            (
+                include_str!("../../../bin/1-chip8-logo.8o"),
+                include_bytes!("../../../bin/1-chip8-logo.ch8"),
+            ),
+            (
+                include_str!("../../../bin/2-ibm-logo.8o"),
+                include_bytes!("../../../bin/2-ibm-logo.ch8"),
+            ),
+            (
+                include_str!("../../../bin/3-corax+.8o"),
+                include_bytes!("../../../bin/3-corax+.ch8"),
+            ),
+            (
+                include_str!("../../../bin/4-flags.8o"),
+                include_bytes!("../../../bin/4-flags.ch8"),
+            ),
+            (
+                include_str!("../../../bin/5-quirks.8o"),
+                include_bytes!("../../../bin/5-quirks.ch8"),
+            ),
+            (
+                include_str!("../../../bin/6-keypad.8o"),
+                include_bytes!("../../../bin/6-keypad.ch8"),
+            ),
+        ];
+        for (source, rom) in roms {
+            assert_eq!(assemble(source).unwrap().rom, rom);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Control flow graphs of roms, exported to Graphviz DOT
+//!
+//! The reachable code found by `Flow` is split into basic blocks, each ending on a
+//! jump, call, return or skip, or right before another block's first instruction.
+//! Blocks are grouped per subroutine, main's code included, following every edge but
+//! calls from each entry point. `Bnnn` targets depend on V0 and can't be resolved:
+//! they are drawn as separate nodes.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::disasm::{self, Syntax};
+use super::flow::{successors, Flow, Successor};
+use super::instruction::Instruction;
+use super::memory::ROM_BASE_ADDR;
+
+/// Instructions executed in sequence, from start up to end (excluded)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    pub end: u16,
+    pub edges: Vec<Successor>, // Of the last instruction, to other blocks
+}
+
+/// Basic blocks of a rom, grouped per subroutine
+#[derive(Debug)]
+pub struct Cfg {
+    flow: Flow,
+    pub blocks: BTreeMap<u16, Block>,
+    pub subroutines: BTreeMap<u16, Vec<u16>>, // Blocks of each entry point, main included
+}
+
+impl Cfg {
+    /// Fails when nothing can be explored, the entry point not holding an instruction
+    pub fn build(rom: &[u8]) -> Result<Self, String> {
+        let flow = Flow::explore(rom);
+        let entry = ROM_BASE_ADDR as u16;
+        if !flow.is_code(entry) {
+            return Err(match flow.word(entry) {
+                Some(word) => format!(
+                    "Undecodable instruction 0x{:04X} at the entry point 0x{:03X}",
+                    word, entry
+                ),
+                None => format!("No instruction at the entry point 0x{:03X}", entry),
+            });
+        }
+        let instruction = |pc: u16| decode(&flow, pc);
+
+        // Blocks start at the entry point and wherever control doesn't just fall through to
+        let mut leaders = BTreeSet::from([ROM_BASE_ADDR as u16]);
+        for pc in flow.code() {
+            let edges = successors(pc, instruction(pc));
+            if edges != [Successor::Next(pc + 2)] {
+                leaders.extend(edges.iter().map(Successor::addr));
+            }
+        }
+        leaders.retain(|addr| flow.is_code(*addr));
+
+        let mut blocks = BTreeMap::new();
+        for start in leaders.iter().copied() {
+            let mut pc = start;
+            let edges = loop {
+                let edges = successors(pc, instruction(pc));
+                let next = pc + 2;
+                if edges != [Successor::Next(next)]
+                    || !flow.is_code(next)
+                    || leaders.contains(&next)
+                {
+                    break edges;
+                }
+                pc = next;
+            };
+            let edges = edges
+                .into_iter()
+                .filter(|edge| flow.is_code(edge.addr()))
+                .collect();
+            let end = pc + 2;
+            blocks.insert(start, Block { start, end, edges });
+        }
+
+        // Each block belongs to the first subroutine reaching it, main coming first
+        let mut subroutines = BTreeMap::new();
+        let mut owned = BTreeSet::new();
+        let entries = std::iter::once(ROM_BASE_ADDR as u16).chain(flow.calls.iter().copied());
+        for entry in entries.filter(|entry| blocks.contains_key(entry)) {
+            let mut members = Vec::new();
+            let mut pending = vec![entry];
+            while let Some(start) = pending.pop() {
+                if !owned.insert(start) {
+                    continue;
+                }
+                members.push(start);
+                for edge in &blocks[&start].edges {
+                    match edge {
+                        Successor::Next(addr) | Successor::Skip(addr) | Successor::Jump(addr) => {
+                            pending.push(*addr)
+                        }
+                        Successor::Call(_) | Successor::Computed(_) => (),
+                    }
+                }
+            }
+            members.sort_unstable();
+            subroutines.entry(entry).or_insert(members);
+        }
+
+        Ok(Cfg {
+            flow,
+            blocks,
+            subroutines,
+        })
+    }
+
+    /// Graphviz graph, one cluster per subroutine, instructions in the given syntax
+    pub fn to_dot(&self, syntax: Syntax) -> String {
+        let mut out = String::from("digraph cfg {\n");
+        out.push_str("  node [shape=box, fontname=monospace];\n  edge [fontname=monospace];\n");
+        let mut clustered = BTreeSet::new();
+        for (entry, members) in &self.subroutines {
+            let name = if *entry == ROM_BASE_ADDR as u16 {
+                String::from("main")
+            } else {
+                format!("sub_{:03X}", entry)
+            };
+            writeln!(
+                out,
+                "  subgraph cluster_{} {{\n    label=\"{}\";",
+                name, name
+            )
+            .unwrap();
+            for start in members {
+                writeln!(out, "    {}", self.node(&self.blocks[start], syntax)).unwrap();
+                clustered.insert(*start);
+            }
+            out.push_str("  }\n");
+        }
+        // Only reached through computed jumps
+        for block in self.blocks.values() {
+            if !clustered.contains(&block.start) {
+                writeln!(out, "  {}", self.node(block, syntax)).unwrap();
+            }
+        }
+
+        for block in self.blocks.values() {
+            for edge in &block.edges {
+                let (label, style) = match edge {
+                    Successor::Next(_) => ("", ""),
+                    Successor::Skip(_) => ("skip", ", style=dashed"),
+                    Successor::Jump(_) => ("jump", ", style=bold"),
+                    Successor::Call(_) => ("call", ", color=blue"),
+                    Successor::Computed(_) => continue,
+                };
+                writeln!(
+                    out,
+                    "  b_{:03X} -> b_{:03X} [label=\"{}\"{}];",
+                    block.start,
+                    edge.addr(),
+                    label,
+                    style
+                )
+                .unwrap();
+            }
+        }
+
+        for pc in &self.flow.computed {
+            let block = self.blocks.range(..=pc).next_back().expect("In a block").1;
+            let base = match decode(&self.flow, *pc) {
+                Instruction::JumpV0(nnn) => nnn,
+                _ => unreachable!("Computed jumps are Bnnn"),
+            };
+            let node = format!("unresolved_{:03X}", pc);
+            writeln!(
+                out,
+                "  {} [label=\"0x{:03X} + V0\\n(unresolved)\", shape=octagon, color=red];",
+                node, base
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "  b_{:03X} -> {} [label=\"computed\", color=red, style=dashed];",
+                block.start, node
+            )
+            .unwrap();
+            if self.blocks.contains_key(&base) {
+                writeln!(
+                    out,
+                    "  {} -> b_{:03X} [label=\"V0 = 0\", color=red, style=dotted];",
+                    node, base
+                )
+                .unwrap();
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    // Node of a block, listing its instructions left aligned
+    fn node(&self, block: &Block, syntax: Syntax) -> String {
+        let mut label = String::new();
+        for pc in (block.start..block.end).step_by(2) {
+            let word = self.flow.word(pc).expect("Code is in the rom");
+            write!(label, "0x{:03X}: {}\\l", pc, disasm::format(word, syntax)).unwrap();
+        }
+        format!(
+            "b_{:03X} [label=\"{}\"];",
+            block.start,
+            label.replace('"', "\\\"")
+        )
+    }
+}
+
+fn decode(flow: &Flow, pc: u16) -> Instruction {
+    Instruction::decode(flow.word(pc).expect("Code is in the rom"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cfg;
+    use crate::chip8::disasm::Syntax;
+    use crate::chip8::flow::Successor;
+
+    #[test]
+    fn basic_blocks() {
+        // 0x200: LD V0, 0; CALL 0x20C; SE V0, 1; JP 0x200; B 0x210
+        // 0x20A: (padding); 0x20C: ADD V0, 1; RET; 0x210: JP 0x210
+        let rom = [
+            0x60, 0x00, 0x22, 0x0C, 0x30, 0x01, 0x12, 0x00, 0xB2, 0x10, 0x00, 0x00, 0x70, 0x01,
+            0x00, 0xEE, 0x12, 0x10,
+        ];
+        let cfg = Cfg::build(&rom).unwrap();
+        let starts: Vec<u16> = cfg.blocks.keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x204, 0x206, 0x208, 0x20C, 0x210]);
+        assert_eq!(cfg.blocks[&0x200].end, 0x204);
+        assert_eq!(
+            cfg.blocks[&0x200].edges,
+            [Successor::Call(0x20C), Successor::Next(0x204)]
+        );
+        assert_eq!(
+            cfg.blocks[&0x204].edges,
+            [Successor::Next(0x206), Successor::Skip(0x208)]
+        );
+        assert_eq!(cfg.subroutines[&0x200], [0x200, 0x204, 0x206, 0x208]);
+        assert_eq!(cfg.subroutines[&0x20C], [0x20C]);
+
+        let dot = cfg.to_dot(Syntax::Cowgod);
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("subgraph cluster_sub_20C {"));
+        assert!(dot.contains("b_200 [label=\"0x200: LD V0, 0x00\\l0x202: CALL 0x20C\\l\"];"));
+        assert!(dot.contains("b_200 -> b_20C [label=\"call\", color=blue];"));
+        assert!(dot.contains("b_204 -> b_208 [label=\"skip\", style=dashed];"));
+        assert!(dot.contains("b_208 -> unresolved_208 [label=\"computed\""));
+        // The computed jump's base is only reached through it, outside of any cluster
+        assert!(dot.contains("\n  b_210 [label="));
+
+        // XO-CHIP's 4-byte `F000 nnnn` isn't decoded
+        assert_eq!(
+            Cfg::build(&[0xF0, 0x00, 0x12, 0x34]).unwrap_err(),
+            "Undecodable instruction 0xF000 at the entry point 0x200"
+        );
+        assert!(Cfg::build(&[0x12]).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Code coverage: which ram addresses were executed, drawn as sprites or written to
+//!
+//! Reports are keyed by address, rows of 16 bytes being shown when they belong to the
+//! rom or were touched at runtime. Given the source lines of the debug symbols
+//! (address -> `file:line`), they also list which source lines were executed.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+use super::memory::{RAM_SIZE, ROM_BASE_ADDR};
+
+pub const EXECUTED: u8 = 0b001; // Fetched as an instruction
+pub const SPRITE: u8 = 0b010; // Read as sprite data by Dxyn
+pub const WRITTEN: u8 = 0b100; // Written by Fx33 or Fx55
+
+const ROW_LEN: usize = 16; // Bytes per report line
+
+// Name, ANSI colour and HTML colour of each kind of byte
+fn class(flags: u8) -> (&'static str, &'static str, &'static str) {
+    match flags {
+        0 => ("untouched", "\x1b[2m", "#777777"),
+        EXECUTED => ("executed", "\x1b[32m", "#2e9e2e"),
+        SPRITE => ("sprite", "\x1b[33m", "#c09000"),
+        WRITTEN => ("written", "\x1b[31m", "#d03030"),
+        _ => ("mixed", "\x1b[35m", "#b030b0"),
+    }
+}
+
+/// Coverage flags of every ram address
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            flags: vec![0; RAM_SIZE],
+        }
+    }
+
+    /// Flags of an address, 0 when never touched
+    pub fn flags(&self, addr: usize) -> u8 {
+        self.flags.get(addr).copied().unwrap_or(0)
+    }
+
+    pub fn mark(&mut self, range: Range<usize>, flag: u8) {
+        for addr in range.take_while(|addr| *addr < RAM_SIZE) {
+            self.flags[addr] |= flag;
+        }
+    }
+
+    /// Number of addresses having the flag
+    pub fn count(&self, flag: u8) -> usize {
+        self.flags.iter().filter(|flags| *flags & flag != 0).count()
+    }
+
+    // Starts of the rows to report: the rom's, and any holding a touched byte
+    fn rows(&self, rom_len: usize) -> Vec<usize> {
+        let rom = ROM_BASE_ADDR..ROM_BASE_ADDR + rom_len;
+        (0..RAM_SIZE)
+            .step_by(ROW_LEN)
+            .filter(|row| {
+                (*row..row + ROW_LEN).any(|addr| rom.contains(&addr) || self.flags[addr] != 0)
+            })
+            .collect()
+    }
+
+    // Each source location with whether one of its addresses was executed, in address order
+    fn source_lines<'a>(&self, lines: &'a BTreeMap<u16, String>) -> Vec<(&'a str, u16, bool)> {
+        let mut seen: Vec<(&str, u16, bool)> = Vec::new();
+        for (addr, location) in lines {
+            let executed = self.flags(*addr as usize) & EXECUTED != 0;
+            match seen
+                .iter_mut()
+                .find(|(seen, _, _)| *seen == location.as_str())
+            {
+                Some((_, _, covered)) => *covered |= executed,
+                None => seen.push((location, *addr, executed)),
+            }
+        }
+        seen
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} byte(s) executed, {} read as sprites, {} written",
+            self.count(EXECUTED),
+            self.count(SPRITE),
+            self.count(WRITTEN)
+        )
+    }
+
+    /// Report using ANSI colours, e.g. for `less -R`
+    pub fn text_report(&self, rom_len: usize, lines: Option<&BTreeMap<u16, String>>) -> String {
+        let mut out = format!("Coverage: {}\nLegend:", self.summary());
+        for flags in [EXECUTED, SPRITE, WRITTEN, EXECUTED | WRITTEN, 0] {
+            let (name, colour, _) = class(flags);
+            write!(out, " {}{}\x1b[0m", colour, name).unwrap();
+        }
+        out.push('\n');
+        for row in self.rows(rom_len) {
+            write!(out, "0x{:03X}:", row).unwrap();
+            for flags in &self.flags[row..row + ROW_LEN] {
+                let (_, colour, _) = class(*flags);
+                write!(out, " {}{}\x1b[0m", colour, symbol(*flags)).unwrap();
+            }
+            out.push('\n');
+        }
+        if let Some(lines) = lines {
+            let source_lines = self.source_lines(lines);
+            let executed = source_lines.iter().filter(|line| line.2).count();
+            writeln!(
+                out,
+                "\nSource lines: {}/{} executed",
+                executed,
+                source_lines.len()
+            )
+            .unwrap();
+            for (location, addr, executed) in source_lines {
+                let (name, colour, _) = class(if executed { EXECUTED } else { 0 });
+                writeln!(
+                    out,
+                    "{}0x{:03X} {} ({})\x1b[0m",
+                    colour, addr, location, name
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+
+    /// Standalone HTML page
+    pub fn html_report(&self, rom_len: usize, lines: Option<&BTreeMap<u16, String>>) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>chipper coverage</title>\n\
+             <style>body { font-family: monospace; } span { padding: 0 2px; }</style>\n\
+             </head><body>\n",
+        );
+        writeln!(out, "<h1>Coverage</h1>\n<p>{}</p>\n<p>", self.summary()).unwrap();
+        for flags in [EXECUTED, SPRITE, WRITTEN, EXECUTED | WRITTEN, 0] {
+            let (name, _, colour) = class(flags);
+            write!(out, "<span style=\"color: {}\">{}</span> ", colour, name).unwrap();
+        }
+        out.push_str("</p>\n<pre>\n");
+        for row in self.rows(rom_len) {
+            write!(out, "0x{:03X}:", row).unwrap();
+            for addr in row..row + ROW_LEN {
+                let (name, _, colour) = class(self.flags[addr]);
+                write!(
+                    out,
+                    " <span title=\"0x{:03X} {}\" style=\"color: {}\">{}</span>",
+                    addr,
+                    name,
+                    colour,
+                    symbol(self.flags[addr])
+                )
+                .unwrap();
+            }
+            out.push('\n');
+        }
+        out.push_str("</pre>\n");
+        if let Some(lines) = lines {
+            out.push_str("<h2>Source lines</h2>\n<table>\n");
+            for (location, addr, executed) in self.source_lines(lines) {
+                let (name, _, colour) = class(if executed { EXECUTED } else { 0 });
+                writeln!(
+                    out,
+                    "<tr style=\"color: {}\"><td>0x{:03X}</td><td>{}</td><td>{}</td></tr>",
+                    colour,
+                    addr,
+                    escape_html(location),
+                    name
+                )
+                .unwrap();
+            }
+            out.push_str("</table>\n");
+        }
+        out.push_str("</body></html>\n");
+        out
+    }
+}
+
+// One character per byte, readable without colours too
+fn symbol(flags: u8) -> char {
+    match flags {
+        0 => '.',
+        EXECUTED => 'X',
+        SPRITE => 'S',
+        WRITTEN => 'W',
+        _ => 'M',
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coverage, EXECUTED, SPRITE, WRITTEN};
+    use crate::chip8::cpu::CPU;
+    use crate::chip8::input::KeyBoard;
+    use crate::chip8::memory::Mem;
+    use crate::chip8::symbols::Symbols;
+
+    #[test]
+    fn recorded_by_cpu() {
+        // LD I, 0x20A; DRW V0, V0, 2; LD [I], V0; JP 0x206; sprite
+        let rom = vec![
+            0xA2, 0x0A, 0xD0, 0x02, 0xF0, 0x55, 0x12, 0x06, 0x00, 0x00, 0xFF, 0x81,
+        ];
+        let mut cpu = CPU::new(Mem::new(rom));
+        cpu.enable_coverage();
+        let kb = KeyBoard::new();
+        for _ in 0..5 {
+            cpu.tick(&kb);
+        }
+        let coverage = cpu.coverage().unwrap();
+        assert_eq!(coverage.flags(0x200), EXECUTED);
+        assert_eq!(coverage.flags(0x207), EXECUTED);
+        assert_eq!(coverage.flags(0x208), 0);
+        assert_eq!(coverage.flags(0x20A), SPRITE | WRITTEN);
+        assert_eq!(coverage.flags(0x20B), SPRITE);
+        assert_eq!(coverage.count(EXECUTED), 8);
+    }
+
+    #[test]
+    fn reports() {
+        let mut coverage = Coverage::new();
+        coverage.mark(0x200..0x202, EXECUTED);
+        coverage.mark(0x300..0x303, WRITTEN);
+        let symbols = Symbols::parse("0x200 game.8o:3\n0x202 game.8o:4 # never run\n").unwrap();
+        let lines = symbols.lines();
+        let text = coverage.text_report(4, Some(lines));
+        assert!(text.starts_with("Coverage: 2 byte(s) executed, 0 read as sprites, 3 written"));
+        assert_eq!(
+            text.lines().filter(|line| line.starts_with("0x")).count(),
+            2
+        );
+        assert!(text.contains("Source lines: 1/2 executed"));
+        assert!(text.contains("0x202 game.8o:4 (untouched)"));
+
+        let html = coverage.html_report(4, Some(lines));
+        assert!(html.contains("<span title=\"0x300 written\""));
+        assert!(html.contains("<td>game.8o:3</td><td>executed</td>"));
+    }
+}
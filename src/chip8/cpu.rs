@@ -1,41 +1,49 @@
 //! API exposing processor mechanisms (instrution fetching, decoding and executing)
 use std::time::{Duration, Instant};
 
+use super::coverage::{Coverage, EXECUTED, SPRITE, WRITTEN};
 use super::display::{Sprite, Vram};
 use super::font::FONT_UNIT_SIZE;
 use super::input::KeyBoard;
-use super::memory::{self, Mem, Registers, Stack, FONTS_BASE_ADDR, RAM_SIZE};
-use rand::{self, Rng};
+use super::memory::{self, Access, Mem, Registers, Stack, FONTS_BASE_ADDR, RAM_SIZE};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 const TIMER_EPSILON: u64 = 16; // Appr. 60 Hz if expressed in ms
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CPU {
     // Some useful registers
     registers: Registers,
     last_timer_change: Instant,
+    rng: StdRng, // Part of the state, so that snapshots replay the same random numbers
     // A stack
     stack: Stack, // independant from main ram
     // Mem
     mem: Mem,
     // Vram
     vram: Vram,
+    coverage: Option<Coverage>, // Recorded when enabled, kept across resets
 }
 
 pub enum CpuState {
     Normal,
     Error(String),
     Finished,
+    // The instruction at pc accessed a watched address (the access did happen)
+    Watchpoint { addr: u16, pc: u16, kind: Access },
 }
 
 impl CPU {
     pub fn new(mem: Mem) -> Self {
         let mut cpu = Self {
             last_timer_change: Instant::now(),
+            rng: StdRng::from_entropy(),
             registers: Registers::default(),
             stack: Stack::default(),
             vram: Vram::default(),
             mem,
+            coverage: None,
         };
         cpu.reset(); // Just for mem and pc reinit.
         cpu
@@ -45,6 +53,46 @@ impl CPU {
         &self.vram
     }
 
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn stack_mut(&mut self) -> &mut Stack {
+        &mut self.stack
+    }
+
+    pub fn mem(&self) -> &Mem {
+        &self.mem
+    }
+
+    pub fn mem_mut(&mut self) -> &mut Mem {
+        &mut self.mem
+    }
+
+    /// Starts recording which addresses get executed, drawn as sprites or written
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::new);
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Forgets the coverage recorded so far, if enabled, e.g. for a new rom
+    pub fn clear_coverage(&mut self) {
+        if let Some(coverage) = &mut self.coverage {
+            *coverage = Coverage::new();
+        }
+    }
+
     pub fn reset(&mut self) {
         self.registers = Registers::default();
         self.last_timer_change = Instant::now();
@@ -57,23 +105,70 @@ impl CPU {
         } // Ram gets reinitialized (rom, fonts), as pc
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        self.mem.load_rom(rom);
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<Vec<String>, String> {
+        self.mem.load_rom(rom)
+    }
+
+    /// Puts the cpu back in the state of a snapshot, keeping the current watchpoints and coverage
+    pub fn restore(&mut self, snapshot: &CPU) {
+        let watchpoints = self.mem.watchpoints().to_vec();
+        let coverage = self.coverage.take();
+        *self = snapshot.clone();
+        self.mem.set_watchpoints(watchpoints);
+        self.coverage = coverage;
+        self.last_timer_change = Instant::now();
+    }
+
+    /// Whether timers are due to be decreased before the next instruction (60 Hz appr.)
+    pub fn timers_due(&self) -> bool {
+        Instant::now() - self.last_timer_change >= Duration::from_millis(TIMER_EPSILON)
     }
-    
+
     pub fn tick(&mut self, kb: &KeyBoard) -> CpuState {
+        let timers = self.timers_due();
+        self.tick_with(kb, timers)
+    }
+
+    /// Executes the instruction at pc, decreasing timers first when told to
+    pub fn tick_with(&mut self, kb: &KeyBoard, decrease_timers: bool) -> CpuState {
         if self.registers.pc as usize >= RAM_SIZE {
             return CpuState::Finished;
         }
-        let instruction = self
-            .fetch(self.registers.pc)
-            .expect("Out of bounds word reading");
-        if Instant::now() - self.last_timer_change >= Duration::from_millis(TIMER_EPSILON) { // 60 Hz appr.
+        let pc = self.registers.pc;
+        let instruction = match self.fetch(pc) {
+            Some(instruction) => instruction,
+            None => return CpuState::Error(format!("No instruction to fetch at 0x{:03X}", pc)),
+        };
+        let fetch_hit = self.mem.watch_hit(pc as usize, 2, Access::Read);
+        if decrease_timers {
             self.decrease_delaytimer();
             self.decrease_soundtimer();
             self.last_timer_change = Instant::now();
         }
-        self.run(instruction, kb)
+        self.record_coverage(instruction);
+        match (self.run(instruction, kb), fetch_hit) {
+            (CpuState::Normal, Some(addr)) => CpuState::Watchpoint {
+                addr: addr as u16,
+                pc,
+                kind: Access::Read,
+            },
+            (state, _) => state,
+        }
+    }
+
+    fn record_coverage(&mut self, instruction: u16) {
+        let access = self.data_access(instruction);
+        let coverage = match &mut self.coverage {
+            Some(coverage) => coverage,
+            None => return,
+        };
+        let pc = self.registers.pc as usize;
+        coverage.mark(pc..pc + 2, EXECUTED);
+        match (access, instruction & 0xF000) {
+            (Some((addr, len, Access::Read)), 0xD000) => coverage.mark(addr..addr + len, SPRITE),
+            (Some((addr, len, Access::Write)), _) => coverage.mark(addr..addr + len, WRITTEN),
+            _ => (),
+        }
     }
 
     pub fn fetch(&self, pc: u16) -> Option<u16> {
@@ -92,7 +187,26 @@ impl CPU {
         }
     }
 
+    /// Data access (address, length and kind) an instruction is about to perform, if any
+    pub fn data_access(&self, instruction: u16) -> Option<(usize, usize, Access)> {
+        let i = self.registers.i as usize;
+        let x = ((instruction & 0x0F00) >> 8) as usize;
+        match (instruction & 0xF000, instruction & 0x00FF) {
+            (0xD000, _) => Some((i, (instruction & 0x000F) as usize, Access::Read)),
+            (0xF000, 0x33) => Some((i, 3, Access::Write)),
+            (0xF000, 0x55) => Some((i, x + 1, Access::Write)),
+            (0xF000, 0x65) => Some((i, x + 1, Access::Read)),
+            _ => None,
+        }
+    }
+
     pub fn run(&mut self, instruction: u16, kb: &KeyBoard) -> CpuState {
+        // Watchpoints are checked before executing, as I may move
+        let pc = self.registers.pc;
+        let watch_hit = self
+            .data_access(instruction)
+            .and_then(|(addr, len, kind)| Some((self.mem.watch_hit(addr, len, kind)?, kind)));
+
         //Nibbling
         let nnn = instruction & 0x0FFF;
         let kk = (instruction & 0x00FF) as u8;
@@ -235,7 +349,7 @@ impl CPU {
             0xB => self.registers.pc = nnn + (self.registers.v[0] as u16), // Set pc = V0 + nnn
             0xC => {
                 // Vx = rand AND kk
-                let random: u8 = rand::thread_rng().gen(); // 0-255
+                let random: u8 = self.rng.gen(); // 0-255
                 self.registers.v[x] = random & kk;
                 self.registers.pc += 2;
             }
@@ -293,7 +407,7 @@ impl CPU {
                     self.registers.pc += 2
                 }
                 0x1E => {
-                    self.registers.i = self.registers.i as u16 + self.registers.v[x] as u16;
+                    self.registers.i += self.registers.v[x] as u16;
                     self.registers.pc += 2;
                 }
                 0x29 => {
@@ -316,7 +430,7 @@ impl CPU {
                     for off in 0..=x {
                         self.mem.write_byte(
                             (self.registers.i + off as u16) as usize,
-                            self.registers.v[off as usize],
+                            self.registers.v[off],
                         );
                     }
                     self.registers.i += x as u16 + 1;
@@ -346,18 +460,25 @@ impl CPU {
                 ))
             }
         }
-        CpuState::Normal
+        match watch_hit {
+            Some((addr, kind)) => CpuState::Watchpoint {
+                addr: addr as u16,
+                pc,
+                kind,
+            },
+            None => CpuState::Normal,
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::memory::ROM_BASE_ADDR;
+    use super::CpuState;
     use super::CPU;
     use crate::chip8::display::VRAM_DEFAULT;
     use crate::chip8::input::KeyBoard;
-    use crate::chip8::memory::Mem;
-    //use super::CpuState;
+    use crate::chip8::memory::{Access, Mem, WatchKind};
 
     fn cpu_setup() -> CPU {
         CPU::new(Mem::new(Vec::from([1, 2, 3, 4]))) // Main setup with all default, but mem's rom (and ram) is filled with 4 bytes
@@ -400,4 +521,32 @@ mod test {
         let mut cpu = cpu_setup();
         cpu.run(0xF0E0, &KeyBoard::new());
     }
+
+    #[test]
+    fn watchpoints() {
+        let kb = KeyBoard::new();
+        // LD I, 0x300; LD B, V0; LD V1, [I]
+        let mut cpu = CPU::new(Mem::new(vec![0xA3, 0x00, 0xF0, 0x33, 0xF1, 0x65]));
+        cpu.mem.add_watchpoint(0x301..0x302, WatchKind::Write);
+        cpu.mem.add_watchpoint(0x204..0x206, WatchKind::Read);
+        assert!(matches!(cpu.tick(&kb), CpuState::Normal));
+        assert!(matches!(
+            cpu.tick(&kb),
+            CpuState::Watchpoint {
+                addr: 0x301,
+                pc: 0x202,
+                kind: Access::Write
+            }
+        ));
+        // Fetching a watched instruction is a read
+        assert!(matches!(
+            cpu.tick(&kb),
+            CpuState::Watchpoint {
+                addr: 0x204,
+                pc: 0x204,
+                kind: Access::Read
+            }
+        ));
+        assert_eq!(cpu.registers.pc, 0x206);
+    }
 }
@@ -0,0 +1,470 @@
+//! Tiny expression language for breakpoint conditions and logpoints
+//!
+//! Expressions combine registers (`v0`-`vf`, `i`, `pc`, `sp`, `dt`, `st`) and
+//! numbers (decimal, 0x-prefixed or 0b-prefixed) with C-like operators, e.g.
+//! `v3 == 0x10 && i > 0x300`. Comparisons and logical operators yield 1 or 0.
+//! The Cowgod assembler evaluates its operands with the same parser, through `evaluate`.
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::cpu::CPU;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnaryOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    // Binding power, higher binds tighter (same order as C)
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+        }
+    }
+
+    fn apply(&self, lhs: i64, rhs: i64) -> i64 {
+        match self {
+            BinaryOp::Or => (lhs != 0 || rhs != 0) as i64,
+            BinaryOp::And => (lhs != 0 && rhs != 0) as i64,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::BitXor => lhs ^ rhs,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::Eq => (lhs == rhs) as i64,
+            BinaryOp::Ne => (lhs != rhs) as i64,
+            BinaryOp::Lt => (lhs < rhs) as i64,
+            BinaryOp::Le => (lhs <= rhs) as i64,
+            BinaryOp::Gt => (lhs > rhs) as i64,
+            BinaryOp::Ge => (lhs >= rhs) as i64,
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Sub => lhs.wrapping_sub(rhs),
+            BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+            BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+            BinaryOp::Mul => lhs.wrapping_mul(rhs),
+            // Dividing by zero yields 0 rather than stopping the emulator
+            BinaryOp::Div => lhs.checked_div(rhs).unwrap_or(0),
+            BinaryOp::Rem => lhs.checked_rem(rhs).unwrap_or(0),
+        }
+    }
+
+    // Fails where apply would wrap, shifts losing bits included
+    fn checked_apply(&self, lhs: i64, rhs: i64) -> Result<i64, String> {
+        let shift = || u32::try_from(rhs).ok();
+        let value = match self {
+            BinaryOp::Div | BinaryOp::Rem if rhs == 0 => {
+                return Err(String::from("Division by zero"))
+            }
+            BinaryOp::Add => lhs.checked_add(rhs),
+            BinaryOp::Sub => lhs.checked_sub(rhs),
+            BinaryOp::Mul => lhs.checked_mul(rhs),
+            BinaryOp::Div => lhs.checked_div(rhs),
+            BinaryOp::Rem => lhs.checked_rem(rhs),
+            BinaryOp::Shl => shift()
+                .and_then(|shift| Some((lhs.checked_shl(shift)?, shift)))
+                .filter(|(value, shift)| value >> shift == lhs)
+                .map(|(value, _)| value),
+            BinaryOp::Shr => shift().and_then(|shift| lhs.checked_shr(shift)),
+            _ => Some(self.apply(lhs, rhs)),
+        };
+        value.ok_or_else(|| String::from("Value overflows"))
+    }
+}
+
+// Names are registers for the debugger, labels for the assembler
+#[derive(Debug, Clone, PartialEq)]
+enum Node<T> {
+    Num(i64),
+    Name(T),
+    Unary(UnaryOp, Box<Node<T>>),
+    Binary(BinaryOp, Box<Node<T>>, Box<Node<T>>),
+}
+
+impl<T> Node<T> {
+    fn eval(&self, value: &impl Fn(&T) -> i64) -> i64 {
+        match self {
+            Node::Num(num) => *num,
+            Node::Name(name) => value(name),
+            Node::Unary(op, node) => {
+                let val = node.eval(value);
+                match op {
+                    UnaryOp::Not => (val == 0) as i64,
+                    UnaryOp::Neg => val.wrapping_neg(),
+                    UnaryOp::BitNot => !val,
+                }
+            }
+            Node::Binary(op, lhs, rhs) => op.apply(lhs.eval(value), rhs.eval(value)),
+        }
+    }
+
+    fn checked_eval(&self, value: &impl Fn(&T) -> i64) -> Result<i64, String> {
+        match self {
+            Node::Unary(op, node) => {
+                let val = node.checked_eval(value)?;
+                match op {
+                    UnaryOp::Not => Ok((val == 0) as i64),
+                    UnaryOp::Neg => val
+                        .checked_neg()
+                        .ok_or_else(|| String::from("Value overflows")),
+                    UnaryOp::BitNot => Ok(!val),
+                }
+            }
+            Node::Binary(op, lhs, rhs) => {
+                op.checked_apply(lhs.checked_eval(value)?, rhs.checked_eval(value)?)
+            }
+            _ => Ok(self.eval(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+// Longest operators first, so that `<=` isn't read as `<`
+const OPERATORS: [&str; 21] = [
+    "||", "&&", "==", "!=", "<<", ">>", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "*", "/",
+    "%", "!", "~", "=",
+];
+
+// Labels may hold dots, as `.loop`
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if is_name_char(c) {
+            let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            let word = &rest[..len];
+            tokens.push(if c.is_ascii_digit() {
+                Token::Num(parse_num(word)?)
+            } else {
+                Token::Ident(word.to_string())
+            });
+            rest = &rest[len..];
+        } else if c == '$' {
+            tokens.push(Token::Ident(String::from("$"))); // The current address when assembling
+            rest = &rest[1..];
+        } else if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("Unexpected character in expression: {}", c))?;
+            if *op == "=" {
+                return Err(String::from("Use == to compare values"));
+            }
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn parse_num(word: &str) -> Result<i64, String> {
+    let parsed = if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = word.strip_prefix("0b").or_else(|| word.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2)
+    } else {
+        word.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number: {}", word))
+}
+
+fn binary_op(op: &str) -> Option<BinaryOp> {
+    Some(match op {
+        "||" => BinaryOp::Or,
+        "&&" => BinaryOp::And,
+        "|" => BinaryOp::BitOr,
+        "^" => BinaryOp::BitXor,
+        "&" => BinaryOp::BitAnd,
+        "==" => BinaryOp::Eq,
+        "!=" => BinaryOp::Ne,
+        "<" => BinaryOp::Lt,
+        "<=" => BinaryOp::Le,
+        ">" => BinaryOp::Gt,
+        ">=" => BinaryOp::Ge,
+        "<<" => BinaryOp::Shl,
+        ">>" => BinaryOp::Shr,
+        "+" => BinaryOp::Add,
+        "-" => BinaryOp::Sub,
+        "*" => BinaryOp::Mul,
+        "/" => BinaryOp::Div,
+        "%" => BinaryOp::Rem,
+        _ => return None,
+    })
+}
+
+/// Precedence climbing parser over a token list, resolving names as it meets them
+struct Parser<'a, T> {
+    tokens: Vec<Token>,
+    pos: usize,
+    name: &'a dyn Fn(&str) -> Result<T, String>,
+}
+
+impl<T> Parser<'_, T> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expression(&mut self, min_precedence: u8) -> Result<Node<T>, String> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = match binary_op(op) {
+                Some(op) if op.precedence() >= min_precedence => op,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.expression(op.precedence() + 1)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node<T>, String> {
+        match self.next() {
+            Some(Token::Num(num)) => Ok(Node::Num(num)),
+            Some(Token::Ident(name)) => (self.name)(&name).map(Node::Name),
+            Some(Token::Op(op)) => {
+                let op = match op {
+                    "+" => return self.unary(),
+                    "!" => UnaryOp::Not,
+                    "-" => UnaryOp::Neg,
+                    "~" => UnaryOp::BitNot,
+                    _ => return Err(format!("Unexpected operator: {}", op)),
+                };
+                Ok(Node::Unary(op, Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                let node = self.expression(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(node),
+                    _ => Err(String::from("Missing closing parenthesis")),
+                }
+            }
+            Some(Token::Close) => Err(String::from("Unexpected closing parenthesis")),
+            None => Err(String::from("Unexpected end of expression")),
+        }
+    }
+}
+
+/// Parses a whole expression, names being resolved by name
+fn parse<T>(s: &str, name: &dyn Fn(&str) -> Result<T, String>) -> Result<Node<T>, String> {
+    let mut parser = Parser {
+        tokens: tokenize(s)?,
+        pos: 0,
+        name,
+    };
+    let node = parser.expression(0)?;
+    if parser.pos < parser.tokens.len() {
+        return Err(format!("Unexpected trailing input in expression: {}", s));
+    }
+    Ok(node)
+}
+
+/// Evaluates a constant expression, e.g. an assembler operand, names being looked up
+/// by value. Unlike breakpoint conditions, overflows and divisions by zero are errors.
+pub fn evaluate(s: &str, value: &dyn Fn(&str) -> Result<i64, String>) -> Result<i64, String> {
+    parse(s, value)?.checked_eval(&|value| *value)
+}
+
+fn operand(name: &str) -> Result<Operand, String> {
+    let name = name.to_ascii_lowercase();
+    match name.as_str() {
+        "i" => Ok(Operand::I),
+        "pc" => Ok(Operand::Pc),
+        "sp" => Ok(Operand::Sp),
+        "dt" => Ok(Operand::Dt),
+        "st" => Ok(Operand::St),
+        _ => match name.strip_prefix('v') {
+            Some(idx) if idx.len() == 1 => usize::from_str_radix(idx, 16)
+                .map(Operand::V)
+                .map_err(|_| format!("Unknown register: {}", name)),
+            _ => Err(format!("Unknown register: {}", name)),
+        },
+    }
+}
+
+/// A parsed expression, remembering its source text for display
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    source: String,
+    node: Node<Operand>,
+}
+
+impl Expr {
+    /// Evaluates the expression against the cpu registers and call depth
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        let registers = cpu.registers();
+        self.node.eval(&|operand| match operand {
+            Operand::V(idx) => registers.v[*idx] as i64,
+            Operand::I => registers.i as i64,
+            Operand::Pc => registers.pc as i64,
+            Operand::Sp => cpu.stack().len() as i64,
+            Operand::Dt => registers.dt as i64,
+            Operand::St => registers.st as i64,
+        })
+    }
+
+    /// Whether the expression holds (evaluates to a non-zero value)
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            source: s.trim().to_string(),
+            node: parse(s, &operand)?,
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, Expr};
+    use crate::chip8::cpu::CPU;
+    use crate::chip8::memory::Mem;
+
+    fn eval(s: &str, cpu: &CPU) -> i64 {
+        s.parse::<Expr>().unwrap().eval(cpu)
+    }
+
+    #[test]
+    fn arithmetic_and_precedence() {
+        let cpu = CPU::new(Mem::new(vec![]));
+        assert_eq!(eval("1 + 2 * 3", &cpu), 7);
+        assert_eq!(eval("(1 + 2) * 3", &cpu), 9);
+        assert_eq!(eval("10 - 4 - 3", &cpu), 3);
+        assert_eq!(eval("0x10 | 1 == 1", &cpu), 0x11);
+        assert_eq!(eval("-1 + ~0 + !0", &cpu), -1);
+        assert_eq!(eval("7 / 0", &cpu), 0);
+        assert_eq!(eval("1 << 4 | 2 == 2", &cpu), 0x11);
+    }
+
+    #[test]
+    fn constant_expressions() {
+        let labels = |name: &str| match name {
+            "$" => Ok(0x200),
+            ".loop" => Ok(0x204),
+            _ => Err(format!("Undefined label: {}", name)),
+        };
+        assert_eq!(evaluate("(.loop - $) * 2 + 0b11", &labels), Ok(11));
+        assert_eq!(evaluate("+1 << 62 >> 61", &labels), Ok(2));
+        for overflowing in [
+            "1 << 70",
+            "1 << 63",
+            "1 << -1",
+            "0x7FFFFFFFFFFFFFFF * 2",
+            "0x7FFFFFFFFFFFFFFF + 1",
+            "-0x7FFFFFFFFFFFFFFF - 2",
+        ] {
+            assert_eq!(
+                evaluate(overflowing, &labels),
+                Err(String::from("Value overflows")),
+                "{}",
+                overflowing
+            );
+        }
+        assert_eq!(
+            evaluate("1 % 0", &labels),
+            Err(String::from("Division by zero"))
+        );
+        assert_eq!(
+            evaluate("end", &labels),
+            Err(String::from("Undefined label: end"))
+        );
+    }
+
+    #[test]
+    fn registers() {
+        let mut cpu = CPU::new(Mem::new(vec![]));
+        cpu.registers_mut().v[3] = 0x10;
+        cpu.registers_mut().i = 0x301;
+        let cond: Expr = "v3 == 0x10 && i > 0x300".parse().unwrap();
+        assert!(cond.holds(&cpu));
+        assert_eq!(cond.to_string(), "v3 == 0x10 && i > 0x300");
+        cpu.registers_mut().i = 0x300;
+        assert!(!cond.holds(&cpu));
+        assert_eq!(eval("pc", &cpu), 0x200);
+        assert_eq!(eval("VF + sp + dt + st", &cpu), 0);
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!("v3 = 1".parse::<Expr>().is_err());
+        assert!("vg > 1".parse::<Expr>().is_err());
+        assert!("(1 + 2".parse::<Expr>().is_err());
+        assert!("1 2".parse::<Expr>().is_err());
+        assert!("1 +".parse::<Expr>().is_err());
+        assert!("".parse::<Expr>().is_err());
+    }
+}
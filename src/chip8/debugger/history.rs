@@ -0,0 +1,150 @@
+//! Execution history, for stepping backwards
+//!
+//! The cpu is snapshotted every `SNAPSHOT_INTERVAL` instructions, and the inputs of
+//! every instruction (pressed key, timer ticks) are recorded. Going back restores the
+//! closest snapshot before the target and re-executes the instructions in between
+//! with the recorded inputs. Random numbers replay as well, the generator being part
+//! of the snapshotted cpu.
+use std::collections::VecDeque;
+
+use crate::chip8::cpu::{CpuState, CPU};
+use crate::chip8::Interpreter;
+
+const SNAPSHOT_INTERVAL: u64 = 256; // Instructions between two snapshots
+const MAX_SNAPSHOTS: usize = 64; // Older history is forgotten
+
+// What an instruction depended on, besides the cpu state
+#[derive(Debug)]
+struct Input {
+    pc: u16,
+    key: Option<u8>,
+    timers: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    cycle: u64, // Instructions executed since recording started
+    snapshots: VecDeque<(u64, CPU)>,
+    inputs: VecDeque<Input>, // One per instruction since the oldest snapshot
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Instructions executed so far
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Earliest cycle that can be gone back to
+    pub fn oldest(&self) -> u64 {
+        self.snapshots
+            .front()
+            .map_or(self.cycle, |(cycle, _)| *cycle)
+    }
+
+    /// Forgets everything, e.g. when the state was changed by hand and can't be replayed
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.inputs.clear();
+    }
+
+    /// Executes one instruction, recording what is needed to replay it
+    pub fn tick(&mut self, chip8: &mut Interpreter) -> CpuState {
+        let due = match self.snapshots.back() {
+            Some((last, _)) => *last != self.cycle && self.cycle.is_multiple_of(SNAPSHOT_INTERVAL),
+            None => true,
+        };
+        if due {
+            self.snapshots.push_back((self.cycle, chip8.cpu.clone()));
+            if self.snapshots.len() > MAX_SNAPSHOTS {
+                self.snapshots.pop_front();
+                let forgotten = (self.oldest() + self.inputs.len() as u64 - self.cycle) as usize;
+                self.inputs.drain(..forgotten);
+            }
+        }
+        let input = Input {
+            pc: chip8.cpu.registers().pc,
+            key: chip8.keyboard.get_key_pressed(),
+            timers: chip8.cpu.timers_due(),
+        };
+        let state = chip8.tick_with(input.timers);
+        self.inputs.push_back(input);
+        self.cycle += 1;
+        state
+    }
+
+    /// Pc of the instruction executed at cycle, if still recorded
+    pub fn pc_at(&self, cycle: u64) -> Option<u16> {
+        let idx = cycle.checked_sub(self.oldest())?;
+        self.inputs.get(idx as usize).map(|input| input.pc)
+    }
+
+    /// Brings the interpreter back to its state before the instruction of cycle ran
+    ///
+    /// Everything recorded after that point is dropped, execution then records anew.
+    pub fn rewind(&mut self, chip8: &mut Interpreter, cycle: u64) -> Result<(), String> {
+        if cycle > self.cycle || cycle < self.oldest() || self.snapshots.is_empty() {
+            return Err(String::from("No more reverse-execution history"));
+        }
+        self.snapshots.retain(|(snapshot, _)| *snapshot <= cycle);
+        let (from, snapshot) = self.snapshots.back().expect("Oldest snapshot was kept");
+        let from = *from;
+        chip8.cpu.restore(snapshot);
+        let base = self.oldest();
+        self.inputs.truncate((cycle - base) as usize);
+
+        // Replaying must not show up in the trace, profile, smc findings or heatmap, nor change
+        // the live keyboard
+        let tracer = chip8.tracer.take();
+        let profiler = chip8.profiler.take();
+        let smc = chip8.smc.take();
+        let heatmap = chip8.heatmap.take();
+        let key = chip8.keyboard.get_key_pressed();
+        for input in self.inputs.range((from - base) as usize..) {
+            chip8.feed_key(input.key);
+            chip8.tick_with(input.timers);
+        }
+        chip8.feed_key(key);
+        chip8.tracer = tracer;
+        chip8.profiler = profiler;
+        chip8.smc = smc;
+        chip8.heatmap = heatmap;
+        self.cycle = cycle;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{History, SNAPSHOT_INTERVAL};
+    use crate::chip8::Interpreter;
+
+    #[test]
+    fn rewind_replays_inputs_and_randomness() {
+        // RND V0, 0xFF; ADD V1, V0; SKP V2; JP 0x200; ADD V3, 1; JP 0x200
+        let mut chip8 = Interpreter::new();
+        chip8
+            .load_rom(vec![
+                0xC0, 0xFF, 0x81, 0x04, 0xE2, 0x9E, 0x12, 0x00, 0x73, 0x01, 0x12, 0x00,
+            ])
+            .unwrap();
+        let mut history = History::new();
+        let mut states = Vec::new();
+        for cycle in 0..SNAPSHOT_INTERVAL * 3 {
+            chip8.feed_key(if cycle % 7 == 0 { Some(0) } else { None });
+            states.push(chip8.cpu.registers().clone());
+            history.tick(&mut chip8);
+        }
+        for cycle in [SNAPSHOT_INTERVAL * 2 + 5, SNAPSHOT_INTERVAL + 1, 3] {
+            history.rewind(&mut chip8, cycle).unwrap();
+            assert_eq!(history.cycle(), cycle);
+            assert_eq!(chip8.cpu.registers(), &states[cycle as usize]);
+        }
+        assert_eq!(history.pc_at(2), Some(0x204));
+        assert_eq!(history.pc_at(3), None);
+        assert!(history.rewind(&mut chip8, 4).is_err());
+    }
+}
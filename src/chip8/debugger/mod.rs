@@ -0,0 +1,934 @@
+//! Interactive debugger, driving an interpreter instruction per instruction
+//!
+//! The debugger owns no machine state: every command is applied to the
+//! `Interpreter` it is given, through the CPU accessors.
+pub mod expr;
+pub mod history;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use expr::Expr;
+use history::History;
+
+use super::cpu::CpuState;
+use super::disasm;
+use super::memory::{Access, WatchKind, RAM_SIZE};
+use super::symbols::Symbols;
+use super::Interpreter;
+
+const DISASM_LINES: usize = 10; // Instructions shown by `disasm`
+const MEM_DEFAULT_LEN: usize = 64; // Bytes shown by `mem` when no length is given
+const MEM_LINE_WIDTH: usize = 16; // Bytes per line of a `mem` dump
+
+pub const HELP: &str = "\
+break [addr]         set a breakpoint at addr, or list breakpoints
+  break <addr> [hits <n>] [if <cond>]
+                     only stop from the nth time cond holds at addr,
+                     e.g. `break 0x2A0 if v3 == 0x10 && i > 0x300`
+log <addr> <expr>    print expr each time addr is reached, without stopping
+delete <addr>        remove the breakpoint or logpoint at addr
+watch <addr> [len]   stop after writes to len bytes from addr (default 1)
+rwatch <addr> [len]  stop after reads of len bytes from addr
+awatch <addr> [len]  stop after reads or writes of len bytes from addr
+unwatch <addr> [len] remove the watchpoints on that range
+step [n]             execute n instructions (default 1)
+continue             resume execution until the next breakpoint
+reverse-step [n]     go back n instructions (default 1)
+reverse-continue     go back to the previous stop at a breakpoint, ignoring
+                     hit counts, or to the start of the recorded history
+regs                 show V0-VF, I, PC, SP, DT and ST
+stack                show the call stack, innermost first
+mem <addr> [len]     hex dump len bytes of ram from addr
+disasm [addr]        disassemble from addr (default: PC)
+set <reg> <value>    write a register (v0-vf, i, pc, dt, st)
+poke <addr> <bytes>  write bytes into ram from addr
+reset                reset the cpu and reload the rom
+                     (set, poke and reset forget the recorded history)
+help                 show this message
+quit                 exit the emulator
+Numbers are decimal, or hexadecimal with a 0x prefix.";
+
+/// A register that can be written from the debugger
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Dt,
+    St,
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str() {
+            "i" => Ok(Register::I),
+            "pc" => Ok(Register::Pc),
+            "dt" => Ok(Register::Dt),
+            "st" => Ok(Register::St),
+            _ => match lower.strip_prefix('v') {
+                Some(idx) if idx.len() == 1 => usize::from_str_radix(idx, 16)
+                    .map(Register::V)
+                    .map_err(|_| format!("Unknown register: {}", s)),
+                _ => Err(format!("Unknown register: {}", s)),
+            },
+        }
+    }
+}
+
+/// A parsed debugger command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Break(Option<(u16, Breakpoint)>),
+    Delete(u16),
+    Watch(u16, usize, WatchKind),
+    Unwatch(u16, usize),
+    Step(usize),
+    Continue,
+    ReverseStep(usize),
+    ReverseContinue,
+    Regs,
+    Stack,
+    Mem(u16, usize),
+    Disasm(Option<u16>),
+    Set(Register, u16),
+    Poke(u16, Vec<u8>),
+    Reset,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().ok_or_else(|| String::from("Empty command"))?;
+        let args: Vec<&str> = words.collect();
+        let command = match (name, args.as_slice()) {
+            ("break" | "b", []) => Command::Break(None),
+            ("break" | "b", [addr, ..]) => {
+                let clauses = skip_words(s, 2);
+                Command::Break(Some((parse_number(addr)?, clauses.parse()?)))
+            }
+            ("log", [addr, _, ..]) => {
+                let expr = skip_words(s, 2).parse()?;
+                Command::Break(Some((parse_number(addr)?, Breakpoint::logpoint(expr))))
+            }
+            ("delete" | "d", [addr]) => Command::Delete(parse_number(addr)?),
+            ("watch" | "rwatch" | "awatch", [addr, len @ ..]) if len.len() <= 1 => {
+                let kind = match name {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                Command::Watch(parse_number(addr)?, parse_len(len)?, kind)
+            }
+            ("unwatch", [addr, len @ ..]) if len.len() <= 1 => {
+                Command::Unwatch(parse_number(addr)?, parse_len(len)?)
+            }
+            ("step" | "s", []) => Command::Step(1),
+            ("step" | "s", [n]) => Command::Step(parse_number(n)? as usize),
+            ("continue" | "c", []) => Command::Continue,
+            ("reverse-step" | "rs", []) => Command::ReverseStep(1),
+            ("reverse-step" | "rs", [n]) => Command::ReverseStep(parse_number(n)? as usize),
+            ("reverse-continue" | "rc", []) => Command::ReverseContinue,
+            ("regs" | "r", []) => Command::Regs,
+            ("stack" | "bt", []) => Command::Stack,
+            ("mem" | "m", [addr]) => Command::Mem(parse_number(addr)?, MEM_DEFAULT_LEN),
+            ("mem" | "m", [addr, len]) => {
+                Command::Mem(parse_number(addr)?, parse_number(len)? as usize)
+            }
+            ("disasm" | "x", []) => Command::Disasm(None),
+            ("disasm" | "x", [addr]) => Command::Disasm(Some(parse_number(addr)?)),
+            ("set", [reg, value]) => Command::Set(reg.parse()?, parse_number(value)?),
+            ("poke", [addr, bytes @ ..]) if !bytes.is_empty() => {
+                let bytes = bytes
+                    .iter()
+                    .map(|byte| parse_byte(byte))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                Command::Poke(parse_number(addr)?, bytes)
+            }
+            ("reset", []) => Command::Reset,
+            ("help" | "h", []) => Command::Help,
+            ("quit" | "q", []) => Command::Quit,
+            _ => return Err(format!("Invalid command: {} (try `help`)", s.trim())),
+        };
+        Ok(command)
+    }
+}
+
+// What remains of a command line after its first n words
+fn skip_words(s: &str, n: usize) -> &str {
+    let mut rest = s.trim_start();
+    for _ in 0..n {
+        let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[len..].trim_start();
+    }
+    rest
+}
+
+/// Parses a decimal or 0x-prefixed hexadecimal number
+pub fn parse_number(s: &str) -> Result<u16, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse::<u16>(),
+    };
+    parsed.map_err(|_| format!("Invalid number: {}", s))
+}
+
+// Optional watched length, one byte by default
+fn parse_len(len: &[&str]) -> Result<usize, String> {
+    match len.first() {
+        Some(len) => Ok(parse_number(len)? as usize),
+        None => Ok(1),
+    }
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    u8::try_from(parse_number(s)?).map_err(|_| format!("Value doesn't fit in a byte: {}", s))
+}
+
+/// A breakpoint, optionally conditional, or a logpoint when it has an expression to print
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Breakpoint {
+    pub condition: Option<Expr>,
+    pub hits_before_stop: usize, // Stop only once the breakpoint was hit this many times
+    pub log: Option<Expr>,
+    hits: usize,
+}
+
+impl Breakpoint {
+    pub fn logpoint(expr: Expr) -> Self {
+        Self {
+            log: Some(expr),
+            ..Default::default()
+        }
+    }
+
+    /// Times the breakpoint was reached with its condition holding
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+}
+
+/// Parses the clauses following the address: `[hits <n>] [if <cond>]`
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut breakpoint = Breakpoint::default();
+        let mut rest = s.trim();
+        if let Some(after) = rest.strip_prefix("hits ") {
+            let after = after.trim_start();
+            let len = after.find(char::is_whitespace).unwrap_or(after.len());
+            breakpoint.hits_before_stop = parse_number(&after[..len])? as usize;
+            rest = after[len..].trim_start();
+        }
+        if let Some(cond) = rest.strip_prefix("if ") {
+            breakpoint.condition = Some(cond.parse()?);
+        } else if !rest.is_empty() {
+            return Err(format!(
+                "Invalid breakpoint clause: {} (expected `hits` or `if`)",
+                rest
+            ));
+        }
+        Ok(breakpoint)
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.log {
+            Some(expr) => write!(f, "logs {}", expr)?,
+            None => write!(f, "stops")?,
+        }
+        if let Some(cond) = &self.condition {
+            write!(f, " if {}", cond)?;
+        }
+        if self.hits_before_stop > 1 {
+            write!(f, " from hit {}", self.hits_before_stop)?;
+        }
+        write!(f, " (hit {} time(s))", self.hits)
+    }
+}
+
+/// Why the debugger stopped a running program
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    Breakpoint(u16),
+    Watchpoint { addr: u16, pc: u16, kind: Access },
+    Error(String),
+    Finished,
+}
+
+impl Stop {
+    /// Message shown to the user, addresses being named by symbols
+    pub fn describe(&self, symbols: &Symbols) -> String {
+        match self {
+            Stop::Breakpoint(addr) => format!("Breakpoint hit at {}", symbols.describe(*addr)),
+            Stop::Watchpoint { addr, pc, kind } => format!(
+                "Watchpoint hit: {} at {} by the instruction at {}",
+                match kind {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                },
+                symbols.describe(*addr),
+                symbols.describe(*pc)
+            ),
+            Stop::Error(err) => format!("Execution error: {}", err),
+            Stop::Finished => String::from("Program finished"),
+        }
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&Symbols::new()))
+    }
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    paused: bool,
+    // Pc we just resumed from, whose breakpoint must not fire again right away
+    resume_from: Option<u16>,
+    logs: Vec<String>, // Logpoint output not yet shown to the user
+    history: History,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// Creates a debugger with no breakpoints, execution being paused
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeMap::new(),
+            paused: true,
+            resume_from: None,
+            logs: Vec::new(),
+            history: History::new(),
+        }
+    }
+
+    /// Forgets the history of a program started over, e.g. a reloaded rom, keeping breakpoints
+    pub fn restart(&mut self) {
+        self.resume_from = None;
+        self.history.clear();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (&u16, &Breakpoint)> {
+        self.breakpoints.iter()
+    }
+
+    /// Takes the lines printed by logpoints since the last call
+    pub fn drain_logs(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.logs)
+    }
+
+    /// Runs one instruction unless paused, stopping on breakpoints, errors and program end
+    pub fn tick(&mut self, chip8: &mut Interpreter) -> Option<Stop> {
+        if self.paused {
+            return None;
+        }
+        let pc = chip8.cpu.registers().pc;
+        if self.resume_from != Some(pc) && self.hit_breakpoint(pc, chip8) {
+            self.paused = true;
+            return Some(Stop::Breakpoint(pc));
+        }
+        let stop = self.step_one(chip8);
+        if chip8.cpu.registers().pc != pc {
+            self.resume_from = None;
+        }
+        stop
+    }
+
+    // Evaluates the breakpoint at pc, if any, returning whether execution must stop
+    fn hit_breakpoint(&mut self, pc: u16, chip8: &Interpreter) -> bool {
+        let breakpoint = match self.breakpoints.get_mut(&pc) {
+            Some(breakpoint) => breakpoint,
+            None => return false,
+        };
+        // Passed through once, until pc moves away (Fx0A waits on the same pc)
+        self.resume_from = Some(pc);
+        if let Some(cond) = &breakpoint.condition {
+            if !cond.holds(&chip8.cpu) {
+                return false;
+            }
+        }
+        breakpoint.hits += 1;
+        if breakpoint.hits < breakpoint.hits_before_stop {
+            return false;
+        }
+        match &breakpoint.log {
+            Some(expr) => {
+                let value = expr.eval(&chip8.cpu);
+                self.logs.push(format!(
+                    "{}: {} = {} (0x{:X})",
+                    chip8.symbols.describe(pc),
+                    expr,
+                    value,
+                    value
+                ));
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Sets an unconditional breakpoint at addr
+    pub fn set_breakpoint(&mut self, addr: u16) {
+        self.insert_breakpoint(addr, Breakpoint::default());
+    }
+
+    /// Sets a breakpoint at addr, replacing any previous one
+    pub fn insert_breakpoint(&mut self, addr: u16, breakpoint: Breakpoint) {
+        self.breakpoints.insert(addr, breakpoint);
+    }
+
+    /// Removes the breakpoint at addr, returning whether there was one
+    pub fn delete_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    /// Resumes execution, moving past a breakpoint at the current pc
+    pub fn resume(&mut self, chip8: &Interpreter) {
+        self.paused = false;
+        self.resume_from = Some(chip8.cpu.registers().pc);
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Executes a single instruction regardless of breakpoints, leaving execution paused
+    pub fn step(&mut self, chip8: &mut Interpreter) -> Option<Stop> {
+        self.paused = true;
+        self.step_one(chip8)
+    }
+
+    /// Goes back n instructions, or as far as the history goes, returning how many
+    pub fn reverse_step(&mut self, chip8: &mut Interpreter, n: usize) -> Result<usize, String> {
+        self.paused = true;
+        let cycle = self.history.cycle();
+        let target = cycle.saturating_sub(n as u64).max(self.history.oldest());
+        self.history.rewind(chip8, target)?;
+        Ok((cycle - target) as usize)
+    }
+
+    /// Goes back to the last time a breakpoint would have stopped execution
+    ///
+    /// Returns `None` when no breakpoint was hit, the history being rewound to its start.
+    pub fn reverse_continue(&mut self, chip8: &mut Interpreter) -> Option<Stop> {
+        self.paused = true;
+        let oldest = self.history.oldest();
+        for cycle in (oldest..self.history.cycle()).rev() {
+            let pc = self.history.pc_at(cycle)?;
+            let condition = match self.breakpoints.get(&pc) {
+                Some(breakpoint) if breakpoint.log.is_none() => breakpoint.condition.clone(),
+                _ => continue,
+            };
+            self.history.rewind(chip8, cycle).ok()?;
+            if condition.is_none_or(|cond| cond.holds(&chip8.cpu)) {
+                return Some(Stop::Breakpoint(pc));
+            }
+        }
+        self.history.rewind(chip8, oldest).ok()?;
+        None
+    }
+
+    fn step_one(&mut self, chip8: &mut Interpreter) -> Option<Stop> {
+        let stop = match self.history.tick(chip8) {
+            CpuState::Normal => return None,
+            CpuState::Error(err) => Stop::Error(err),
+            CpuState::Finished => Stop::Finished,
+            CpuState::Watchpoint { addr, pc, kind } => Stop::Watchpoint { addr, pc, kind },
+        };
+        self.paused = true;
+        Some(stop)
+    }
+
+    /// Applies a command to the interpreter, returning the text to show to the user
+    pub fn execute(&mut self, command: Command, chip8: &mut Interpreter) -> String {
+        match command {
+            Command::Break(None) => {
+                if self.breakpoints.is_empty() {
+                    return String::from("No breakpoints");
+                }
+                self.breakpoints
+                    .iter()
+                    .map(|(addr, breakpoint)| format!("0x{:03X} {}", addr, breakpoint))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+            Command::Break(Some((addr, breakpoint))) => {
+                let kind = if breakpoint.log.is_some() {
+                    "Logpoint"
+                } else {
+                    "Breakpoint"
+                };
+                self.insert_breakpoint(addr, breakpoint);
+                format!("{} set at 0x{:03X}", kind, addr)
+            }
+            Command::Delete(addr) => {
+                if self.delete_breakpoint(addr) {
+                    format!("Breakpoint at 0x{:03X} deleted", addr)
+                } else {
+                    format!("No breakpoint at 0x{:03X}", addr)
+                }
+            }
+            Command::Watch(addr, len, kind) => {
+                if addr as usize + len > RAM_SIZE || len == 0 {
+                    return format!("Invalid watched range: 0x{:03X} ({} bytes)", addr, len);
+                }
+                let range = addr as usize..addr as usize + len;
+                chip8.cpu.mem_mut().add_watchpoint(range, kind);
+                format!(
+                    "{:?} watchpoint set on 0x{:03X} ({} bytes)",
+                    kind, addr, len
+                )
+            }
+            Command::Unwatch(addr, len) => {
+                let range = addr as usize..addr as usize + len;
+                let mem = chip8.cpu.mem_mut();
+                let removed = [WatchKind::Read, WatchKind::Write, WatchKind::Access]
+                    .into_iter()
+                    .filter(|kind| mem.remove_watchpoint(range.clone(), *kind))
+                    .count();
+                format!("{} watchpoint(s) deleted", removed)
+            }
+            Command::Step(n) => {
+                self.pause();
+                for _ in 0..n {
+                    if let Some(stop) = self.step(chip8) {
+                        return stop.to_string();
+                    }
+                }
+                let pc = chip8.cpu.registers().pc;
+                disasm_line(chip8, pc)
+            }
+            Command::Continue => {
+                self.resume(chip8);
+                String::from("Continuing")
+            }
+            Command::ReverseStep(n) => match self.reverse_step(chip8, n) {
+                Ok(done) if done < n => format!(
+                    "Reached the start of the recorded history\n{}",
+                    disasm_line(chip8, chip8.cpu.registers().pc)
+                ),
+                Ok(_) => disasm_line(chip8, chip8.cpu.registers().pc),
+                Err(err) => err,
+            },
+            Command::ReverseContinue => match self.reverse_continue(chip8) {
+                Some(stop) => stop.to_string(),
+                None => String::from("Reached the start of the recorded history"),
+            },
+            Command::Regs => format_registers(chip8),
+            Command::Stack => format_stack(chip8),
+            Command::Mem(addr, len) => format_mem(chip8, addr as usize, len),
+            Command::Disasm(addr) => {
+                let start = addr.unwrap_or(chip8.cpu.registers().pc);
+                (0..DISASM_LINES)
+                    .map(|line| start as usize + line * 2)
+                    .take_while(|addr| addr + 1 < RAM_SIZE)
+                    .map(|addr| disasm_line(chip8, addr as u16))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+            Command::Set(register, value) => match set_register(chip8, register, value) {
+                Ok(()) => {
+                    self.history.clear();
+                    format!("{:?} = 0x{:X}", register, value)
+                }
+                Err(err) => err,
+            },
+            Command::Poke(addr, bytes) => {
+                if addr as usize + bytes.len() > RAM_SIZE {
+                    return format!("Address out of ram: 0x{:03X}", addr);
+                }
+                self.history.clear();
+                for (off, byte) in bytes.iter().enumerate() {
+                    chip8.cpu.mem_mut().write_byte(addr as usize + off, *byte);
+                }
+                format!("Wrote {} byte(s) at 0x{:03X}", bytes.len(), addr)
+            }
+            Command::Reset => {
+                chip8.cpu.reset();
+                self.paused = true;
+                self.resume_from = None;
+                self.history.clear();
+                String::from("Cpu reset, execution paused")
+            }
+            Command::Help => String::from(HELP),
+            Command::Quit => String::new(), // Handled by the frontend
+        }
+    }
+}
+
+fn set_register(chip8: &mut Interpreter, register: Register, value: u16) -> Result<(), String> {
+    if register == Register::Pc && chip8.cpu.fetch(value).is_none() {
+        return Err(format!("No instruction to fetch at 0x{:X}", value));
+    }
+    let registers = chip8.cpu.registers_mut();
+    let byte =
+        || u8::try_from(value).map_err(|_| format!("0x{:X} doesn't fit in {:?}", value, register));
+    match register {
+        Register::V(idx) => registers.v[idx] = byte()?,
+        Register::Dt => registers.dt = byte()?,
+        Register::St => registers.st = byte()?,
+        Register::I => registers.i = value,
+        Register::Pc => registers.pc = value,
+    }
+    Ok(())
+}
+
+fn disasm_line(chip8: &Interpreter, addr: u16) -> String {
+    let marker = if addr == chip8.cpu.registers().pc {
+        "=>"
+    } else {
+        "  "
+    };
+    let line = match chip8.cpu.mem().read_word(addr as usize) {
+        Some(word) => format!(
+            "{} 0x{:03X}: {:04X}  {}",
+            marker,
+            addr,
+            word,
+            disasm::mnemonic(word)
+        ),
+        None => format!("{} 0x{:03X}: out of ram", marker, addr),
+    };
+    with_location(chip8, addr, line)
+}
+
+// Appends where addr comes from in the source, when symbols are loaded
+fn with_location(chip8: &Interpreter, addr: u16, line: String) -> String {
+    match chip8.symbols.locate(addr) {
+        Some(location) => format!("{}  ; {}", line, location),
+        None => line,
+    }
+}
+
+fn format_registers(chip8: &Interpreter) -> String {
+    let registers = chip8.cpu.registers();
+    let v: Vec<String> = registers
+        .v
+        .iter()
+        .enumerate()
+        .map(|(idx, val)| format!("V{:X}={:02X}", idx, val))
+        .collect();
+    format!(
+        "{}\n{}\nI={:04X} PC={:04X} SP={} DT={:02X} ST={:02X}",
+        v[..8].join(" "),
+        v[8..].join(" "),
+        registers.i,
+        registers.pc,
+        chip8.cpu.stack().len(),
+        registers.dt,
+        registers.st
+    )
+}
+
+fn format_stack(chip8: &Interpreter) -> String {
+    let stack = chip8.cpu.stack();
+    if stack.is_empty() {
+        return String::from("Stack is empty");
+    }
+    stack
+        .as_slice()
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(depth, addr)| with_location(chip8, *addr, format!("#{} 0x{:03X}", depth, addr)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn format_mem(chip8: &Interpreter, addr: usize, len: usize) -> String {
+    let end = (addr + len).min(RAM_SIZE);
+    if addr >= end {
+        return format!("Address out of ram: 0x{:03X}", addr);
+    }
+    (addr..end)
+        .step_by(MEM_LINE_WIDTH)
+        .map(|line| {
+            let bytes = chip8
+                .cpu
+                .mem()
+                .read_segment((end - line).min(MEM_LINE_WIDTH), line)
+                .unwrap_or_default();
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("0x{:03X}: {}", line, hex.join(" "))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Breakpoint, Command, Debugger, Register, Stop};
+    use crate::chip8::memory::{Access, WatchKind};
+    use crate::chip8::symbols::Symbols;
+    use crate::chip8::Interpreter;
+
+    fn setup(rom: Vec<u8>) -> (Debugger, Interpreter) {
+        let mut chip8 = Interpreter::new();
+        chip8.load_rom(rom).unwrap();
+        (Debugger::new(), chip8)
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            "break 0x200".parse(),
+            Ok(Command::Break(Some((0x200, Breakpoint::default()))))
+        );
+        assert_eq!("step".parse(), Ok(Command::Step(1)));
+        assert_eq!("step 12".parse(), Ok(Command::Step(12)));
+        assert_eq!("mem 0x300 8".parse(), Ok(Command::Mem(0x300, 8)));
+        assert_eq!(
+            "set v3 0x10".parse(),
+            Ok(Command::Set(Register::V(3), 0x10))
+        );
+        assert_eq!("set VF 1".parse(), Ok(Command::Set(Register::V(15), 1)));
+        assert_eq!(
+            "poke 0x300 1 0xFF".parse(),
+            Ok(Command::Poke(0x300, vec![1, 0xFF]))
+        );
+        assert!("set v16 1".parse::<Command>().is_err());
+        assert!("poke 0x300 256".parse::<Command>().is_err());
+        assert!("frobnicate".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn starts_paused() {
+        let (mut debugger, mut chip8) = setup(vec![0x60, 0x01]);
+        assert!(debugger.tick(&mut chip8).is_none());
+        assert_eq!(chip8.cpu.registers().pc, 0x200);
+    }
+
+    #[test]
+    fn step_and_break() {
+        // LD V0, 1; LD V1, 2; JP 0x200
+        let (mut debugger, mut chip8) = setup(vec![0x60, 0x01, 0x61, 0x02, 0x12, 0x00]);
+        debugger.execute(Command::Step(2), &mut chip8);
+        assert_eq!(chip8.cpu.registers().v[..2], [1, 2]);
+        assert_eq!(chip8.cpu.registers().pc, 0x204);
+
+        debugger.set_breakpoint(0x202);
+        debugger.execute(Command::Continue, &mut chip8);
+        let mut stop = None;
+        for _ in 0..10 {
+            stop = debugger.tick(&mut chip8);
+            if stop.is_some() {
+                break;
+            }
+        }
+        assert_eq!(stop, Some(Stop::Breakpoint(0x202)));
+        assert!(debugger.is_paused());
+
+        // Continuing from a breakpoint moves past it
+        debugger.execute(Command::Continue, &mut chip8);
+        assert!(debugger.tick(&mut chip8).is_none());
+        assert_eq!(chip8.cpu.registers().pc, 0x204);
+    }
+
+    fn run_until_stop(debugger: &mut Debugger, chip8: &mut Interpreter) -> Option<Stop> {
+        for _ in 0..100 {
+            if let Some(stop) = debugger.tick(chip8) {
+                return Some(stop);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn parse_breakpoint_clauses() {
+        let Ok(Command::Break(Some((addr, breakpoint)))) =
+            "break 0x2A0 hits 3 if v3 == 0x10 && i > 0x300".parse::<Command>()
+        else {
+            panic!("Breakpoint should parse");
+        };
+        assert_eq!(addr, 0x2A0);
+        assert_eq!(breakpoint.hits_before_stop, 3);
+        assert_eq!(
+            breakpoint.condition.unwrap().to_string(),
+            "v3 == 0x10 && i > 0x300"
+        );
+        let Ok(Command::Break(Some((_, logpoint)))) = "log 0x2A0 v0 + 1".parse::<Command>() else {
+            panic!("Logpoint should parse");
+        };
+        assert_eq!(logpoint.log.unwrap().to_string(), "v0 + 1");
+        assert!("break 0x2A0 when v0 == 1".parse::<Command>().is_err());
+        assert!("break 0x2A0 if v0 = 1".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        // ADD V0, 1; JP 0x200
+        let (mut debugger, mut chip8) = setup(vec![0x70, 0x01, 0x12, 0x00]);
+        debugger.execute("break 0x202 if v0 == 3".parse().unwrap(), &mut chip8);
+        debugger.execute(Command::Continue, &mut chip8);
+        assert_eq!(
+            run_until_stop(&mut debugger, &mut chip8),
+            Some(Stop::Breakpoint(0x202))
+        );
+        assert_eq!(chip8.cpu.registers().v[0], 3);
+    }
+
+    #[test]
+    fn hit_count_and_logpoint() {
+        // ADD V0, 1; JP 0x200
+        let (mut debugger, mut chip8) = setup(vec![0x70, 0x01, 0x12, 0x00]);
+        // Continuing from 0x200 doesn't log it, it is only reached again after one loop
+        debugger.execute("log 0x200 v0 * 2".parse().unwrap(), &mut chip8);
+        debugger.execute("break 0x202 hits 2".parse().unwrap(), &mut chip8);
+        debugger.execute(Command::Continue, &mut chip8);
+        assert_eq!(
+            run_until_stop(&mut debugger, &mut chip8),
+            Some(Stop::Breakpoint(0x202))
+        );
+        assert_eq!(chip8.cpu.registers().v[0], 2);
+        assert_eq!(debugger.drain_logs(), vec!["0x200: v0 * 2 = 2 (0x2)"]);
+        assert!(debugger.drain_logs().is_empty());
+        assert_eq!(debugger.breakpoints().nth(1).unwrap().1.hits(), 2);
+    }
+
+    #[test]
+    fn stops_on_watchpoint() {
+        // LD I, 0x300; LD [I], V1; JP 0x204
+        let (mut debugger, mut chip8) = setup(vec![0xA3, 0x00, 0xF1, 0x55, 0x12, 0x04]);
+        assert_eq!(
+            "watch 0x301".parse(),
+            Ok(Command::Watch(0x301, 1, WatchKind::Write))
+        );
+        debugger.execute(Command::Watch(0x301, 1, WatchKind::Write), &mut chip8);
+        debugger.execute(Command::Continue, &mut chip8);
+        assert!(debugger.tick(&mut chip8).is_none());
+        assert_eq!(
+            debugger.tick(&mut chip8),
+            Some(Stop::Watchpoint {
+                addr: 0x301,
+                pc: 0x202,
+                kind: Access::Write
+            })
+        );
+        assert!(debugger.is_paused());
+        assert_eq!(
+            debugger.execute(Command::Unwatch(0x301, 1), &mut chip8),
+            "1 watchpoint(s) deleted"
+        );
+    }
+
+    #[test]
+    fn reverse_execution() {
+        // ADD V0, 1; ADD V1, 2; JP 0x200
+        let (mut debugger, mut chip8) = setup(vec![0x70, 0x01, 0x71, 0x02, 0x12, 0x00]);
+        assert_eq!(
+            debugger.execute("reverse-step".parse().unwrap(), &mut chip8),
+            "No more reverse-execution history"
+        );
+        debugger.execute(Command::Step(7), &mut chip8);
+        assert_eq!(chip8.cpu.registers().v[..2], [3, 4]);
+        debugger.execute("rs 2".parse().unwrap(), &mut chip8);
+        assert_eq!(chip8.cpu.registers().pc, 0x204);
+        assert_eq!(chip8.cpu.registers().v[..2], [2, 4]);
+
+        // Back to the previous time v0 was 1 at the breakpoint, then to the start
+        debugger.execute("break 0x202 if v0 == 1".parse().unwrap(), &mut chip8);
+        assert_eq!(
+            debugger.reverse_continue(&mut chip8),
+            Some(Stop::Breakpoint(0x202))
+        );
+        assert_eq!(chip8.cpu.registers().v[..2], [1, 0]);
+        assert_eq!(
+            debugger.execute(Command::ReverseContinue, &mut chip8),
+            "Reached the start of the recorded history"
+        );
+        assert_eq!(chip8.cpu.registers().pc, 0x200);
+        assert_eq!(chip8.cpu.registers().v[0], 0);
+
+        // Execution goes on from there, recording anew
+        debugger.execute(Command::Step(3), &mut chip8);
+        assert_eq!(chip8.cpu.registers().v[..2], [1, 2]);
+        debugger.execute(Command::Set(Register::V(0), 9), &mut chip8);
+        assert_eq!(
+            debugger.execute(Command::ReverseStep(1), &mut chip8),
+            "No more reverse-execution history"
+        );
+    }
+
+    #[test]
+    fn stops_on_error() {
+        let (mut debugger, mut chip8) = setup(vec![0x01, 0x23]);
+        debugger.execute(Command::Continue, &mut chip8);
+        assert!(matches!(debugger.tick(&mut chip8), Some(Stop::Error(_))));
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn set_and_poke() {
+        let (mut debugger, mut chip8) = setup(vec![0x12, 0x00]); // JP 0x200
+        debugger.execute(Command::Set(Register::V(3), 0x10), &mut chip8);
+        debugger.execute(Command::Set(Register::I, 0x300), &mut chip8);
+        assert_eq!(chip8.cpu.registers().v[3], 0x10);
+        assert_eq!(chip8.cpu.registers().i, 0x300);
+        debugger.execute(Command::Set(Register::Dt, 0x100), &mut chip8);
+        assert_eq!(chip8.cpu.registers().dt, 0);
+
+        assert_eq!(
+            debugger.execute(Command::Set(Register::Pc, 0xFFF), &mut chip8),
+            "No instruction to fetch at 0xFFF"
+        );
+        assert_eq!(chip8.cpu.registers().pc, 0x200);
+        // Still reachable by running off the end of ram
+        chip8.cpu.registers_mut().pc = 0xFFF;
+        assert!(debugger
+            .execute(Command::Step(1), &mut chip8)
+            .starts_with("Execution error: No instruction to fetch at 0xFFF"));
+
+        debugger.execute(Command::Poke(0x300, vec![0xAB, 0xCD]), &mut chip8);
+        assert_eq!(chip8.cpu.mem().read_word(0x300), Some(0xABCD));
+        assert_eq!(
+            debugger.execute(Command::Mem(0x300, 2), &mut chip8),
+            "0x300: AB CD"
+        );
+    }
+
+    #[test]
+    fn stack_listing() {
+        // CALL 0x204; (padding); CALL 0x208
+        let (mut debugger, mut chip8) = setup(vec![0x22, 0x04, 0x00, 0x00, 0x22, 0x08]);
+        assert_eq!(
+            debugger.execute(Command::Stack, &mut chip8),
+            "Stack is empty"
+        );
+        debugger.execute(Command::Step(2), &mut chip8);
+        assert_eq!(
+            debugger.execute(Command::Stack, &mut chip8),
+            "#0 0x206\n#1 0x202"
+        );
+        chip8.symbols = Symbols::parse("label 0x200 main\nlabel 0x204 draw").unwrap();
+        assert_eq!(
+            debugger.execute(Command::Stack, &mut chip8),
+            "#0 0x206  ; draw+0x2\n#1 0x202  ; main+0x2"
+        );
+        assert_eq!(
+            Stop::Breakpoint(0x208).describe(&chip8.symbols),
+            "Breakpoint hit at draw+0x4"
+        );
+    }
+}
@@ -0,0 +1,865 @@
+//! Decompiler, recovering structured control flow from the reachable code of roms
+//!
+//! Skips followed by a forward jump become `if`/`else` blocks, backward jumps become
+//! loops, skips over them `do`/`while` loops and skips over a jump out of a loop its
+//! `while` condition. Other guarded jumps become `if ... goto`. A pattern is only
+//! recovered when Octo assembles the structure back to the very same instructions,
+//! so the Octo output re-assembles to an identical rom. Anything else is left as
+//! plain jumps and skips, pseudo-code writing `skip` for jumping over the next
+//! instruction.
+//!
+//! Pseudo-code lists each subroutine with the registers it reads before writing them
+//! as parameters, and the ones it writes in a comment, both in address order: they
+//! don't follow calls nor branches.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::Range;
+
+use super::disasm::{self, Syntax};
+use super::flow::Flow;
+use super::instruction::Instruction;
+use super::memory::ROM_BASE_ADDR;
+
+const DATA_ROW_LEN: usize = 8; // Bytes per line of data
+
+/// Right-hand side of a comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rhs {
+    Byte(u8),
+    Reg(u8),
+}
+
+/// Condition under which some code runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cond {
+    Eq(u8, Rhs),
+    Ne(u8, Rhs),
+    Key(u8),    // Key of the register's value is held down
+    NotKey(u8), // It isn't
+}
+
+impl Cond {
+    /// Condition under which the instruction after a skip runs
+    fn of_skip(instruction: Instruction) -> Option<Self> {
+        use Instruction::*;
+        Some(match instruction {
+            SkipEqByte { x, kk } => Cond::Ne(x, Rhs::Byte(kk)),
+            SkipNeByte { x, kk } => Cond::Eq(x, Rhs::Byte(kk)),
+            SkipEqReg { x, y } => Cond::Ne(x, Rhs::Reg(y)),
+            SkipNeReg { x, y } => Cond::Eq(x, Rhs::Reg(y)),
+            SkipKey(x) => Cond::NotKey(x),
+            SkipNotKey(x) => Cond::Key(x),
+            _ => return None,
+        })
+    }
+
+    fn negate(self) -> Self {
+        match self {
+            Cond::Eq(x, rhs) => Cond::Ne(x, rhs),
+            Cond::Ne(x, rhs) => Cond::Eq(x, rhs),
+            Cond::Key(x) => Cond::NotKey(x),
+            Cond::NotKey(x) => Cond::Key(x),
+        }
+    }
+
+    /// Registers the condition reads, as a bit mask
+    fn reads(self) -> u16 {
+        match self {
+            Cond::Eq(x, Rhs::Reg(y)) | Cond::Ne(x, Rhs::Reg(y)) => 1 << x | 1 << y,
+            Cond::Eq(x, _) | Cond::Ne(x, _) | Cond::Key(x) | Cond::NotKey(x) => 1 << x,
+        }
+    }
+
+    fn octo(self) -> String {
+        let rhs = |rhs| match rhs {
+            Rhs::Byte(kk) => format!("0x{:02X}", kk),
+            Rhs::Reg(y) => format!("v{:x}", y),
+        };
+        match self {
+            Cond::Eq(x, y) => format!("v{:x} == {}", x, rhs(y)),
+            Cond::Ne(x, y) => format!("v{:x} != {}", x, rhs(y)),
+            Cond::Key(x) => format!("v{:x} key", x),
+            Cond::NotKey(x) => format!("v{:x} -key", x),
+        }
+    }
+
+    fn pseudo(self) -> String {
+        match self {
+            Cond::Key(x) => format!("key_down(v{:x})", x),
+            Cond::NotKey(x) => format!("!key_down(v{:x})", x),
+            cond => cond.octo(),
+        }
+    }
+}
+
+/// Structured statement
+#[derive(Debug, Clone, PartialEq)]
+enum Stmt {
+    Op(u16, Instruction),
+    Data(u16, Vec<u8>),
+    IfThen(u16, Cond, Instruction), // A skip and the one instruction it guards
+    If {
+        addr: u16,
+        cond: Cond,
+        then: Vec<Stmt>,
+        otherwise: Option<Vec<Stmt>>,
+    },
+    Loop(Vec<Stmt>), // Labels go on its first statement
+    While(u16, Cond),
+    DoWhile {
+        body: Vec<Stmt>, // Labels go on its first statement
+        addr: u16,       // Of the skip, over the jump back
+        cond: Cond,      // Under which the loop goes on
+    },
+}
+
+/// Subroutine, main included, from its entry point up to the next one
+struct Function {
+    entry: u16,
+    body: Vec<Stmt>,
+}
+
+/// Structured code of a rom
+pub struct Decompiled {
+    functions: Vec<Function>,
+    labels: BTreeMap<u16, String>,
+}
+
+struct Structurer {
+    flow: Flow,
+    lines: Vec<(u16, Option<Instruction>)>,
+    index: BTreeMap<u16, usize>, // Line starting at each address
+    consumed: BTreeSet<u16>,     // Jumps turned into structures, needing no label
+}
+
+impl Structurer {
+    // Whether something may jump to or point at addr, which then needs a label
+    fn referenced(&self, addr: u16) -> bool {
+        addr == ROM_BASE_ADDR as u16
+            || self.flow.calls.contains(&addr)
+            || self.flow.jumps.contains(&addr)
+            || self.flow.pointers.contains(&addr)
+    }
+
+    fn instruction(&self, idx: usize) -> Option<Instruction> {
+        self.lines
+            .get(idx)
+            .and_then(|(_, instruction)| *instruction)
+    }
+
+    fn is_skip(&self, idx: usize) -> bool {
+        self.instruction(idx).is_some_and(|ins| ins.is_skip())
+    }
+
+    /// Index of the line at addr, if it is code within the range along with the lines before it
+    fn code_until(&self, range: &Range<usize>, from: usize, addr: u16) -> Option<usize> {
+        let idx = *self.index.get(&addr)?;
+        let code = (from..idx).all(|idx| self.instruction(idx).is_some());
+        (idx >= from && idx <= range.end && code).then_some(idx)
+    }
+
+    /// Target of an unconditional jump at idx that nothing else refers to
+    fn free_jump(&self, idx: usize) -> Option<u16> {
+        match self.instruction(idx)? {
+            Instruction::Jump(target) if !self.referenced(self.lines[idx].0) => Some(target),
+            _ => None,
+        }
+    }
+
+    fn structure(&mut self, range: Range<usize>, loop_exit: Option<u16>) -> Vec<Stmt> {
+        let mut stmts = Vec::new();
+        let mut idx = range.start;
+        while idx < range.end {
+            let (addr, instruction) = self.lines[idx];
+            let instruction = match instruction {
+                Some(instruction) => instruction,
+                None => {
+                    let mut bytes = vec![self.flow.byte(addr).expect("Data is in the rom")];
+                    while idx + bytes.len() < range.end && bytes.len() < DATA_ROW_LEN {
+                        let (addr, instruction) = self.lines[idx + bytes.len()];
+                        if instruction.is_some() || self.referenced(addr) {
+                            break;
+                        }
+                        bytes.push(self.flow.byte(addr).expect("Data is in the rom"));
+                    }
+                    idx += bytes.len();
+                    stmts.push(Stmt::Data(addr, bytes));
+                    continue;
+                }
+            };
+            // Right after a skip, the instruction stays alone to be skipped
+            if idx > range.start && self.is_skip(idx - 1) {
+                stmts.push(Stmt::Op(addr, instruction));
+                idx += 1;
+                continue;
+            }
+            if let Some(end) = self.back_jump(&range, idx) {
+                self.consumed.insert(self.lines[end].0);
+                let body = self.structure(idx..end, Some(self.lines[end].0 + 2));
+                stmts.push(Stmt::Loop(body));
+                idx = end + 1;
+                continue;
+            }
+            if let Some(end) = self.guarded_back_jump(&range, idx) {
+                self.consumed.insert(self.lines[end].0);
+                let (addr, skip) = self.lines[end - 1];
+                let cond = Cond::of_skip(skip.expect("Skips are code")).expect("Skip");
+                let body = self.structure(idx..end - 1, Some(self.lines[end].0 + 2));
+                stmts.push(Stmt::DoWhile { body, addr, cond });
+                idx = end + 1;
+                continue;
+            }
+            if let Some(cond) = Cond::of_skip(instruction) {
+                let (stmt, next) = self.skip(&range, idx, cond, loop_exit);
+                stmts.push(stmt);
+                idx = next;
+                continue;
+            }
+            stmts.push(Stmt::Op(addr, instruction));
+            idx += 1;
+        }
+        stmts
+    }
+
+    // Whether the block of lines would end on a skip, which would jump out of it
+    fn ends_with_skip(&self, start: usize, end: usize) -> bool {
+        end > start && self.is_skip(end - 1)
+    }
+
+    // The last jump back to the line at idx, closing a loop within the range
+    fn back_jump(&self, range: &Range<usize>, idx: usize) -> Option<usize> {
+        let head = self.lines[idx].0;
+        (idx + 1..range.end).rev().find(|end| {
+            self.free_jump(*end) == Some(head)
+                && !self.ends_with_skip(idx, *end)
+                && self.code_until(range, idx, self.lines[*end].0).is_some()
+        })
+    }
+
+    // The last jump back to the line at idx right after a skip, closing a `do`/`while` loop
+    fn guarded_back_jump(&self, range: &Range<usize>, idx: usize) -> Option<usize> {
+        let head = self.lines[idx].0;
+        (idx + 1..range.end).rev().find(|end| {
+            self.free_jump(*end) == Some(head)
+                && self.is_skip(end - 1)
+                && !self.ends_with_skip(idx, end - 1)
+                && self.code_until(range, idx, self.lines[*end].0).is_some()
+        })
+    }
+
+    // Structure starting with the skip at idx, and the index of the line after it
+    fn skip(
+        &mut self,
+        range: &Range<usize>,
+        idx: usize,
+        cond: Cond,
+        loop_exit: Option<u16>,
+    ) -> (Stmt, usize) {
+        let addr = self.lines[idx].0;
+        if idx + 1 >= range.end {
+            let skip = self.instruction(idx).expect("Skips are code");
+            return (Stmt::Op(addr, skip), idx + 1);
+        }
+        let jump_addr = self.lines[idx + 1].0;
+        // The guarded instruction alone, e.g. a jump that can't be structured: `if ... goto`
+        let raw = match self.instruction(idx + 1) {
+            Some(next) if !next.is_skip() && !self.referenced(jump_addr) => {
+                (Stmt::IfThen(addr, cond, next), idx + 2)
+            }
+            _ => {
+                let skip = self.instruction(idx).expect("Skips are code");
+                (Stmt::Op(addr, skip), idx + 1)
+            }
+        };
+        match self.free_jump(idx + 1) {
+            // The jump runs when the skip doesn't: out of the loop, or over the `then` block
+            Some(target) if Some(target) == loop_exit => {
+                self.consumed.insert(jump_addr);
+                (Stmt::While(addr, cond.negate()), idx + 2)
+            }
+            Some(target) if target > jump_addr => {
+                let end = match self.code_until(range, idx + 2, target) {
+                    Some(end) => end,
+                    None => return raw,
+                };
+                // A `then` block ending with a jump over the code right after it has an `else`
+                let otherwise = match end.checked_sub(1).filter(|last| *last >= idx + 2) {
+                    Some(last) if !self.is_skip(last - 1) => self
+                        .free_jump(last)
+                        .filter(|target| *target > self.lines[end].0)
+                        .and_then(|target| self.code_until(range, end, target))
+                        .filter(|else_end| !self.ends_with_skip(end, *else_end))
+                        .map(|else_end| (last, else_end)),
+                    _ => None,
+                };
+                let (then_end, next) = match otherwise {
+                    Some((last, else_end)) => (last, else_end),
+                    None if self.ends_with_skip(idx + 2, end) => return raw,
+                    None => (end, end),
+                };
+                self.consumed.insert(jump_addr);
+                let then = self.structure(idx + 2..then_end, loop_exit);
+                let otherwise = otherwise.map(|(last, else_end)| {
+                    self.consumed.insert(self.lines[last].0);
+                    self.structure(end..else_end, loop_exit)
+                });
+                let stmt = Stmt::If {
+                    addr,
+                    cond: cond.negate(),
+                    then,
+                    otherwise,
+                };
+                (stmt, next)
+            }
+            _ => raw,
+        }
+    }
+}
+
+/// Decompiles the code reachable from `ROM_BASE_ADDR`, split into subroutines
+pub fn decompile(rom: &[u8]) -> Decompiled {
+    let flow = Flow::explore(rom);
+    let lines = flow.lines();
+    let index = lines
+        .iter()
+        .enumerate()
+        .map(|(idx, (addr, _))| (*addr, idx))
+        .collect();
+    let mut structurer = Structurer {
+        flow,
+        lines,
+        index,
+        consumed: BTreeSet::new(),
+    };
+
+    let mut entries: Vec<usize> = std::iter::once(ROM_BASE_ADDR as u16)
+        .chain(structurer.flow.calls.iter().copied())
+        .filter_map(|addr| structurer.index.get(&addr).copied())
+        .collect();
+    entries.dedup();
+    let mut functions = Vec::new();
+    for (nth, start) in entries.iter().enumerate() {
+        let end = entries
+            .get(nth + 1)
+            .copied()
+            .unwrap_or(structurer.lines.len());
+        functions.push(Function {
+            entry: structurer.lines[*start].0,
+            body: structurer.structure(*start..end, None),
+        });
+    }
+
+    // Jumps turned into structures don't need their target labelled
+    let flow = &structurer.flow;
+    let jumped: BTreeSet<u16> = flow
+        .code()
+        .filter(|pc| !structurer.consumed.contains(pc))
+        .filter_map(|pc| match Instruction::decode(flow.word(pc)?) {
+            Instruction::Jump(nnn) | Instruction::JumpV0(nnn) => Some(nnn),
+            _ => None,
+        })
+        .collect();
+    let mut labels = BTreeMap::new();
+    for (addr, _) in &structurer.lines {
+        let name = if *addr == ROM_BASE_ADDR as u16 {
+            String::from("main")
+        } else if flow.calls.contains(addr) {
+            format!("sub_{:03X}", addr)
+        } else if jumped.contains(addr) {
+            format!("label_{:03X}", addr)
+        } else if flow.pointers.contains(addr) {
+            format!("data_{:03X}", addr)
+        } else {
+            continue;
+        };
+        labels.insert(*addr, name);
+    }
+    Decompiled { functions, labels }
+}
+
+// Registers an instruction reads and writes, as bit masks
+fn registers(instruction: Instruction) -> (u16, u16) {
+    use Instruction::*;
+    let reg = |x: u8| 1u16 << x;
+    let upto = |x: u8| (reg(x) << 1).wrapping_sub(1);
+    const VF: u16 = 1 << 0xF;
+    match instruction {
+        SkipEqByte { x, .. } | SkipNeByte { x, .. } | SkipKey(x) | SkipNotKey(x) => (reg(x), 0),
+        SkipEqReg { x, y } | SkipNeReg { x, y } => (reg(x) | reg(y), 0),
+        LoadByte { x, .. } | Random { x, .. } | LoadDelay(x) | WaitKey(x) => (0, reg(x)),
+        AddByte { x, .. } => (reg(x), reg(x)),
+        LoadReg { x, y } => (reg(y), reg(x)),
+        Or { x, y } | And { x, y } | Xor { x, y } => (reg(x) | reg(y), reg(x)),
+        AddReg { x, y } | Sub { x, y } | SubN { x, y } => (reg(x) | reg(y), reg(x) | VF),
+        ShiftRight { x, .. } | ShiftLeft { x, .. } => (reg(x), reg(x) | VF),
+        JumpV0(_) => (reg(0), 0),
+        Draw { x, y, .. } => (reg(x) | reg(y), VF),
+        SetDelay(x) | SetSound(x) | AddI(x) | LoadFont(x) | Bcd(x) => (reg(x), 0),
+        Store(x) => (upto(x), 0),
+        Load(x) => (0, upto(x)),
+        Cls | Ret | Jump(_) | Call(_) | LoadI(_) | Data(_) => (0, 0),
+    }
+}
+
+// Address of the first statement, where a loop starts
+fn first_addr(stmts: &[Stmt]) -> Option<u16> {
+    match stmts.first()? {
+        Stmt::Op(addr, _)
+        | Stmt::Data(addr, _)
+        | Stmt::IfThen(addr, _, _)
+        | Stmt::If { addr, .. }
+        | Stmt::While(addr, _) => Some(*addr),
+        Stmt::Loop(body) => first_addr(body),
+        Stmt::DoWhile { body, addr, .. } => first_addr(body).or(Some(*addr)),
+    }
+}
+
+fn register_list(mask: u16) -> String {
+    let names: Vec<String> = (0..16)
+        .filter(|x| mask & (1 << x) != 0)
+        .map(|x| format!("v{:x}", x))
+        .collect();
+    names.join(", ")
+}
+
+fn indent(out: &mut String, depth: usize) {
+    out.push_str(&"  ".repeat(depth));
+}
+
+impl Decompiled {
+    fn operand(&self, nnn: u16) -> String {
+        self.labels
+            .get(&nnn)
+            .cloned()
+            .unwrap_or_else(|| format!("0x{:03X}", nnn))
+    }
+
+    /// Octo source assembling back to the same rom
+    pub fn to_octo(&self) -> String {
+        let mut out = String::new();
+        for function in &self.functions {
+            self.octo_block(&mut out, &function.body, 1, None);
+        }
+        out
+    }
+
+    fn octo_label(&self, out: &mut String, addr: u16) {
+        if let Some(label) = self.labels.get(&addr) {
+            writeln!(out, ": {}", label).unwrap();
+        }
+    }
+
+    // Loop heads are labelled before `loop`, which would otherwise come before `: main`
+    // and get a `jump main` inserted; labelled is the head whose label is already written
+    fn octo_block(&self, out: &mut String, stmts: &[Stmt], depth: usize, labelled: Option<u16>) {
+        let label = |out: &mut String, addr: u16| {
+            if Some(addr) != labelled {
+                self.octo_label(out, addr);
+            }
+        };
+        let format =
+            |instruction| disasm::format_with(instruction, Syntax::Octo, &|nnn| self.operand(nnn));
+        for stmt in stmts {
+            match stmt {
+                Stmt::Op(addr, instruction) => {
+                    label(out, *addr);
+                    indent(out, depth);
+                    writeln!(out, "{}", format(*instruction)).unwrap();
+                }
+                Stmt::Data(addr, bytes) => {
+                    label(out, *addr);
+                    indent(out, depth);
+                    let bytes: Vec<String> =
+                        bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    writeln!(out, "{}", bytes.join(" ")).unwrap();
+                }
+                Stmt::IfThen(addr, cond, instruction) => {
+                    label(out, *addr);
+                    indent(out, depth);
+                    writeln!(out, "if {} then {}", cond.octo(), format(*instruction)).unwrap();
+                }
+                Stmt::If {
+                    addr,
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    label(out, *addr);
+                    indent(out, depth);
+                    writeln!(out, "if {} begin", cond.octo()).unwrap();
+                    self.octo_block(out, then, depth + 1, labelled);
+                    if let Some(otherwise) = otherwise {
+                        indent(out, depth);
+                        out.push_str("else\n");
+                        self.octo_block(out, otherwise, depth + 1, labelled);
+                    }
+                    indent(out, depth);
+                    out.push_str("end\n");
+                }
+                Stmt::Loop(body) => {
+                    let head = first_addr(body);
+                    head.inspect(|head| label(out, *head));
+                    indent(out, depth);
+                    out.push_str("loop\n");
+                    self.octo_block(out, body, depth + 1, head.or(labelled));
+                    indent(out, depth);
+                    out.push_str("again\n");
+                }
+                Stmt::While(addr, cond) => {
+                    label(out, *addr);
+                    indent(out, depth);
+                    writeln!(out, "while {}", cond.octo()).unwrap();
+                }
+                Stmt::DoWhile { body, addr, cond } => {
+                    let head = first_addr(body).unwrap_or(*addr);
+                    label(out, head);
+                    indent(out, depth);
+                    out.push_str("loop\n");
+                    self.octo_block(out, body, depth + 1, Some(head));
+                    if *addr != head {
+                        label(out, *addr);
+                    }
+                    indent(out, depth + 1);
+                    writeln!(out, "if {} then again", cond.octo()).unwrap();
+                }
+            }
+        }
+    }
+
+    /// C-like pseudo-code, one function per subroutine
+    pub fn to_pseudo_code(&self) -> String {
+        let mut out = String::new();
+        for function in &self.functions {
+            let (mut read, mut written) = (0, 0);
+            usage(&function.body, &mut |reads, writes| {
+                read |= reads & !written;
+                written |= writes;
+            });
+            write!(
+                out,
+                "{}({}) {{",
+                self.operand(function.entry),
+                register_list(read)
+            )
+            .unwrap();
+            if written != 0 {
+                write!(out, "  // writes {}", register_list(written)).unwrap();
+            }
+            out.push('\n');
+            let mut printer = PseudoPrinter {
+                decompiled: self,
+                out,
+                entry: function.entry,
+                i: None,
+            };
+            printer.block(&function.body, 1);
+            out = printer.out;
+            out.push_str("}\n\n");
+        }
+        out.pop();
+        out
+    }
+}
+
+// Registers read and written by each statement, as bit masks, in address order
+fn usage(stmts: &[Stmt], f: &mut impl FnMut(u16, u16)) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Op(_, instruction) => {
+                let (reads, writes) = registers(*instruction);
+                f(reads, writes)
+            }
+            Stmt::Data(_, _) => (),
+            Stmt::IfThen(_, cond, instruction) => {
+                f(cond.reads(), 0);
+                let (reads, writes) = registers(*instruction);
+                f(reads, writes)
+            }
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                f(cond.reads(), 0);
+                usage(then, f);
+                if let Some(otherwise) = otherwise {
+                    usage(otherwise, f);
+                }
+            }
+            Stmt::Loop(body) => usage(body, f),
+            Stmt::While(_, cond) => f(cond.reads(), 0),
+            Stmt::DoWhile { body, cond, .. } => {
+                usage(body, f);
+                f(cond.reads(), 0)
+            }
+        }
+    }
+}
+
+// Writes pseudo-code, following what I points to for sprite draws
+struct PseudoPrinter<'a> {
+    decompiled: &'a Decompiled,
+    out: String,
+    entry: u16,
+    i: Option<String>, // Sprite I points to, when known
+}
+
+impl PseudoPrinter<'_> {
+    fn label(&mut self, addr: u16, depth: usize) {
+        if addr == self.entry {
+            return; // Named by the function
+        }
+        if let Some(label) = self.decompiled.labels.get(&addr) {
+            indent(&mut self.out, depth - 1);
+            writeln!(self.out, "{}:", label).unwrap();
+            self.i = None; // Reached from elsewhere
+        }
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        indent(&mut self.out, depth);
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn block(&mut self, stmts: &[Stmt], depth: usize) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Op(addr, instruction) => {
+                    self.label(*addr, depth);
+                    let text = match Cond::of_skip(*instruction) {
+                        Some(cond) => format!("if ({}) skip;", cond.negate().pseudo()),
+                        None => self.op(*instruction),
+                    };
+                    self.line(depth, &text);
+                }
+                Stmt::Data(addr, bytes) => {
+                    self.label(*addr, depth);
+                    let bytes: Vec<String> =
+                        bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    self.line(depth, &format!("bytes {};", bytes.join(", ")));
+                }
+                Stmt::IfThen(addr, cond, instruction) => {
+                    self.label(*addr, depth);
+                    let op = self.op(*instruction);
+                    self.line(depth, &format!("if ({}) {}", cond.pseudo(), op));
+                    self.i = None;
+                }
+                Stmt::If {
+                    addr,
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    self.label(*addr, depth);
+                    self.line(depth, &format!("if ({}) {{", cond.pseudo()));
+                    let before = self.i.clone();
+                    self.block(then, depth + 1);
+                    if let Some(otherwise) = otherwise {
+                        self.line(depth, "} else {");
+                        self.i = before;
+                        self.block(otherwise, depth + 1);
+                    }
+                    self.line(depth, "}");
+                    self.i = None;
+                }
+                Stmt::Loop(body) => {
+                    self.i = None;
+                    self.line(depth, "loop {");
+                    self.block(body, depth + 1);
+                    self.line(depth, "}");
+                    self.i = None;
+                }
+                Stmt::While(addr, cond) => {
+                    self.label(*addr, depth);
+                    self.line(depth, &format!("if ({}) break;", cond.negate().pseudo()));
+                }
+                Stmt::DoWhile { body, addr, cond } => {
+                    self.i = None;
+                    self.line(depth, "do {");
+                    self.block(body, depth + 1);
+                    self.label(*addr, depth + 1);
+                    self.line(depth, &format!("}} while ({});", cond.pseudo()));
+                    self.i = None;
+                }
+            }
+        }
+    }
+
+    fn op(&mut self, instruction: Instruction) -> String {
+        use Instruction::*;
+        let operand = |nnn| self.decompiled.operand(nnn);
+        let text = match instruction {
+            Cls => String::from("clear();"),
+            Ret => String::from("return;"),
+            Jump(nnn) => format!("goto {};", operand(nnn)),
+            Call(nnn) => format!("{}();", operand(nnn)),
+            LoadByte { x, kk } => format!("v{:x} = 0x{:02X};", x, kk),
+            AddByte { x, kk } => format!("v{:x} += 0x{:02X};", x, kk),
+            LoadReg { x, y } => format!("v{:x} = v{:x};", x, y),
+            Or { x, y } => format!("v{:x} |= v{:x};", x, y),
+            And { x, y } => format!("v{:x} &= v{:x};", x, y),
+            Xor { x, y } => format!("v{:x} ^= v{:x};", x, y),
+            AddReg { x, y } => format!("v{:x} += v{:x};  // vf = carry", x, y),
+            Sub { x, y } => format!("v{:x} -= v{:x};  // vf = no borrow", x, y),
+            SubN { x, y } => format!("v{:x} = v{:x} - v{:x};  // vf = no borrow", x, y, x),
+            ShiftRight { x, .. } => format!("v{:x} >>= 1;  // vf = bit shifted out", x),
+            ShiftLeft { x, .. } => format!("v{:x} <<= 1;  // vf = bit shifted out", x),
+            LoadI(nnn) => format!("i = {};", operand(nnn)),
+            JumpV0(nnn) => format!("goto *({} + v0);", operand(nnn)),
+            Random { x, kk } => format!("v{:x} = random() & 0x{:02X};", x, kk),
+            Draw { x, y, n } => format!(
+                "vf = draw(v{:x}, v{:x}, {}, {});",
+                x,
+                y,
+                self.i.as_deref().unwrap_or("i"),
+                n
+            ),
+            LoadDelay(x) => format!("v{:x} = delay;", x),
+            WaitKey(x) => format!("v{:x} = wait_key();", x),
+            SetDelay(x) => format!("delay = v{:x};", x),
+            SetSound(x) => format!("buzzer = v{:x};", x),
+            AddI(x) => format!("i += v{:x};", x),
+            LoadFont(x) => format!("i = hex(v{:x});", x),
+            Bcd(x) => format!("bcd(v{:x});", x),
+            Store(0) => String::from("save(v0);"),
+            Store(x) => format!("save(v0..v{:x});", x),
+            Load(0) => String::from("load(v0);"),
+            Load(x) => format!("load(v0..v{:x});", x),
+            skip => format!("{};", disasm::format_with(skip, Syntax::Cowgod, &operand)),
+        };
+        self.i = match instruction {
+            LoadI(nnn) => Some(operand(nnn)),
+            LoadFont(x) => Some(format!("hex(v{:x})", x)),
+            AddI(_) | Store(_) | Load(_) | Bcd(_) | Call(_) => None,
+            _ => self.i.take(),
+        };
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompile;
+    use crate::chip8::assembler::octo;
+    use crate::chip8::loader::bundled_roms;
+    use std::fs;
+
+    #[test]
+    fn structured_code() {
+        let source = "
+            : main
+              v0 := 0
+              loop
+                v0 += 1
+                if v0 == 5 begin
+                  i := sprite
+                  sprite v1 v2 5
+                else
+                  v1 := 2
+                end
+                while v0 != 10
+              again
+              if v1 key then v2 := 1
+              sub
+            : stop
+              jump stop
+            : sub
+              return
+            : sprite
+              0xF0 0x90
+        ";
+        let rom = octo::assemble(source).unwrap().rom;
+        let decompiled = decompile(&rom);
+        assert_eq!(
+            decompiled.to_pseudo_code(),
+            "\
+main(v1, v2) {  // writes v0, v1, v2, vf
+  v0 = 0x00;
+  loop {
+    v0 += 0x01;
+    if (v0 == 0x05) {
+      i = data_220;
+      vf = draw(v1, v2, data_220, 5);
+    } else {
+      v1 = 0x02;
+    }
+    if (v0 == 0x0A) break;
+  }
+  if (key_down(v1)) v2 = 0x01;
+  sub_21E();
+label_21C:
+  goto label_21C;
+}
+
+sub_21E() {
+  return;
+data_220:
+  bytes 0xF0, 0x90;
+}
+"
+        );
+        let octo_source = decompiled.to_octo();
+        assert!(octo_source.contains("\n    if v0 == 0x05 begin\n"));
+        assert!(octo_source.contains("\n    while v0 != 0x0A\n  again\n"));
+        assert_eq!(octo::assemble(&octo_source).unwrap().rom, rom);
+    }
+
+    #[test]
+    fn guarded_jumps() {
+        let source = "
+            : main
+              loop
+                v0 := delay
+                if v0 != 0 then again
+              if v1 == 1 then jump done
+              sub
+            : stop
+              jump stop
+            : sub
+              v2 := 1
+            : done
+              return
+        ";
+        let rom = octo::assemble(source).unwrap().rom;
+        let decompiled = decompile(&rom);
+        assert_eq!(
+            decompiled.to_pseudo_code(),
+            "\
+main(v1) {  // writes v0
+  do {
+    v0 = delay;
+  } while (v0 != 0x00);
+  if (v1 == 0x01) goto label_210;
+  sub_20E();
+label_20C:
+  goto label_20C;
+}
+
+sub_20E() {  // writes v2
+  v2 = 0x01;
+label_210:
+  return;
+}
+"
+        );
+        let octo_source = decompiled.to_octo();
+        assert!(octo_source.contains("\n    if v0 != 0x00 then again\n"));
+        assert_eq!(octo::assemble(&octo_source).unwrap().rom, rom);
+    }
+
+    #[test]
+    fn octo_output_reassembles() {
+        for path in bundled_roms() {
+            let rom = fs::read(&path).unwrap();
+            let source = decompile(&rom).to_octo();
+            assert_eq!(
+                octo::assemble(&source).unwrap().rom,
+                rom,
+                "{}",
+                path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! Instruction disassembly, turning raw words back into readable mnemonics
+//!
+//! Two syntaxes are supported: Cowgod's (`LD V3, 0x10`), used by the debugger and
+//! traces, and Octo's (`v3 := 0x10`). Roms can be listed word by word, or following the
+//! reachable code to tell it apart from the data between it.
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use super::flow::Flow;
+use super::instruction::Instruction;
+use super::memory::ROM_BASE_ADDR;
+
+/// Assembly syntax of the disassembled output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    #[default]
+    Cowgod,
+    Octo,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("Unknown syntax: {} (expected cowgod or octo)", s)),
+        }
+    }
+}
+
+/// Cowgod-style mnemonic for a raw instruction word (e.g. `LD V3, 0x10`)
+pub fn mnemonic(instruction: u16) -> String {
+    cowgod(Instruction::decode(instruction), &hex_addr)
+}
+
+/// Mnemonic for a raw instruction word in the given syntax
+pub fn format(instruction: u16, syntax: Syntax) -> String {
+    format_with(Instruction::decode(instruction), syntax, &hex_addr)
+}
+
+/// Mnemonic for a decoded instruction, its address operand formatted by addr
+pub fn format_with(
+    instruction: Instruction,
+    syntax: Syntax,
+    addr: &dyn Fn(u16) -> String,
+) -> String {
+    match syntax {
+        Syntax::Cowgod => cowgod(instruction, addr),
+        Syntax::Octo => octo(instruction, addr),
+    }
+}
+
+fn hex_addr(nnn: u16) -> String {
+    format!("0x{:03X}", nnn)
+}
+
+fn cowgod(instruction: Instruction, addr: &dyn Fn(u16) -> String) -> String {
+    use Instruction::*;
+    match instruction {
+        Cls => String::from("CLS"),
+        Ret => String::from("RET"),
+        Jump(nnn) => format!("JP {}", addr(nnn)),
+        Call(nnn) => format!("CALL {}", addr(nnn)),
+        SkipEqByte { x, kk } => format!("SE V{:X}, 0x{:02X}", x, kk),
+        SkipNeByte { x, kk } => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        SkipEqReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        LoadByte { x, kk } => format!("LD V{:X}, 0x{:02X}", x, kk),
+        AddByte { x, kk } => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        LoadReg { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        AddReg { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        SubN { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        SkipNeReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        LoadI(nnn) => format!("LD I, {}", addr(nnn)),
+        JumpV0(nnn) => format!("JP V0, {}", addr(nnn)),
+        Random { x, kk } => format!("RND V{:X}, 0x{:02X}", x, kk),
+        Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        SkipKey(x) => format!("SKP V{:X}", x),
+        SkipNotKey(x) => format!("SKNP V{:X}", x),
+        LoadDelay(x) => format!("LD V{:X}, DT", x),
+        WaitKey(x) => format!("LD V{:X}, K", x),
+        SetDelay(x) => format!("LD DT, V{:X}", x),
+        SetSound(x) => format!("LD ST, V{:X}", x),
+        AddI(x) => format!("ADD I, V{:X}", x),
+        LoadFont(x) => format!("LD F, V{:X}", x),
+        Bcd(x) => format!("LD B, V{:X}", x),
+        Store(x) => format!("LD [I], V{:X}", x),
+        Load(x) => format!("LD V{:X}, [I]", x),
+        // Words that don't decode to a supported instruction are shown as raw data
+        Data(word) => format!("DW 0x{:04X}", word),
+    }
+}
+
+// Octo skips are conditions on the *next* instruction running, hence the inversions
+fn octo(instruction: Instruction, addr: &dyn Fn(u16) -> String) -> String {
+    use Instruction::*;
+    match instruction {
+        Cls => String::from("clear"),
+        Ret => String::from("return"),
+        Jump(nnn) => format!("jump {}", addr(nnn)),
+        Call(nnn) => format!(":call {}", addr(nnn)),
+        SkipEqByte { x, kk } => format!("if v{:x} != 0x{:02X} then", x, kk),
+        SkipNeByte { x, kk } => format!("if v{:x} == 0x{:02X} then", x, kk),
+        SkipEqReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        LoadByte { x, kk } => format!("v{:x} := 0x{:02X}", x, kk),
+        AddByte { x, kk } => format!("v{:x} += 0x{:02X}", x, kk),
+        LoadReg { x, y } => format!("v{:x} := v{:x}", x, y),
+        Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+        Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        SubN { x, y } => format!("v{:x} =- v{:x}", x, y),
+        ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        SkipNeReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        LoadI(nnn) => format!("i := {}", addr(nnn)),
+        JumpV0(nnn) => format!("jump0 {}", addr(nnn)),
+        Random { x, kk } => format!("v{:x} := random 0x{:02X}", x, kk),
+        Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        SkipKey(x) => format!("if v{:x} -key then", x),
+        SkipNotKey(x) => format!("if v{:x} key then", x),
+        LoadDelay(x) => format!("v{:x} := delay", x),
+        WaitKey(x) => format!("v{:x} := key", x),
+        SetDelay(x) => format!("delay := v{:x}", x),
+        SetSound(x) => format!("buzzer := v{:x}", x),
+        AddI(x) => format!("i += v{:x}", x),
+        LoadFont(x) => format!("i := hex v{:x}", x),
+        Bcd(x) => format!("bcd v{:x}", x),
+        Store(x) => format!("save v{:x}", x),
+        Load(x) => format!("load v{:x}", x),
+        Data(word) => format!("0x{:02X} 0x{:02X}", word >> 8, word & 0xFF),
+    }
+}
+
+/// Disassembles a rom word by word from `ROM_BASE_ADDR`, one `addr: bytes  mnemonic` line each
+///
+/// Only words whose address is within range are listed, when one is given.
+pub fn disassemble(rom: &[u8], syntax: Syntax, range: Option<RangeInclusive<u16>>) -> String {
+    let mut out = String::new();
+    for (idx, bytes) in rom.chunks(2).enumerate() {
+        let addr = (ROM_BASE_ADDR + idx * 2) as u16;
+        if range.as_ref().is_some_and(|range| !range.contains(&addr)) {
+            continue;
+        }
+        let line = match *bytes {
+            [high, low] => format!(
+                "0x{:03X}: {:02X} {:02X}  {}",
+                addr,
+                high,
+                low,
+                format(u16::from_be_bytes([high, low]), syntax)
+            ),
+            // A trailing odd byte
+            [byte] => match syntax {
+                Syntax::Cowgod => format!("0x{:03X}: {:02X}     DB 0x{:02X}", addr, byte, byte),
+                Syntax::Octo => format!("0x{:03X}: {:02X}     0x{:02X}", addr, byte, byte),
+            },
+            _ => unreachable!("Chunks of 2 bytes"),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Disassembles the code reachable from `ROM_BASE_ADDR` as labelled source, the rest as data
+///
+/// Call targets get `sub_` labels, other jump targets `label_` ones and `Annn` pointers
+/// `data_` ones. Data is emitted one byte per line, with its bits drawn as a comment
+/// since it's mostly sprites. Only lines whose address is within range are listed.
+pub fn disassemble_recursive(
+    rom: &[u8],
+    syntax: Syntax,
+    range: Option<RangeInclusive<u16>>,
+) -> String {
+    let flow = Flow::explore(rom);
+    let lines = flow.lines();
+
+    // Only addresses starting a line can be labelled
+    let mut labels = BTreeMap::new();
+    for (addr, _) in &lines {
+        let name = if *addr == ROM_BASE_ADDR as u16 {
+            String::from("main")
+        } else if flow.calls.contains(addr) {
+            format!("sub_{:03X}", addr)
+        } else if flow.jumps.contains(addr) {
+            format!("label_{:03X}", addr)
+        } else if flow.pointers.contains(addr) {
+            format!("data_{:03X}", addr)
+        } else {
+            continue;
+        };
+        labels.insert(*addr, name);
+    }
+    let addr_operand = |nnn: u16| labels.get(&nnn).cloned().unwrap_or_else(|| hex_addr(nnn));
+
+    let mut out = String::new();
+    for (addr, instruction) in lines {
+        if range.as_ref().is_some_and(|range| !range.contains(&addr)) {
+            continue;
+        }
+        if let Some(label) = labels.get(&addr) {
+            match syntax {
+                Syntax::Cowgod => out.push_str(&format!("{}:\n", label)),
+                Syntax::Octo => out.push_str(&format!(": {}\n", label)),
+            }
+        }
+        let line = match instruction {
+            Some(instruction) => format_with(instruction, syntax, &addr_operand),
+            None => {
+                let byte = flow.byte(addr).expect("Data lines are within the rom");
+                let bits: String = (0..8)
+                    .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                    .collect();
+                match syntax {
+                    Syntax::Cowgod => format!("DB 0x{:02X}  ; {}", byte, bits),
+                    Syntax::Octo => format!(":byte 0x{:02X}  # {}", byte, bits),
+                }
+            }
+        };
+        out.push_str("  ");
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_recursive, format, mnemonic, Syntax};
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(mnemonic(0x00E0), "CLS");
+        assert_eq!(mnemonic(0x00EE), "RET");
+        assert_eq!(mnemonic(0x1228), "JP 0x228");
+        assert_eq!(mnemonic(0x6310), "LD V3, 0x10");
+        assert_eq!(mnemonic(0x8AB4), "ADD VA, VB");
+        assert_eq!(mnemonic(0xD015), "DRW V0, V1, 5");
+        assert_eq!(mnemonic(0xF355), "LD [I], V3");
+    }
+
+    #[test]
+    fn unknown_words_are_data() {
+        assert_eq!(mnemonic(0x0123), "DW 0x0123");
+        assert_eq!(mnemonic(0x8AB9), "DW 0x8AB9");
+        assert_eq!(mnemonic(0xF0FF), "DW 0xF0FF");
+    }
+
+    #[test]
+    fn octo_syntax() {
+        assert_eq!(format(0x6310, Syntax::Octo), "v3 := 0x10");
+        assert_eq!(format(0x3A05, Syntax::Octo), "if va != 0x05 then");
+        assert_eq!(format(0xE1A1, Syntax::Octo), "if v1 key then");
+        assert_eq!(format(0xD015, Syntax::Octo), "sprite v0 v1 5");
+        assert_eq!(format(0x2ABC, Syntax::Octo), ":call 0xABC");
+        assert_eq!(format(0xF0FF, Syntax::Octo), "0xF0 0xFF");
+        assert_eq!("OCTO".parse(), Ok(Syntax::Octo));
+        assert!("intel".parse::<Syntax>().is_err());
+    }
+
+    #[test]
+    fn listing() {
+        let rom = [0x63, 0x10, 0xA3, 0x00, 0x12];
+        assert_eq!(
+            disassemble(&rom, Syntax::Cowgod, None),
+            "0x200: 63 10  LD V3, 0x10\n0x202: A3 00  LD I, 0x300\n0x204: 12     DB 0x12\n"
+        );
+        assert_eq!(
+            disassemble(&rom, Syntax::Octo, Some(0x202..=0x203)),
+            "0x202: A3 00  i := 0x300\n"
+        );
+    }
+
+    #[test]
+    fn recursive_listing() {
+        // 0x200: CALL 0x208; LD I, 0x20C; JP 0x200; 0x206: unreachable
+        // 0x208: DRW V0, V1, 2; RET; 0x20C: sprite data
+        let rom = [
+            0x22, 0x08, 0xA2, 0x0C, 0x12, 0x00, 0x12, 0x34, 0xD0, 0x12, 0x00, 0xEE, 0xF0, 0x81,
+        ];
+        assert_eq!(
+            disassemble_recursive(&rom, Syntax::Octo, None),
+            "\
+: main
+  :call sub_208
+  i := data_20C
+  jump main
+  :byte 0x12  # ...#..#.
+  :byte 0x34  # ..##.#..
+: sub_208
+  sprite v0 v1 2
+  return
+: data_20C
+  :byte 0xF0  # ####....
+  :byte 0x81  # #......#
+"
+        );
+        let cowgod = disassemble_recursive(&rom, Syntax::Cowgod, Some(0x208..=0x20C));
+        assert_eq!(
+            cowgod,
+            "sub_208:\n  DRW V0, V1, 2\n  RET\ndata_20C:\n  DB 0xF0  ; ####....\n"
+        );
+    }
+}
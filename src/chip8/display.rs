@@ -9,6 +9,7 @@ pub const VRAM_HEIGHT: usize = 32;
 pub const SCREEN_WIDTH: usize = 640;
 pub const SCREEN_HEIGHT: usize = 320;
 pub const VRAM_DEFAULT: VramType = [[0; VRAM_WIDTH]; VRAM_HEIGHT];
+pub const DEFAULT_PALETTE: [u32; 2] = [0x0, 0xFFFF]; // Colours of unset and set pixels
 
 pub struct Sprite {
     pub data: Vec<u8>,
@@ -19,7 +20,7 @@ impl TryFrom<Vec<u8>> for Sprite {
     type Error = ();
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
         let len = value.len();
-        if len < 1 || len > SPRITE_MAX_SIZE {
+        if !(1..=SPRITE_MAX_SIZE).contains(&len) {
             return Err(());
         }
         Ok(Self { data: value, len })
@@ -36,7 +37,7 @@ impl Sprite {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Vram {
     arr: VramType,
 }
@@ -57,6 +58,10 @@ impl Vram {
     }
 
     pub fn to_screen_buffer(&self) -> Vec<u32> {
+        self.to_screen_buffer_with(DEFAULT_PALETTE)
+    }
+
+    pub fn to_screen_buffer_with(&self, palette: [u32; 2]) -> Vec<u32> {
         let mut buffer = vec![0; SCREEN_HEIGHT * SCREEN_WIDTH];
         for y in 0..SCREEN_HEIGHT {
             let y_vram_coord = y / 10;
@@ -64,8 +69,7 @@ impl Vram {
                 let x_vram_coord = x / 10;
                 let pixel = self.get_pixel(x_vram_coord, y_vram_coord).unwrap();
                 let color = match *pixel {
-                    0 => 0x0,
-                    1 => 0xFFFF,
+                    0 | 1 => palette[*pixel as usize],
                     _ => panic!("Unknown colour"),
                 };
                 buffer[y * SCREEN_WIDTH + x] = color;
@@ -79,7 +83,7 @@ impl Vram {
         if idx >= VRAM_WIDTH {
             return None;
         }
-        Some(&mut self.arr[idx])
+        Some(&self.arr[idx])
     }
 
     pub fn get_pixel(&self, x: usize, y: usize) -> Option<&u8> {
@@ -112,10 +116,8 @@ impl Vram {
         let mut collision = 0;
         for (i, line) in sprite.to_bytes_iter().enumerate() {
             for (j, bit) in bits_from_u8(*line).iter().enumerate() {
-                if *bit {
-                    if self.set_pixel(x + j, y + i) {
-                        collision+=1;
-                    }
+                if *bit && self.set_pixel(x + j, y + i) {
+                    collision += 1;
                 }
             }
         }
@@ -138,11 +140,7 @@ trait FromInteger {
 
 impl FromInteger for bool {
     fn from_u8(val: u8) -> bool {
-        if val > 0 {
-            true
-        } else {
-            false
-        }
+        val > 0
     }
 }
 
@@ -0,0 +1,167 @@
+//! Static control flow analysis of roms, finding which bytes are reachable code
+//!
+//! Code is explored recursively from `ROM_BASE_ADDR`, following jumps, calls, both
+//! outcomes of skips and falling through everything else, until `00EE`, a word that
+//! doesn't decode or the end of the rom. `Bnnn` computed jumps can't be resolved
+//! statically: only their base address is followed.
+use std::collections::BTreeSet;
+
+use super::instruction::Instruction;
+use super::memory::ROM_BASE_ADDR;
+
+/// Where an instruction may continue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Successor {
+    Next(u16),     // Falls through
+    Skip(u16),     // Taken skip, over the next instruction
+    Jump(u16),     // 1nnn
+    Call(u16),     // 2nnn, returning to the next instruction
+    Computed(u16), // Bnnn base address, the actual target depending on V0
+}
+
+impl Successor {
+    pub fn addr(&self) -> u16 {
+        match self {
+            Successor::Next(addr)
+            | Successor::Skip(addr)
+            | Successor::Jump(addr)
+            | Successor::Call(addr)
+            | Successor::Computed(addr) => *addr,
+        }
+    }
+}
+
+/// Successors of the instruction at pc, calls also listing their return address
+pub fn successors(pc: u16, instruction: Instruction) -> Vec<Successor> {
+    match instruction {
+        Instruction::Ret | Instruction::Data(_) => vec![],
+        Instruction::Jump(nnn) => vec![Successor::Jump(nnn)],
+        Instruction::JumpV0(nnn) => vec![Successor::Computed(nnn)],
+        Instruction::Call(nnn) => vec![Successor::Call(nnn), Successor::Next(pc + 2)],
+        skip if skip.is_skip() => vec![Successor::Next(pc + 2), Successor::Skip(pc + 4)],
+        _ => vec![Successor::Next(pc + 2)],
+    }
+}
+
+/// Reachable code of a rom and the addresses it refers to
+#[derive(Debug, Default)]
+pub struct Flow {
+    rom: Vec<u8>,
+    code: BTreeSet<u16>,         // Addresses of reachable instructions
+    pub calls: BTreeSet<u16>,    // Subroutine entry points
+    pub jumps: BTreeSet<u16>,    // Jump targets
+    pub pointers: BTreeSet<u16>, // Annn operands
+    pub computed: BTreeSet<u16>, // Addresses of Bnnn instructions
+}
+
+impl Flow {
+    /// Explores the rom from its first instruction
+    pub fn explore(rom: &[u8]) -> Self {
+        let mut flow = Flow {
+            rom: rom.to_vec(),
+            ..Default::default()
+        };
+        let mut pending = vec![ROM_BASE_ADDR as u16];
+        while let Some(pc) = pending.pop() {
+            let instruction = match flow.word(pc) {
+                Some(word) if !flow.code.contains(&pc) => Instruction::decode(word),
+                _ => continue,
+            };
+            if let Instruction::Data(_) = instruction {
+                continue; // Not code after all
+            }
+            flow.code.insert(pc);
+            match instruction {
+                Instruction::LoadI(nnn) => {
+                    flow.pointers.insert(nnn);
+                }
+                Instruction::JumpV0(_) => {
+                    flow.computed.insert(pc);
+                }
+                _ => (),
+            }
+            for successor in successors(pc, instruction) {
+                match successor {
+                    Successor::Call(addr) => flow.calls.insert(addr),
+                    Successor::Jump(addr) | Successor::Computed(addr) => flow.jumps.insert(addr),
+                    Successor::Next(_) | Successor::Skip(_) => false,
+                };
+                pending.push(successor.addr());
+            }
+        }
+        flow
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Address just past the rom
+    pub fn end(&self) -> u16 {
+        (ROM_BASE_ADDR + self.rom.len()) as u16
+    }
+
+    /// The word at addr, if it is entirely within the rom
+    pub fn word(&self, addr: u16) -> Option<u16> {
+        let offset = (addr as usize).checked_sub(ROM_BASE_ADDR)?;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn byte(&self, addr: u16) -> Option<u8> {
+        let offset = (addr as usize).checked_sub(ROM_BASE_ADDR)?;
+        self.rom.get(offset).copied()
+    }
+
+    /// Whether a reachable instruction starts at addr
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.code.contains(&addr)
+    }
+
+    /// Addresses of the reachable instructions, in order
+    pub fn code(&self) -> impl Iterator<Item = u16> + '_ {
+        self.code.iter().copied()
+    }
+
+    /// Where each line of a listing starts: an instruction, or a data byte (`None`)
+    pub fn lines(&self) -> Vec<(u16, Option<Instruction>)> {
+        let mut lines = Vec::new();
+        let mut addr = ROM_BASE_ADDR as u16;
+        while addr < self.end() {
+            match self.word(addr) {
+                Some(word) if self.is_code(addr) => {
+                    lines.push((addr, Some(Instruction::decode(word))));
+                    addr += 2;
+                }
+                _ => {
+                    lines.push((addr, None));
+                    addr += 1;
+                }
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Flow;
+
+    #[test]
+    fn explores_reachable_code() {
+        // 0x200: CALL 0x20A; SE V0, 1; JP 0x208; LD I, 0x20E; JP 0x200
+        // 0x20A: ADD V0, 1; RET; 0x20E: sprite data
+        let rom = [
+            0x22, 0x0A, 0x30, 0x01, 0x12, 0x08, 0xA2, 0x0E, 0x12, 0x00, 0x70, 0x01, 0x00, 0xEE,
+            0xF0, 0x90,
+        ];
+        let flow = Flow::explore(&rom);
+        let code: Vec<u16> = flow.code().collect();
+        assert_eq!(code, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x20C]);
+        assert!(!flow.is_code(0x20E));
+        assert!(flow.calls.contains(&0x20A));
+        assert!(flow.jumps.contains(&0x208) && !flow.jumps.contains(&0x206));
+        assert!(flow.pointers.contains(&0x20E));
+        assert_eq!(flow.end(), 0x210);
+    }
+}
@@ -82,4 +82,87 @@ pub const FONT_SET: [u8; FONT_UNIT_SIZE * FONTS_NUM] = [
     0xF0,
     0x80,
     0x80,
-];
\ No newline at end of file
+];
+
+pub const TEXT_GLYPH_WIDTH: usize = 3; // In pixels, stored in the high bits of each row
+pub const TEXT_GLYPH_HEIGHT: usize = 5; // In bytes, one per row
+const TEXT_FIRST_CHAR: u8 = b' ';
+const TEXT_GLYPHS_NUM: usize = 64; // ' ' to '_', lowercase letters are drawn in uppercase
+
+/// Glyphs used to draw text in the window (debug overlay), laid out like FONT_SET
+pub const TEXT_FONT: [u8; TEXT_GLYPH_HEIGHT * TEXT_GLYPHS_NUM] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, // space
+    0x40, 0x40, 0x40, 0x00, 0x40, // !
+    0xA0, 0xA0, 0x00, 0x00, 0x00, // "
+    0xA0, 0xE0, 0xA0, 0xE0, 0xA0, // #
+    0x60, 0xC0, 0x40, 0x60, 0xC0, // $
+    0x80, 0x20, 0x40, 0x80, 0x20, // %
+    0x40, 0xA0, 0x40, 0xA0, 0x60, // &
+    0x40, 0x40, 0x00, 0x00, 0x00, // '
+    0x20, 0x40, 0x40, 0x40, 0x20, // (
+    0x80, 0x40, 0x40, 0x40, 0x80, // )
+    0x00, 0xA0, 0x40, 0xA0, 0x00, // *
+    0x00, 0x40, 0xE0, 0x40, 0x00, // +
+    0x00, 0x00, 0x00, 0x40, 0x80, // ,
+    0x00, 0x00, 0xE0, 0x00, 0x00, // -
+    0x00, 0x00, 0x00, 0x00, 0x40, // .
+    0x20, 0x20, 0x40, 0x80, 0x80, // /
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0xC0, 0x40, 0x40, 0xE0, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0x60, 0x20, 0xE0, // 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x40, 0x40, 0x40, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0x00, 0x40, 0x00, 0x40, 0x00, // :
+    0x00, 0x40, 0x00, 0x40, 0x80, // ;
+    0x20, 0x40, 0x80, 0x40, 0x20, // <
+    0x00, 0xE0, 0x00, 0xE0, 0x00, // =
+    0x80, 0x40, 0x20, 0x40, 0x80, // >
+    0xE0, 0x20, 0x60, 0x00, 0x40, // ?
+    0x40, 0xA0, 0xE0, 0x80, 0x60, // @
+    0x40, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xC0, 0xA0, 0xC0, // B
+    0x60, 0x80, 0x80, 0x80, 0x60, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xC0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+    0x60, 0x80, 0xA0, 0xA0, 0x60, // G
+    0xA0, 0xA0, 0xE0, 0xA0, 0xA0, // H
+    0xE0, 0x40, 0x40, 0x40, 0xE0, // I
+    0x20, 0x20, 0x20, 0xA0, 0x40, // J
+    0xA0, 0xA0, 0xC0, 0xA0, 0xA0, // K
+    0x80, 0x80, 0x80, 0x80, 0xE0, // L
+    0xA0, 0xE0, 0xE0, 0xA0, 0xA0, // M
+    0xC0, 0xA0, 0xA0, 0xA0, 0xA0, // N
+    0x40, 0xA0, 0xA0, 0xA0, 0x40, // O
+    0xC0, 0xA0, 0xC0, 0x80, 0x80, // P
+    0x40, 0xA0, 0xA0, 0xC0, 0x60, // Q
+    0xC0, 0xA0, 0xC0, 0xA0, 0xA0, // R
+    0x60, 0x80, 0x40, 0x20, 0xC0, // S
+    0xE0, 0x40, 0x40, 0x40, 0x40, // T
+    0xA0, 0xA0, 0xA0, 0xA0, 0xE0, // U
+    0xA0, 0xA0, 0xA0, 0xA0, 0x40, // V
+    0xA0, 0xA0, 0xE0, 0xE0, 0xA0, // W
+    0xA0, 0xA0, 0x40, 0xA0, 0xA0, // X
+    0xA0, 0xA0, 0x40, 0x40, 0x40, // Y
+    0xE0, 0x20, 0x40, 0x80, 0xE0, // Z
+    0x60, 0x40, 0x40, 0x40, 0x60, // [
+    0x80, 0x80, 0x40, 0x20, 0x20, // \
+    0xC0, 0x40, 0x40, 0x40, 0xC0, // ]
+    0x40, 0xA0, 0x00, 0x00, 0x00, // ^
+    0x00, 0x00, 0x00, 0x00, 0xE0, // _
+];
+
+/// Rows of the glyph drawing c, characters without a glyph being drawn as '?'
+pub fn text_glyph(c: char) -> &'static [u8] {
+    let code = c.to_ascii_uppercase() as u32;
+    let idx = match code.checked_sub(TEXT_FIRST_CHAR as u32) {
+        Some(idx) if (idx as usize) < TEXT_GLYPHS_NUM => idx as usize,
+        _ => (b'?' - TEXT_FIRST_CHAR) as usize,
+    };
+    &TEXT_FONT[idx * TEXT_GLYPH_HEIGHT..(idx + 1) * TEXT_GLYPH_HEIGHT]
+}
@@ -0,0 +1,543 @@
+//! GDB remote serial protocol stub, letting standard debugger front-ends drive the interpreter
+//!
+//! The register file exposed to the client is, in order: V0-VF (8 bits each),
+//! I and PC (16 bits, little endian), SP (call depth), DT and ST (8 bits each).
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use super::debugger::{Debugger, Stop};
+use super::memory::{Access, WatchKind, RAM_SIZE};
+use super::Interpreter;
+
+const REGS_NUM: usize = 21; // V0-VF, I, PC, SP, DT, ST
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+
+const PACKET_SIZE: usize = 0x1000;
+const INTERRUPT: u8 = 0x03; // Sent raw (outside of a packet) by the client to stop a running target
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Target description sent to the client, mapping the CHIP-8 register file
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chipper.chip8">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// State of the debugging session after handling client traffic
+#[derive(Debug, PartialEq)]
+pub enum Session {
+    Attached,
+    Detached,
+    Killed,
+}
+
+/// A single client connection, driving an `Interpreter` through a `Debugger`
+pub struct GdbStub {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    debugger: Debugger,
+    // A continue or step is pending, the client waits for a stop reply
+    waiting_stop: bool,
+}
+
+impl GdbStub {
+    /// Waits for a client to connect on the listener; the target starts stopped
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            buffer: Vec::new(),
+            debugger: Debugger::new(),
+            waiting_stop: false,
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.debugger.is_paused()
+    }
+
+    /// Handles every packet the client has sent so far, without blocking
+    pub fn poll(&mut self, chip8: &mut Interpreter) -> io::Result<Session> {
+        let mut chunk = [0; 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(Session::Detached), // Connection closed
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            let session = self.handle(&packet, chip8)?;
+            if session != Session::Attached {
+                return Ok(session);
+            }
+        }
+        Ok(Session::Attached)
+    }
+
+    /// Runs one instruction when the client asked to continue, reporting the stop if any
+    pub fn tick(&mut self, chip8: &mut Interpreter) -> io::Result<()> {
+        if let Some(stop) = self.debugger.tick(chip8) {
+            self.report_stop(&stop, chip8)?;
+        }
+        Ok(())
+    }
+
+    /// Serves the client until it detaches or kills the target, for headless sessions
+    pub fn serve(&mut self, chip8: &mut Interpreter) -> io::Result<Session> {
+        loop {
+            let session = self.poll(chip8)?;
+            if session != Session::Attached {
+                return Ok(session);
+            }
+            if self.is_running() {
+                self.tick(chip8)?;
+            } else {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    // Pops the next complete packet out of the buffer, acknowledging it
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.buffer.first() {
+                None => return Ok(None),
+                Some(&INTERRUPT) => {
+                    self.buffer.remove(0);
+                    return Ok(Some(String::from("\x03")));
+                }
+                Some(b'$') => break,
+                Some(_) => {
+                    self.buffer.remove(0); // Acks and line noise
+                }
+            }
+        }
+        let end = match self.buffer.iter().position(|byte| *byte == b'#') {
+            Some(end) if end + 2 < self.buffer.len() => end,
+            _ => return Ok(None), // Incomplete packet
+        };
+        let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if checksum != Some(checksum_of(data)) {
+            self.stream_write(b"-")?; // Asks for a retransmission
+            return self.next_packet();
+        }
+        self.stream_write(b"+")?;
+        Ok(Some(String::from_utf8_lossy(data).into_owned()))
+    }
+
+    fn handle(&mut self, packet: &str, chip8: &mut Interpreter) -> io::Result<Session> {
+        let reply = match packet.as_bytes().first() {
+            Some(&INTERRUPT) => {
+                self.debugger.pause();
+                if self.waiting_stop {
+                    self.waiting_stop = false;
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                }
+                return Ok(Session::Attached);
+            }
+            Some(b'?') => format!("S{:02x}", SIGTRAP),
+            Some(b'g') => encode_hex(&read_registers(chip8)),
+            Some(b'G') => match decode_hex(&packet[1..]) {
+                Some(bytes) if bytes.len() == register_file_len() => {
+                    write_registers(chip8, &bytes);
+                    String::from("OK")
+                }
+                _ => error(),
+            },
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(reg) if reg < REGS_NUM => encode_hex(&read_register(chip8, reg)),
+                _ => error(),
+            },
+            Some(b'P') => self.write_register_packet(&packet[1..], chip8),
+            Some(b'm') => read_memory(&packet[1..], chip8).unwrap_or_else(error),
+            Some(b'M') => match write_memory(&packet[1..], chip8) {
+                Some(()) => String::from("OK"),
+                None => error(),
+            },
+            Some(b'Z') | Some(b'z') => self.breakpoint_packet(packet, chip8),
+            Some(b'c') => {
+                if let Some(addr) = parse_addr(&packet[1..]) {
+                    chip8.cpu.registers_mut().pc = addr;
+                }
+                self.debugger.resume(chip8);
+                self.waiting_stop = true;
+                return Ok(Session::Attached); // The reply is the stop packet
+            }
+            Some(b's') => {
+                if let Some(addr) = parse_addr(&packet[1..]) {
+                    chip8.cpu.registers_mut().pc = addr;
+                }
+                self.waiting_stop = true;
+                // A completed step is reported as a trap, like a breakpoint
+                let stop = self
+                    .debugger
+                    .step(chip8)
+                    .unwrap_or(Stop::Breakpoint(chip8.cpu.registers().pc));
+                self.report_stop(&stop, chip8)?;
+                return Ok(Session::Attached);
+            }
+            Some(b'D') => {
+                self.send("OK")?;
+                return Ok(Session::Detached);
+            }
+            Some(b'k') => return Ok(Session::Killed),
+            Some(b'H') => String::from("OK"),
+            Some(b'q') | Some(b'Q') => query(packet),
+            Some(b'v') if packet.starts_with("vKill") => {
+                self.send("OK")?;
+                return Ok(Session::Killed);
+            }
+            _ => String::new(), // Unsupported packets get an empty reply
+        };
+        self.send(&reply)?;
+        Ok(Session::Attached)
+    }
+
+    fn write_register_packet(&mut self, args: &str, chip8: &mut Interpreter) -> String {
+        let parsed = args.split_once('=').and_then(|(reg, value)| {
+            let reg = usize::from_str_radix(reg, 16).ok()?;
+            let value = decode_hex(value)?;
+            (reg < REGS_NUM && value.len() == register_len(reg)).then_some((reg, value))
+        });
+        match parsed {
+            Some((reg, value)) => {
+                write_register(chip8, reg, &value);
+                String::from("OK")
+            }
+            None => error(),
+        }
+    }
+
+    // Z0/Z1 (software and hardware breakpoints) are both handled by the debugger,
+    // Z2/Z3/Z4 (write, read and access watchpoints) by the memory
+    fn breakpoint_packet(&mut self, packet: &str, chip8: &mut Interpreter) -> String {
+        let mut fields = packet[1..].split(',');
+        let kind = fields.next();
+        let addr = fields
+            .next()
+            .and_then(|addr| usize::from_str_radix(addr, 16).ok());
+        let len = fields
+            .next()
+            .and_then(|len| usize::from_str_radix(len, 16).ok());
+        let insert = packet.starts_with('Z');
+        let watch_kind = match kind {
+            Some("0") | Some("1") => None,
+            Some("2") => Some(WatchKind::Write),
+            Some("3") => Some(WatchKind::Read),
+            Some("4") => Some(WatchKind::Access),
+            _ => return String::new(),
+        };
+        let (addr, len) = match (addr, len) {
+            (Some(addr), Some(len)) if addr.checked_add(len).is_some_and(|end| end <= RAM_SIZE) => {
+                (addr, len)
+            }
+            _ => return error(),
+        };
+        match (watch_kind, insert) {
+            (None, true) => self.debugger.set_breakpoint(addr as u16),
+            (None, false) => {
+                self.debugger.delete_breakpoint(addr as u16);
+            }
+            (Some(kind), true) => chip8.cpu.mem_mut().add_watchpoint(addr..addr + len, kind),
+            (Some(kind), false) => {
+                chip8
+                    .cpu
+                    .mem_mut()
+                    .remove_watchpoint(addr..addr + len, kind);
+            }
+        }
+        String::from("OK")
+    }
+
+    fn report_stop(&mut self, stop: &Stop, chip8: &Interpreter) -> io::Result<()> {
+        if !self.waiting_stop {
+            return Ok(());
+        }
+        self.waiting_stop = false;
+        let reply = match stop {
+            Stop::Breakpoint(_) => format!("S{:02x}", SIGTRAP),
+            Stop::Watchpoint { addr, kind, .. } => format!(
+                "T{:02x}{}:{:x};",
+                SIGTRAP,
+                match watch_kind(chip8, *addr, *kind) {
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Write => "watch",
+                    WatchKind::Access => "awatch",
+                },
+                addr
+            ),
+            Stop::Error(_) => format!("S{:02x}", SIGILL),
+            Stop::Finished => String::from("W00"),
+        };
+        self.send(&reply)
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream_write(packet.as_bytes())
+    }
+
+    // The stream is non-blocking for reads, so writes may have to be retried
+    fn stream_write(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match self.stream.write(bytes) {
+                Ok(len) => bytes = &bytes[len..],
+                Err(err) if err.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        self.stream.flush()
+    }
+}
+
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+    }
+    if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        return match args.split_once(',').and_then(|(off, len)| {
+            Some((
+                usize::from_str_radix(off, 16).ok()?,
+                usize::from_str_radix(len, 16).ok()?,
+            ))
+        }) {
+            Some((off, len)) => {
+                let xml = TARGET_XML.as_bytes();
+                let start = off.min(xml.len());
+                let end = off.saturating_add(len).min(xml.len());
+                let prefix = if end == xml.len() { 'l' } else { 'm' };
+                format!("{}{}", prefix, String::from_utf8_lossy(&xml[start..end]))
+            }
+            None => error(),
+        };
+    }
+    match packet {
+        "qAttached" => String::from("1"),
+        "qC" => String::from("QC1"),
+        "qfThreadInfo" => String::from("m1"),
+        "qsThreadInfo" => String::from("l"),
+        "qSymbol::" => String::from("OK"),
+        _ => String::new(),
+    }
+}
+
+// Kind the hit watchpoint was set with, one watching only this kind of access first
+fn watch_kind(chip8: &Interpreter, addr: u16, access: Access) -> WatchKind {
+    let watched = |kind: WatchKind| {
+        chip8.cpu.mem().watchpoints().iter().any(|watchpoint| {
+            watchpoint.kind == kind && watchpoint.range.contains(&(addr as usize))
+        })
+    };
+    let kind = match access {
+        Access::Read => WatchKind::Read,
+        Access::Write => WatchKind::Write,
+    };
+    if watched(kind) {
+        kind
+    } else {
+        WatchKind::Access
+    }
+}
+
+fn error() -> String {
+    String::from("E01")
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_addr(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    // Client supplied, addr + len may not even fit in a usize
+    addr.checked_add(len)
+        .is_some_and(|end| end <= RAM_SIZE)
+        .then_some((addr, len))
+}
+
+fn read_memory(args: &str, chip8: &Interpreter) -> Option<String> {
+    let (addr, len) = parse_range(args)?;
+    let bytes = chip8.cpu.mem().read_segment(len, addr)?;
+    Some(encode_hex(&bytes))
+}
+
+fn write_memory(args: &str, chip8: &mut Interpreter) -> Option<()> {
+    let (range, data) = args.split_once(':')?;
+    let (addr, len) = parse_range(range)?;
+    let bytes = decode_hex(data)?;
+    if bytes.len() != len {
+        return None;
+    }
+    for (off, byte) in bytes.iter().enumerate() {
+        chip8.cpu.mem_mut().write_byte(addr + off, *byte);
+    }
+    Some(())
+}
+
+fn register_len(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn register_file_len() -> usize {
+    (0..REGS_NUM).map(register_len).sum()
+}
+
+fn read_register(chip8: &Interpreter, reg: usize) -> Vec<u8> {
+    let registers = chip8.cpu.registers();
+    match reg {
+        REG_I => registers.i.to_le_bytes().to_vec(),
+        REG_PC => registers.pc.to_le_bytes().to_vec(),
+        REG_SP => vec![chip8.cpu.stack().len() as u8],
+        REG_DT => vec![registers.dt],
+        REG_ST => vec![registers.st],
+        _ => vec![registers.v[reg]],
+    }
+}
+
+fn read_registers(chip8: &Interpreter) -> Vec<u8> {
+    (0..REGS_NUM)
+        .flat_map(|reg| read_register(chip8, reg))
+        .collect()
+}
+
+// SP mirrors the call depth and can't be written
+fn write_register(chip8: &mut Interpreter, reg: usize, value: &[u8]) {
+    let registers = chip8.cpu.registers_mut();
+    match reg {
+        REG_I => registers.i = u16::from_le_bytes([value[0], value[1]]),
+        REG_PC => registers.pc = u16::from_le_bytes([value[0], value[1]]),
+        REG_SP => (),
+        REG_DT => registers.dt = value[0],
+        REG_ST => registers.st = value[0],
+        _ => registers.v[reg] = value[0],
+    }
+}
+
+fn write_registers(chip8: &mut Interpreter, bytes: &[u8]) {
+    let mut off = 0;
+    for reg in 0..REGS_NUM {
+        let len = register_len(reg);
+        write_register(chip8, reg, &bytes[off..off + len]);
+        off += len;
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(s.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        checksum_of, decode_hex, encode_hex, parse_range, read_registers, register_file_len,
+        watch_kind,
+    };
+    use crate::chip8::memory::{Access, WatchKind};
+    use crate::chip8::Interpreter;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(checksum_of(b"OK"), 0x9a);
+        assert_eq!(checksum_of(b""), 0);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = vec![0x00, 0x12, 0xAB, 0xFF];
+        assert_eq!(encode_hex(&bytes), "0012abff");
+        assert_eq!(decode_hex("0012abff"), Some(bytes));
+        assert_eq!(decode_hex("123"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn memory_ranges() {
+        assert_eq!(parse_range("200,2"), Some((0x200, 2)));
+        assert_eq!(parse_range("fff,1"), Some((0xFFF, 1)));
+        assert_eq!(parse_range("fff,2"), None);
+        assert_eq!(parse_range("ffffffffffffffff,2"), None);
+        assert_eq!(parse_range("200"), None);
+    }
+
+    #[test]
+    fn watchpoint_kinds() {
+        let mut chip8 = Interpreter::new();
+        let mem = chip8.cpu.mem_mut();
+        mem.add_watchpoint(0x300..0x302, WatchKind::Access);
+        mem.add_watchpoint(0x301..0x302, WatchKind::Write);
+        assert_eq!(watch_kind(&chip8, 0x300, Access::Read), WatchKind::Access);
+        assert_eq!(watch_kind(&chip8, 0x300, Access::Write), WatchKind::Access);
+        assert_eq!(watch_kind(&chip8, 0x301, Access::Write), WatchKind::Write);
+    }
+
+    #[test]
+    fn register_file() {
+        let chip8 = Interpreter::new();
+        let registers = read_registers(&chip8);
+        assert_eq!(registers.len(), register_file_len());
+        assert_eq!(registers[18..20], [0x00, 0x02]); // Little endian PC = 0x200
+    }
+}
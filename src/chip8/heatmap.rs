@@ -0,0 +1,165 @@
+//! Heatmap of ram accesses, one cell per byte on a 64x64 grid
+//!
+//! Reads are drawn in blue, writes in red and executed bytes in green. The live map
+//! fades as `decay` gets called (once per frame), while the session map accumulates
+//! counts over the whole run and can be exported as a PPM image.
+use std::io::{self, Write};
+
+use super::cpu::CPU;
+use super::memory::{Access, RAM_SIZE};
+
+pub const HEATMAP_SIDE: usize = 64; // Cells per row and column, 64 * 64 = RAM_SIZE
+const DECAY: f32 = 0.97; // Intensity kept at each frame
+const CHANNELS: usize = 3;
+const READ: usize = 0;
+const WRITE: usize = 1;
+const EXECUTE: usize = 2;
+
+pub struct Heatmap {
+    live: Vec<[f32; CHANNELS]>,    // Intensities between 0 and 1
+    session: Vec<[u64; CHANNELS]>, // Access counts
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self {
+            live: vec![[0.0; CHANNELS]; RAM_SIZE],
+            session: vec![[0; CHANNELS]; RAM_SIZE],
+        }
+    }
+
+    fn hit(&mut self, start: usize, len: usize, channel: usize) {
+        for addr in (start..start + len).take_while(|addr| *addr < RAM_SIZE) {
+            self.live[addr][channel] = 1.0;
+            self.session[addr][channel] += 1;
+        }
+    }
+
+    /// Records the accesses of the instruction the cpu is about to execute
+    pub fn record(&mut self, cpu: &CPU) {
+        let pc = cpu.registers().pc;
+        let instruction = match cpu.fetch(pc) {
+            Some(instruction) => instruction,
+            None => return, // Nothing gets executed
+        };
+        self.hit(pc as usize, 2, EXECUTE);
+        match cpu.data_access(instruction) {
+            Some((addr, len, Access::Read)) => self.hit(addr, len, READ),
+            Some((addr, len, Access::Write)) => self.hit(addr, len, WRITE),
+            None => (),
+        }
+    }
+
+    /// Fades the live map, to be called once per frame
+    pub fn decay(&mut self) {
+        for cell in self.live.iter_mut() {
+            for intensity in cell.iter_mut() {
+                *intensity *= DECAY;
+            }
+        }
+    }
+
+    /// Live map as a window buffer, each cell being scale x scale pixels
+    pub fn to_buffer(&self, scale: usize) -> Vec<u32> {
+        let colours: Vec<u32> = self
+            .live
+            .iter()
+            .map(|cell| rgb(cell.map(|intensity| (intensity * 255.0) as u8)))
+            .collect();
+        scale_cells(&colours, scale)
+    }
+
+    // Counts scaled logarithmically against the maximum of their channel
+    fn session_colours(&self) -> Vec<[u8; CHANNELS]> {
+        let mut max = [0u64; CHANNELS];
+        for cell in &self.session {
+            for channel in 0..CHANNELS {
+                max[channel] = max[channel].max(cell[channel]);
+            }
+        }
+        self.session
+            .iter()
+            .map(|cell| {
+                let mut colour = [0u8; CHANNELS];
+                for channel in 0..CHANNELS {
+                    if cell[channel] > 0 {
+                        let level = (cell[channel] as f64).ln_1p() / (max[channel] as f64).ln_1p();
+                        colour[channel] = (64.0 + level * 191.0) as u8; // Any access stays visible
+                    }
+                }
+                colour
+            })
+            .collect()
+    }
+
+    /// Writes the session map as a binary PPM image, each cell being scale x scale pixels
+    pub fn write_ppm<W: Write>(&self, out: &mut W, scale: usize) -> io::Result<()> {
+        let side = HEATMAP_SIDE * scale;
+        write!(out, "P6\n{} {}\n255\n", side, side)?;
+        let colours = self.session_colours();
+        for y in 0..side {
+            for x in 0..side {
+                let [read, write, execute] = colours[(y / scale) * HEATMAP_SIDE + x / scale];
+                out.write_all(&[write, execute, read])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Read, write and execute intensities to minifb's 0RGB
+fn rgb([read, write, execute]: [u8; CHANNELS]) -> u32 {
+    ((write as u32) << 16) | ((execute as u32) << 8) | read as u32
+}
+
+fn scale_cells(colours: &[u32], scale: usize) -> Vec<u32> {
+    let side = HEATMAP_SIDE * scale;
+    let mut buffer = vec![0; side * side];
+    for (idx, pixel) in buffer.iter_mut().enumerate() {
+        let (x, y) = (idx % side / scale, idx / side / scale);
+        *pixel = colours[y * HEATMAP_SIDE + x];
+    }
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heatmap, HEATMAP_SIDE};
+    use crate::chip8::cpu::CPU;
+    use crate::chip8::input::KeyBoard;
+    use crate::chip8::memory::{Mem, RAM_SIZE};
+
+    #[test]
+    fn live_and_session_maps() {
+        // LD I, 0x300; LD [I], V0
+        let kb = KeyBoard::new();
+        let mut cpu = CPU::new(Mem::new(vec![0xA3, 0x00, 0xF0, 0x55]));
+        let mut heatmap = Heatmap::new();
+        for _ in 0..2 {
+            heatmap.record(&cpu);
+            cpu.tick(&kb);
+        }
+        let buffer = heatmap.to_buffer(2);
+        assert_eq!(buffer.len(), (HEATMAP_SIDE * 2).pow(2));
+        // 0x200 is the first cell of row 8, 0x300 of row 12
+        assert_eq!(buffer[8 * 2 * HEATMAP_SIDE * 2], 0x00FF00);
+        assert_eq!(buffer[12 * 2 * HEATMAP_SIDE * 2 + 1], 0xFF0000);
+        heatmap.decay();
+        assert!(heatmap.to_buffer(1)[0x300] < 0xFF0000);
+
+        let mut image = Vec::new();
+        heatmap.write_ppm(&mut image, 1).unwrap();
+        let header = b"P6\n64 64\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(image.len(), header.len() + RAM_SIZE * 3);
+        let pixel = |addr: usize| &image[header.len() + addr * 3..header.len() + addr * 3 + 3];
+        assert_eq!(pixel(0x300), [255, 0, 0]);
+        assert_eq!(pixel(0x400), [0, 0, 0]);
+    }
+}
@@ -6,6 +6,12 @@ pub struct KeyBoard {
     key: Option<u8>,
 }
 
+impl Default for KeyBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KeyBoard {
     pub fn new() -> Self {
         KeyBoard { key: None }
@@ -34,35 +40,49 @@ impl KeyBoard {
     }
 }
 
+/// Keyboard key of each hexadecimal key, laid out for a french (azerty) keyboard
+const KEYMAP: [(Key, u8); 16] = [
+    (Key::Y, 0x1),
+    (Key::U, 0x2),
+    (Key::I, 0x3),
+    (Key::O, 0xC),
+
+    (Key::A, 0x4),
+    (Key::Z, 0x5),
+    (Key::E, 0x6),
+    (Key::R, 0xD),
+
+    (Key::Q, 0x7),
+    (Key::S, 0x8),
+    (Key::D, 0x9),
+    (Key::F, 0xE),
+
+    (Key::W, 0xA),
+    (Key::X, 0x0),
+    (Key::C, 0xB),
+    (Key::V, 0xF),
+];
+
 pub fn get_key_opcode(key: Option<Key>) -> Option<u8> {
-    match key {
-        Some(Key::Y) => Some(0x1),
-        Some(Key::U) => Some(0x2),
-        Some(Key::I) => Some(0x3),
-        Some(Key::O) => Some(0xC),
-
-        Some(Key::A) => Some(0x4),
-        Some(Key::Z) => Some(0x5),
-        Some(Key::E) => Some(0x6),
-        Some(Key::R) => Some(0xD),
-
-        Some(Key::Q) => Some(0x7),
-        Some(Key::S) => Some(0x8),
-        Some(Key::D) => Some(0x9),
-        Some(Key::F) => Some(0xE),
-
-        Some(Key::W) => Some(0xA),
-        Some(Key::X) => Some(0x0),
-        Some(Key::C) => Some(0xB),
-        Some(Key::V) => Some(0xF),
-        _ => None,
-    }
+    let key = key?;
+    KEYMAP
+        .iter()
+        .find(|(mapped, _)| *mapped == key)
+        .map(|(_, opcode)| *opcode)
+}
 
+/// Keyboard key pressed for a hexadecimal key
+pub fn keyboard_key(opcode: u8) -> Option<Key> {
+    KEYMAP
+        .iter()
+        .find(|(_, mapped)| *mapped == opcode)
+        .map(|(key, _)| *key)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::KeyBoard;
+    use super::{get_key_opcode, keyboard_key, KeyBoard};
+    use minifb::Key;
     
     #[test]
     fn keyboard_tests() {
@@ -76,4 +96,12 @@ mod tests {
         assert!(!kb.is_key_up(3));
         assert!(kb.is_key_up(4));
     }
+
+    #[test]
+    fn keymap() {
+        assert_eq!(get_key_opcode(Some(Key::Z)), Some(0x5));
+        assert_eq!(get_key_opcode(Some(Key::P)), None);
+        assert_eq!(keyboard_key(0x5), Some(Key::Z));
+        assert_eq!(keyboard_key(0x10), None);
+    }
 }
\ No newline at end of file
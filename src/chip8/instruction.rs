@@ -0,0 +1,140 @@
+//! Instruction decoding, from raw words to a typed representation
+//!
+//! Register operands are indexes (0x0 to 0xF) into V0-VF.
+
+/// A decoded CHIP-8 instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Cls,                          // 00E0
+    Ret,                          // 00EE
+    Jump(u16),                    // 1nnn
+    Call(u16),                    // 2nnn
+    SkipEqByte { x: u8, kk: u8 }, // 3xkk
+    SkipNeByte { x: u8, kk: u8 }, // 4xkk
+    SkipEqReg { x: u8, y: u8 },   // 5xy0
+    LoadByte { x: u8, kk: u8 },   // 6xkk
+    AddByte { x: u8, kk: u8 },    // 7xkk
+    LoadReg { x: u8, y: u8 },     // 8xy0
+    Or { x: u8, y: u8 },          // 8xy1
+    And { x: u8, y: u8 },         // 8xy2
+    Xor { x: u8, y: u8 },         // 8xy3
+    AddReg { x: u8, y: u8 },      // 8xy4
+    Sub { x: u8, y: u8 },         // 8xy5
+    ShiftRight { x: u8, y: u8 },  // 8xy6
+    SubN { x: u8, y: u8 },        // 8xy7
+    ShiftLeft { x: u8, y: u8 },   // 8xyE
+    SkipNeReg { x: u8, y: u8 },   // 9xy0
+    LoadI(u16),                   // Annn
+    JumpV0(u16),                  // Bnnn
+    Random { x: u8, kk: u8 },     // Cxkk
+    Draw { x: u8, y: u8, n: u8 }, // Dxyn
+    SkipKey(u8),                  // Ex9E
+    SkipNotKey(u8),               // ExA1
+    LoadDelay(u8),                // Fx07
+    WaitKey(u8),                  // Fx0A
+    SetDelay(u8),                 // Fx15
+    SetSound(u8),                 // Fx18
+    AddI(u8),                     // Fx1E
+    LoadFont(u8),                 // Fx29
+    Bcd(u8),                      // Fx33
+    Store(u8),                    // Fx55
+    Load(u8),                     // Fx65
+    Data(u16),                    // Any word that isn't a supported instruction
+}
+
+impl Instruction {
+    pub fn decode(word: u16) -> Self {
+        let nnn = word & 0x0FFF;
+        let kk = (word & 0x00FF) as u8;
+        let n = (word & 0x000F) as u8;
+        let x = ((word & 0x0F00) >> 8) as u8;
+        let y = ((word & 0x00F0) >> 4) as u8;
+
+        match (word & 0xF000) >> 12 {
+            0x0 => match nnn {
+                0x0E0 => Instruction::Cls,
+                0x0EE => Instruction::Ret,
+                _ => Instruction::Data(word),
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipEqByte { x, kk },
+            0x4 => Instruction::SkipNeByte { x, kk },
+            0x5 if n == 0 => Instruction::SkipEqReg { x, y },
+            0x6 => Instruction::LoadByte { x, kk },
+            0x7 => Instruction::AddByte { x, kk },
+            0x8 => match n {
+                0x0 => Instruction::LoadReg { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::AddReg { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::ShiftRight { x, y },
+                0x7 => Instruction::SubN { x, y },
+                0xE => Instruction::ShiftLeft { x, y },
+                _ => Instruction::Data(word),
+            },
+            0x9 if n == 0 => Instruction::SkipNeReg { x, y },
+            0xA => Instruction::LoadI(nnn),
+            0xB => Instruction::JumpV0(nnn),
+            0xC => Instruction::Random { x, kk },
+            0xD => Instruction::Draw { x, y, n },
+            0xE => match kk {
+                0x9E => Instruction::SkipKey(x),
+                0xA1 => Instruction::SkipNotKey(x),
+                _ => Instruction::Data(word),
+            },
+            0xF => match kk {
+                0x07 => Instruction::LoadDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::LoadFont(x),
+                0x33 => Instruction::Bcd(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Load(x),
+                _ => Instruction::Data(word),
+            },
+            _ => Instruction::Data(word),
+        }
+    }
+
+    /// Whether the instruction may skip the next one
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipEqByte { .. }
+                | Instruction::SkipNeByte { .. }
+                | Instruction::SkipEqReg { .. }
+                | Instruction::SkipNeReg { .. }
+                | Instruction::SkipKey(_)
+                | Instruction::SkipNotKey(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction;
+
+    #[test]
+    fn decoding() {
+        assert_eq!(Instruction::decode(0x00E0), Instruction::Cls);
+        assert_eq!(Instruction::decode(0x2ABC), Instruction::Call(0xABC));
+        assert_eq!(
+            Instruction::decode(0x8AB7),
+            Instruction::SubN { x: 0xA, y: 0xB }
+        );
+        assert_eq!(
+            Instruction::decode(0xD125),
+            Instruction::Draw { x: 1, y: 2, n: 5 }
+        );
+        assert_eq!(Instruction::decode(0xF365), Instruction::Load(3));
+        assert!(Instruction::decode(0xE19E).is_skip());
+        for word in [0x0123, 0x5121, 0x8AB9, 0x9AB1, 0xE1FF, 0xF0FF] {
+            assert_eq!(Instruction::decode(word), Instruction::Data(word));
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Rom selection menu, shown in the window when chipper is started on a directory
+//!
+//! Files are listed by their title in the rom database, by file name otherwise, and
+//! drawn with the glyphs of `font::TEXT_FONT` like the debug overlay.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use minifb::Key;
+
+use super::loader;
+use super::memory::MAX_ROM_SIZE;
+use super::overlay::{
+    Canvas, BACKGROUND, CHAR_WIDTH, HIGHLIGHT_BAR, HIGHLIGHT_TEXT, LINE_HEIGHT, TEXT, TITLE,
+};
+use super::romdb::RomDb;
+
+const MARGIN: usize = 10;
+const HELP: &str = "UP/DOWN: SELECT  ENTER: PLAY  BACKSPACE: BACK TO MENU  ESC: QUIT";
+
+/// A rom of the menu
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub path: PathBuf,
+    pub title: String,
+}
+
+/// Roms of a directory, one of them selected
+#[derive(Debug)]
+pub struct Launcher {
+    dir: PathBuf,
+    items: Vec<Item>,
+    selected: usize,
+    status: String, // Shown under the list, e.g. why a rom didn't start
+}
+
+impl Launcher {
+    pub fn new(dir: &Path, items: Vec<Item>) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            items,
+            selected: 0,
+            status: String::new(),
+        }
+    }
+
+    /// Lists the files of dir which may be roms, and the roms of its zip archives, by title
+    pub fn scan(dir: &Path, db: &RomDb) -> Result<Self, String> {
+        let entries =
+            fs::read_dir(dir).map_err(|err| format!("Couldn't list {}: {}", dir.display(), err))?;
+        let mut items = Vec::new();
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let len = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            if !path.is_file() {
+                continue;
+            }
+            if loader::is_zip(&path) {
+                for (name, rom) in loader::read_zip_roms(&path).unwrap_or_default() {
+                    let title =
+                        known_title(&rom, db).unwrap_or_else(|| format!("{}:{}", file_name, name));
+                    let path = PathBuf::from(format!("{}:{}", path.display(), name));
+                    items.push(Item { path, title });
+                }
+            } else if len > 0 && len <= MAX_ROM_SIZE as u64 {
+                let title = fs::read(&path)
+                    .ok()
+                    .and_then(|rom| known_title(&rom, db))
+                    .unwrap_or(file_name);
+                items.push(Item { path, title });
+            }
+        }
+        items.sort_by_key(|item| item.title.to_lowercase());
+        Ok(Self::new(dir, items))
+    }
+
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    pub fn selected(&self) -> Option<&Item> {
+        self.items.get(self.selected)
+    }
+
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+
+    /// Moves the selection, returning the rom to play when Enter is pressed
+    pub fn handle_key(&mut self, key: Key, page: usize) -> Option<PathBuf> {
+        let last = self.items.len().saturating_sub(1);
+        self.selected = match key {
+            Key::Up => self.selected.saturating_sub(1),
+            Key::Down => (self.selected + 1).min(last),
+            Key::PageUp => self.selected.saturating_sub(page),
+            Key::PageDown => (self.selected + page).min(last),
+            Key::Home => 0,
+            Key::End => last,
+            Key::Enter | Key::NumPadEnter => return self.selected().map(|item| item.path.clone()),
+            _ => return None,
+        };
+        None
+    }
+
+    /// Roms listed at once in a window of the given height
+    pub fn page_len(height: usize) -> usize {
+        // Title, blank line, then the list, then a blank line, the status and help
+        (height.saturating_sub(2 * MARGIN) / LINE_HEIGHT)
+            .saturating_sub(5)
+            .max(1)
+    }
+
+    /// Draws the menu into a width x height buffer
+    pub fn render(&self, width: usize, height: usize) -> Vec<u32> {
+        let mut canvas = Canvas::new(width, height, BACKGROUND);
+        let columns = width.saturating_sub(2 * MARGIN) / CHAR_WIDTH;
+        let clip = |text: &str| text.chars().take(columns).collect::<String>();
+        let title = format!("CHIPPER - {}", self.dir.display());
+        canvas.draw_text(MARGIN, MARGIN, &clip(&title), TITLE);
+
+        let page = Self::page_len(height);
+        let first = (self.selected / page) * page;
+        let top = MARGIN + 2 * LINE_HEIGHT;
+        if self.items.is_empty() {
+            canvas.draw_text(MARGIN, top, "NO ROMS FOUND", TEXT);
+        }
+        for (line, item) in self.items.iter().skip(first).take(page).enumerate() {
+            let y = top + line * LINE_HEIGHT;
+            if first + line == self.selected {
+                canvas.fill_rect(
+                    MARGIN - 2,
+                    y - 1,
+                    width - 2 * MARGIN + 4,
+                    LINE_HEIGHT,
+                    HIGHLIGHT_BAR,
+                );
+                canvas.draw_text(MARGIN, y, &clip(&item.title), HIGHLIGHT_TEXT);
+            } else {
+                canvas.draw_text(MARGIN, y, &clip(&item.title), TEXT);
+            }
+        }
+
+        let bottom = top + (page + 1) * LINE_HEIGHT;
+        canvas.draw_text(MARGIN, bottom, &clip(&self.status), HIGHLIGHT_TEXT);
+        canvas.draw_text(MARGIN, bottom + LINE_HEIGHT, &clip(HELP), TITLE);
+        canvas.into_pixels()
+    }
+}
+
+fn known_title(rom: &[u8], db: &RomDb) -> Option<String> {
+    db.lookup(rom).map(|entry| entry.program.title.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Item, Launcher};
+    use crate::chip8::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use crate::chip8::romdb::RomDb;
+    use minifb::Key;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn scans_roms() {
+        let launcher = Launcher::scan(Path::new("roms"), &RomDb::embedded()).unwrap();
+        let titles: Vec<&str> = launcher
+            .items()
+            .iter()
+            .map(|item| item.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Landing", "Space Invaders", "Tetris"]);
+        assert_eq!(launcher.items()[2].path, Path::new("roms/TETRIS"));
+        assert!(Launcher::scan(Path::new("missing"), &RomDb::embedded()).is_err());
+    }
+
+    #[test]
+    fn navigation() {
+        let items = (0..5)
+            .map(|idx| Item {
+                path: PathBuf::from(format!("rom{}", idx)),
+                title: format!("Rom {}", idx),
+            })
+            .collect();
+        let mut launcher = Launcher::new(Path::new("."), items);
+        assert_eq!(launcher.handle_key(Key::Up, 2), None);
+        assert_eq!(launcher.selected().unwrap().title, "Rom 0");
+        launcher.handle_key(Key::PageDown, 2);
+        launcher.handle_key(Key::Down, 2);
+        assert_eq!(launcher.selected().unwrap().title, "Rom 3");
+        launcher.handle_key(Key::End, 2);
+        launcher.handle_key(Key::Down, 2);
+        assert_eq!(
+            launcher.handle_key(Key::Enter, 2),
+            Some(PathBuf::from("rom4"))
+        );
+        launcher.handle_key(Key::Home, 2);
+        assert_eq!(launcher.selected().unwrap().title, "Rom 0");
+
+        launcher.set_status("Couldn't load rom");
+        let buffer = launcher.render(SCREEN_WIDTH, SCREEN_HEIGHT);
+        assert_eq!(buffer.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(Launcher::page_len(SCREEN_HEIGHT), 20);
+    }
+}
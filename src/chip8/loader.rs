@@ -0,0 +1,197 @@
+//! Reading roms from files, or from zip archives without extracting them
+//!
+//! `collection.zip` gives the only rom of an archive and `collection.zip:games/pong.ch8`
+//! a given one, roms being the entries with a `.ch8`, `.sc8` or `.xo8` extension.
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use zip::ZipArchive;
+
+pub const ROM_EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+
+/// Whether path names a zip archive, from its extension
+pub fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+// `archive.zip:entry` split into the archive and the entry
+fn split_entry(path: &str) -> Option<(&str, &str)> {
+    let idx = path.to_ascii_lowercase().find(".zip:")?;
+    Some((&path[..idx + 4], &path[idx + 5..]))
+}
+
+/// The file holding the rom at path, the archive of an `archive.zip:entry`
+pub fn file_path(path: &str) -> &Path {
+    Path::new(split_entry(path).map_or(path, |(archive, _)| archive))
+}
+
+fn open(archive: &Path) -> Result<ZipArchive<File>, String> {
+    File::open(archive)
+        .map_err(|err| err.to_string())
+        .and_then(|file| ZipArchive::new(file).map_err(|err| err.to_string()))
+        .map_err(|err| format!("Couldn't read {}: {}", archive.display(), err))
+}
+
+// Whether an archive entry is a rom, from its extension
+fn is_rom(name: &str) -> bool {
+    Path::new(name).extension().is_some_and(|extension| {
+        ROM_EXTENSIONS
+            .iter()
+            .any(|rom| extension.eq_ignore_ascii_case(rom))
+    })
+}
+
+/// Names of the roms of an archive, in archive order
+pub fn zip_roms(archive: &Path) -> Result<Vec<String>, String> {
+    let mut zip = open(archive)?;
+    let mut roms = Vec::new();
+    for idx in 0..zip.len() {
+        let entry = zip
+            .by_index_raw(idx) // Only the name is needed, nothing gets decompressed
+            .map_err(|err| format!("Couldn't read {}: {}", archive.display(), err))?;
+        if is_rom(entry.name()) && entry.is_file() {
+            roms.push(entry.name().to_string());
+        }
+    }
+    Ok(roms)
+}
+
+/// Names and contents of the roms of an archive, in archive order, reading it only once
+pub fn read_zip_roms(archive: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut zip = open(archive)?;
+    let mut roms = Vec::new();
+    for idx in 0..zip.len() {
+        let mut entry = zip
+            .by_index(idx)
+            .map_err(|err| format!("Couldn't read {}: {}", archive.display(), err))?;
+        if !is_rom(entry.name()) || !entry.is_file() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let mut rom = Vec::new();
+        entry
+            .read_to_end(&mut rom)
+            .map_err(|err| format!("Couldn't read {}:{}: {}", archive.display(), name, err))?;
+        roms.push((name, rom));
+    }
+    Ok(roms)
+}
+
+/// Reads one entry of an archive
+pub fn read_zip_entry(archive: &Path, name: &str) -> Result<Vec<u8>, String> {
+    let mut zip = open(archive)?;
+    let failed = |err: String| format!("Couldn't read {}:{}: {}", archive.display(), name, err);
+    let mut entry = zip.by_name(name).map_err(|err| failed(err.to_string()))?;
+    let mut rom = Vec::new();
+    entry
+        .read_to_end(&mut rom)
+        .map_err(|err| failed(err.to_string()))?;
+    Ok(rom)
+}
+
+/// Reads a rom file, the only rom of an archive, or an `archive.zip:entry`
+pub fn read_rom(path: &str) -> Result<Vec<u8>, String> {
+    if let Some((archive, name)) = split_entry(path) {
+        return read_zip_entry(Path::new(archive), name);
+    }
+    let file = Path::new(path);
+    if !is_zip(file) {
+        return fs::read(file).map_err(|err| format!("Couldn't read {}: {}", path, err));
+    }
+    match zip_roms(file)?.as_slice() {
+        [] => Err(format!("No rom in {}", path)),
+        [name] => read_zip_entry(file, name),
+        names => Err(format!(
+            "{} holds several roms, pick one as {}:<rom>: {}",
+            path,
+            path,
+            names.join(", ")
+        )),
+    }
+}
+
+/// Roms bundled with the sources, for tests going through all of them: every file of
+/// `roms/` and the `.ch8` files of `bin/`
+#[cfg(test)]
+pub fn bundled_roms() -> Vec<std::path::PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut roms = Vec::new();
+    for dir in ["roms", "bin"] {
+        for entry in fs::read_dir(root.join(dir)).unwrap() {
+            let path = entry.unwrap().path();
+            if dir == "roms" || path.extension().is_some_and(|ext| ext == "ch8") {
+                roms.push(path);
+            }
+        }
+    }
+    roms.sort();
+    roms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_path, read_rom, read_zip_roms, split_entry, zip_roms};
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use zip::write::{FileOptions, ZipWriter};
+
+    fn archive(name: &str, entries: &[(&str, &[u8])]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("chipper-{}-{}", std::process::id(), name));
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (name, data) in entries {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn zip_archives() {
+        assert_eq!(
+            split_entry("a/roms.ZIP:pong.ch8"),
+            Some(("a/roms.ZIP", "pong.ch8"))
+        );
+        assert_eq!(split_entry("roms/pong.ch8"), None);
+        assert_eq!(file_path("roms.zip:pong.ch8"), Path::new("roms.zip"));
+
+        let single = archive(
+            "single.zip",
+            &[("README", b"hi"), ("games/pong.ch8", &[0x12, 0x00])],
+        );
+        assert_eq!(zip_roms(&single).unwrap(), ["games/pong.ch8"]);
+        assert_eq!(
+            read_zip_roms(&single).unwrap(),
+            [(String::from("games/pong.ch8"), vec![0x12, 0x00])]
+        );
+        assert_eq!(read_rom(single.to_str().unwrap()).unwrap(), [0x12, 0x00]);
+
+        let several = archive(
+            "several.zip",
+            &[("a.ch8", &[0x00, 0xE0]), ("b.SC8", &[0x00, 0xFF])],
+        );
+        let several = several.to_str().unwrap();
+        let err = read_rom(several).unwrap_err();
+        let listed = format!(
+            "{0} holds several roms, pick one as {0}:<rom>: a.ch8, b.SC8",
+            several
+        );
+        assert_eq!(err, listed);
+        assert_eq!(
+            read_rom(&format!("{}:b.SC8", several)).unwrap(),
+            [0x00, 0xFF]
+        );
+        assert!(read_rom(&format!("{}:c.ch8", several)).is_err());
+
+        let empty = archive("empty.zip", &[("README", b"hi")]);
+        assert!(read_rom(empty.to_str().unwrap())
+            .unwrap_err()
+            .starts_with("No rom in"));
+        for path in [single, empty, PathBuf::from(several)] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
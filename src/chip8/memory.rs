@@ -1,5 +1,7 @@
 //! API exposing mem mechanisms
 //!
+use std::ops::Range;
+
 use super::font::FONT_SET;
 
 const STACK_SIZE: usize = 16;
@@ -7,8 +9,9 @@ pub const RAM_SIZE: usize = 0x1000; // 4096
 
 pub const FONTS_BASE_ADDR: usize = 0x000; // Base adress for fonts in RAM
 pub const ROM_BASE_ADDR: usize = 0x200; // Base adress for ROM in RAM
+pub const MAX_ROM_SIZE: usize = RAM_SIZE - ROM_BASE_ADDR; // 3584, what fits after ROM_BASE_ADDR
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 /// A set of registers, likely to be owned by a CPU
 pub struct Registers {
     // General purpose regs, which can be written to and read from (VF is not accessible from programs though)
@@ -22,7 +25,7 @@ pub struct Registers {
     pub st: u8, // Sound timer -> active whenever it's not 0
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Stack {
     vec: Vec<u16>, // Default: all 0
 }
@@ -41,21 +44,68 @@ impl Stack {
         // Responsability of the caller to handle the empty stack
         self.vec.pop()
     }
+
+    /// Saved return addresses, from the bottom of the stack to its top
+    pub fn as_slice(&self) -> &[u16] {
+        self.vec.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
 }
 
 impl Default for Stack {
     fn default() -> Self {
         Self {
-            vec: Vec::with_capacity(STACK_SIZE as usize),
+            vec: Vec::with_capacity(STACK_SIZE),
         }
     }
 }
 
-#[derive(Debug)]
+/// A memory access performed by the cpu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Accesses a watchpoint triggers on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // Both reads and writes
+}
+
+impl WatchKind {
+    pub fn matches(&self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::Access, _)
+                | (WatchKind::Read, Access::Read)
+                | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+/// A watched range of ram addresses
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub range: Range<usize>,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone)]
 /// Main memory unit
 pub struct Mem {
-    ram: [u8; RAM_SIZE], // Main RAM
-    rom: Vec<u8>,        // Embedded instructions, wich will be included in RAM
+    ram: [u8; RAM_SIZE],          // Main RAM
+    rom: Vec<u8>,                 // Embedded instructions, wich will be included in RAM
+    watchpoints: Vec<Watchpoint>, // Kept across resets
 }
 
 impl Mem {
@@ -64,6 +114,7 @@ impl Mem {
         let mut mem = Self {
             ram: [0; RAM_SIZE],
             rom,
+            watchpoints: Vec::new(),
         };
         mem.reset(); // Puts the loaded (embedded) rom into ram
         mem
@@ -74,22 +125,41 @@ impl Mem {
     }
 
     /// Load a (new) custom rom into the mem context (for "rom switching")
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
+    ///
+    /// Empty roms and roms not fitting in ram are rejected, the current rom being kept.
+    /// Returns warnings about roms which load but look suspicious.
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<Vec<String>, String> {
+        if rom.is_empty() {
+            return Err(String::from("Rom is empty"));
+        }
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(format!(
+                "Rom is too large: {} bytes, only {} fit in ram from 0x{:03X}",
+                rom.len(),
+                MAX_ROM_SIZE,
+                ROM_BASE_ADDR
+            ));
+        }
+        let mut warnings = Vec::new();
+        if !rom.len().is_multiple_of(2) {
+            warnings.push(format!(
+                "Rom has an odd length ({} bytes), its last byte isn't a whole instruction",
+                rom.len()
+            ));
+        }
         self.rom = rom;
         self.reset();
+        Ok(warnings)
     }
 
     /// (Re-)sets mem: initializes rom and fonts into ram
     pub fn reset(&mut self) {
+        // Clears ram, so nothing of a previous (longer) rom or of its writes is left
+        self.ram = [0; RAM_SIZE];
         // Sets rom -> loads the embedded rom into the actual ram
-        for (i, byte) in self.rom.iter().enumerate() {
-            if i + ROM_BASE_ADDR < RAM_SIZE {
-                // Still RAM available
-                self.ram[ROM_BASE_ADDR + i] = *byte;
-            } else {
-                break; // RAM is full, the entire ROM can't be placed in
-            }
-        }
+        // load_rom rejects roms not fitting, only those given to new get cut
+        let len = self.rom.len().min(MAX_ROM_SIZE);
+        self.ram[ROM_BASE_ADDR..ROM_BASE_ADDR + len].copy_from_slice(&self.rom[..len]);
         //Sets fonts
         for (i, byte) in FONT_SET.iter().enumerate() {
             self.ram[FONTS_BASE_ADDR + i] = *byte;
@@ -120,6 +190,39 @@ impl Mem {
         Some(segment)
     }
 
+    /// Watches the given address range for the given kind of accesses
+    pub fn add_watchpoint(&mut self, range: Range<usize>, kind: WatchKind) {
+        let watchpoint = Watchpoint { range, kind };
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    /// Removes a watchpoint, returning whether it existed
+    pub fn remove_watchpoint(&mut self, range: Range<usize>, kind: WatchKind) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.range != range || watchpoint.kind != kind);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.watchpoints.as_slice()
+    }
+
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.watchpoints = watchpoints;
+    }
+
+    /// First address of an access of len bytes from addr that triggers a watchpoint
+    pub fn watch_hit(&self, addr: usize, len: usize, access: Access) -> Option<usize> {
+        (addr..addr + len).find(|addr| {
+            self.watchpoints.iter().any(|watchpoint| {
+                watchpoint.kind.matches(access) && watchpoint.range.contains(addr)
+            })
+        })
+    }
+
     /// Reads a complete word (2-byte in CHIP-8) from ram beginning at addr
     // Two consecutive regs (addr and addr + 1) are OR'd and yield a new u16
     pub fn read_word(&self, addr: usize) -> Option<u16> {
@@ -134,7 +237,7 @@ impl Mem {
 mod tests {
     use crate::chip8::font::FONT_SET;
 
-    use super::{Mem, Stack};
+    use super::{Access, Mem, Stack, WatchKind, MAX_ROM_SIZE, RAM_SIZE, ROM_BASE_ADDR};
 
     #[test]
     fn stack_push_valid() {
@@ -180,10 +283,26 @@ mod tests {
     #[test]
     fn mem_load_rom() {
         let mut mem = mem_setup();
-        mem.load_rom(vec![4, 4, 3, 4]);
+        assert_eq!(mem.load_rom(vec![4, 4, 3, 4]), Ok(vec![]));
         assert_eq!(mem.rom, vec![4, 4, 3, 4]);
     }
 
+    #[test]
+    fn mem_load_invalid_rom() {
+        let mut mem = mem_setup_filled(vec![4, 4, 3, 4]);
+        assert_eq!(mem.load_rom(vec![]), Err(String::from("Rom is empty")));
+        let err = mem.load_rom(vec![0; MAX_ROM_SIZE + 1]).unwrap_err();
+        assert!(err.starts_with("Rom is too large: 3585 bytes"));
+        assert_eq!(mem.rom, vec![4, 4, 3, 4]); // Kept on errors
+
+        assert_eq!(mem.load_rom(vec![0xFF; MAX_ROM_SIZE]), Ok(vec![]));
+        assert_eq!(mem.read_byte(RAM_SIZE - 1), Some(0xFF));
+        let warnings = mem.load_rom(vec![1, 2, 3]).unwrap();
+        assert_eq!(mem.read_byte(ROM_BASE_ADDR + 3), Some(0)); // The longer rom is gone
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Rom has an odd length (3 bytes)"));
+    }
+
     #[test]
     fn mem_set() {
         let mem = mem_setup(); // Should take care of putting an empty vec in ram (for rom) and putting fonts at head
@@ -217,4 +336,22 @@ mod tests {
         let mem = mem_setup_filled(vec![4, 4, 3, 4]);
         assert_eq!(mem.read_segment(5, 4095).unwrap(), vec![0, 0, 0, 0, 0]);
     }
+
+    #[test]
+    fn watchpoints() {
+        let mut mem = mem_setup();
+        assert_eq!(mem.watch_hit(0x300, 4, Access::Write), None);
+        mem.add_watchpoint(0x302..0x304, WatchKind::Write);
+        mem.add_watchpoint(0x400..0x401, WatchKind::Access);
+        assert_eq!(mem.watch_hit(0x300, 4, Access::Write), Some(0x302));
+        assert_eq!(mem.watch_hit(0x300, 4, Access::Read), None);
+        assert_eq!(mem.watch_hit(0x3FF, 2, Access::Read), Some(0x400));
+        assert_eq!(mem.watch_hit(0x304, 1, Access::Write), None);
+
+        mem.reset();
+        assert_eq!(mem.watchpoints().len(), 2);
+        assert!(mem.remove_watchpoint(0x302..0x304, WatchKind::Write));
+        assert!(!mem.remove_watchpoint(0x302..0x304, WatchKind::Write));
+        assert_eq!(mem.watch_hit(0x300, 4, Access::Write), None);
+    }
 }
@@ -1,18 +1,55 @@
 //!Main chip8 API mod
 
+pub mod assembler;
+pub mod cfg;
+pub mod coverage;
 pub mod cpu;
+pub mod debugger;
+pub mod decompile;
+pub mod disasm;
 pub mod display;
+pub mod flow;
 pub mod font;
+pub mod gdb;
+pub mod heatmap;
 pub mod input;
+pub mod instruction;
+pub mod launcher;
+pub mod loader;
 pub mod memory;
+pub mod overlay;
+pub mod platform;
+pub mod profile;
+pub mod romdb;
+pub mod smc;
+pub mod symbols;
+pub mod trace;
+pub mod trace_diff;
+pub mod watch;
 
 use cpu::{CPU, CpuState};
+use heatmap::Heatmap;
 use input::KeyBoard;
 use self::memory::Mem;
+use profile::Profiler;
+use smc::SmcDetector;
+use symbols::Symbols;
+use trace::Tracer;
 
 pub struct Interpreter {
     pub cpu: CPU,
     pub keyboard: KeyBoard,
+    pub tracer: Option<Tracer>, // Sees every executed instruction
+    pub profiler: Option<Profiler>,
+    pub smc: Option<SmcDetector>, // Flags code and data overlapping at runtime
+    pub heatmap: Option<Heatmap>,
+    pub symbols: Symbols, // Names addresses in error messages, empty unless loaded
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
@@ -21,11 +58,35 @@ impl Interpreter {
         Self {
             cpu: CPU::new(mem),
             keyboard: KeyBoard::new(),
+            tracer: None,
+            profiler: None,
+            smc: None,
+            heatmap: None,
+            symbols: Symbols::new(),
         }
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        self.cpu.load_rom(rom);
+    /// Loads a rom, rejecting empty ones and those not fitting in ram, warning about odd ones
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<Vec<String>, String> {
+        self.cpu.load_rom(rom)
+    }
+
+    /// Loads a rom in place of the current one and resets the cpu to run it from the start,
+    /// the tools forgetting what they recorded of the previous rom (the trace goes on)
+    pub fn reload_rom(&mut self, rom: Vec<u8>) -> Result<Vec<String>, String> {
+        let warnings = self.cpu.load_rom(rom)?;
+        self.cpu.reset();
+        self.cpu.clear_coverage();
+        if self.profiler.is_some() {
+            self.profiler = Some(Profiler::new());
+        }
+        if self.smc.is_some() {
+            self.smc = Some(SmcDetector::new());
+        }
+        if self.heatmap.is_some() {
+            self.heatmap = Some(Heatmap::new());
+        }
+        Ok(warnings)
     }
 
     pub fn feed_key(&mut self, key: Option<u8>) {
@@ -33,6 +94,62 @@ impl Interpreter {
     }
 
     pub fn tick(&mut self) -> CpuState {
-        self.cpu.tick(&self.keyboard)
+        let timers = self.cpu.timers_due();
+        self.tick_with(timers)
+    }
+
+    /// Executes one instruction, decreasing timers first when told to (replays use recorded ticks)
+    pub fn tick_with(&mut self, decrease_timers: bool) -> CpuState {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(&self.cpu);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(&self.cpu);
+        }
+        if let Some(smc) = &mut self.smc {
+            smc.record(&self.cpu);
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(&self.cpu);
+        }
+        let pc = self.cpu.registers().pc;
+        let state = match self.cpu.tick_with(&self.keyboard, decrease_timers) {
+            CpuState::Error(err) => {
+                CpuState::Error(format!("{} at {}", err, self.symbols.describe(pc)))
+            }
+            state => state,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            if let Err(err) = tracer.after(&self.cpu, &state) {
+                return CpuState::Error(format!("Couldn't write trace: {}", err));
+            }
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::coverage::EXECUTED;
+    use super::profile::Profiler;
+    use super::Interpreter;
+
+    #[test]
+    fn reloading_forgets_the_previous_rom() {
+        let mut chip8 = Interpreter::new();
+        chip8.cpu.enable_coverage();
+        chip8.profiler = Some(Profiler::new());
+        // 0x200: LD V0, 1; JP 0x200
+        chip8.load_rom(vec![0x60, 0x01, 0x12, 0x00]).unwrap();
+        for _ in 0..4 {
+            chip8.tick_with(false);
+        }
+        assert_eq!(chip8.profiler.as_ref().unwrap().total(), 4);
+
+        chip8.reload_rom(vec![0x12, 0x00]).unwrap();
+        assert_eq!(chip8.cpu.registers().pc, 0x200);
+        assert_eq!(chip8.profiler.as_ref().unwrap().total(), 0);
+        let coverage = chip8.cpu.coverage().expect("Still enabled");
+        assert_eq!(coverage.count(EXECUTED), 0);
     }
 }
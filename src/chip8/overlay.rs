@@ -0,0 +1,261 @@
+//! In-window debug layout: the game screen next to live panels of the machine state
+//!
+//! Everything is drawn in software into a minifb buffer, text using the glyphs of
+//! `font::TEXT_FONT`.
+use super::display::{bits_from_u8, SCREEN_HEIGHT, SCREEN_WIDTH};
+use super::font::{text_glyph, TEXT_GLYPH_HEIGHT, TEXT_GLYPH_WIDTH};
+use super::memory::RAM_SIZE;
+use super::{disasm, Interpreter};
+
+const TEXT_SCALE: usize = 2; // Screen pixels per glyph pixel
+pub const CHAR_WIDTH: usize = (TEXT_GLYPH_WIDTH + 1) * TEXT_SCALE; // One pixel of spacing
+pub const LINE_HEIGHT: usize = (TEXT_GLYPH_HEIGHT + 1) * TEXT_SCALE;
+
+const MARGIN: usize = 10;
+const PANEL_COLUMNS: usize = 30; // Characters per panel line
+const PANEL_LINES: usize = 17; // Lines of the disassembly and memory panels
+const RIGHT_X: usize = SCREEN_WIDTH + MARGIN; // Registers and stack, right of the game
+const BOTTOM_Y: usize = SCREEN_HEIGHT + MARGIN; // Disassembly and memory, under the game
+const MEM_X: usize = MARGIN * 2 + PANEL_COLUMNS * CHAR_WIDTH;
+const MEM_ROW_LEN: usize = 8; // Bytes per line of the memory panel
+
+pub const DEBUG_WIDTH: usize = RIGHT_X + PANEL_COLUMNS * CHAR_WIDTH + MARGIN;
+pub const DEBUG_HEIGHT: usize = BOTTOM_Y + (PANEL_LINES + 1) * LINE_HEIGHT + MARGIN;
+
+pub const BACKGROUND: u32 = 0x101018;
+pub const TITLE: u32 = 0xFFFF; // Same as lit pixels
+pub const TEXT: u32 = 0xC0C0C0;
+pub const HIGHLIGHT_TEXT: u32 = 0xFFFF00;
+pub const HIGHLIGHT_BAR: u32 = 0x404000;
+
+/// A software framebuffer, in minifb's 0RGB format
+pub struct Canvas {
+    pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize, color: u32) -> Self {
+        Self {
+            pixels: vec![color; width * height],
+            width,
+            height,
+        }
+    }
+
+    /// Draws over an existing width x height buffer
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<u32>) -> Self {
+        Self {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    pub fn into_pixels(self) -> Vec<u32> {
+        self.pixels
+    }
+
+    /// Fills a rectangle, clipped to the canvas
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for row in y..(y + height).min(self.height) {
+            for col in x..(x + width).min(self.width) {
+                self.pixels[row * self.width + col] = color;
+            }
+        }
+    }
+
+    /// Copies a width x height buffer at (x, y)
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, buffer: &[u32]) {
+        for (row, line) in buffer.chunks(width).enumerate() {
+            for (col, pixel) in line.iter().enumerate() {
+                if x + col < self.width && y + row < self.height {
+                    self.pixels[(y + row) * self.width + x + col] = *pixel;
+                }
+            }
+        }
+    }
+
+    /// Draws a single line of text with its top left corner at (x, y)
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, color: u32) {
+        for (idx, c) in text.chars().enumerate() {
+            let left = x + idx * CHAR_WIDTH;
+            for (row, byte) in text_glyph(c).iter().enumerate() {
+                for (col, bit) in bits_from_u8(*byte).iter().enumerate() {
+                    if *bit {
+                        self.fill_rect(
+                            left + col * TEXT_SCALE,
+                            y + row * TEXT_SCALE,
+                            TEXT_SCALE,
+                            TEXT_SCALE,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Draws a line of a panel, highlighted with a bar when it is the current one
+    fn draw_line(&mut self, x: usize, y: usize, text: &str, highlighted: bool) {
+        if highlighted {
+            self.fill_rect(
+                x,
+                y - 1,
+                PANEL_COLUMNS * CHAR_WIDTH,
+                LINE_HEIGHT,
+                HIGHLIGHT_BAR,
+            );
+            self.draw_text(x, y, text, HIGHLIGHT_TEXT);
+        } else {
+            self.draw_text(x, y, text, TEXT);
+        }
+    }
+}
+
+/// Renders the debug layout (`DEBUG_WIDTH` x `DEBUG_HEIGHT`), status being shown under the panels
+pub fn render(chip8: &Interpreter, status: &str) -> Vec<u32> {
+    let mut canvas = Canvas::new(DEBUG_WIDTH, DEBUG_HEIGHT, BACKGROUND);
+    canvas.blit(0, 0, SCREEN_WIDTH, &chip8.cpu.vram().to_screen_buffer());
+    draw_registers(&mut canvas, chip8);
+    draw_stack(&mut canvas, chip8);
+    draw_disasm(&mut canvas, chip8);
+    draw_mem(&mut canvas, chip8);
+    canvas.draw_text(RIGHT_X, BOTTOM_Y, status, TITLE);
+    canvas.into_pixels()
+}
+
+/// Draws text on a highlight bar over the top left corner of a buffer, e.g. "RELOADED"
+pub fn notice(buffer: Vec<u32>, width: usize, height: usize, text: &str) -> Vec<u32> {
+    let mut canvas = Canvas::from_pixels(width, height, buffer);
+    let bar_width = (text.chars().count() + 2) * CHAR_WIDTH;
+    canvas.fill_rect(0, 0, bar_width, LINE_HEIGHT + 4, HIGHLIGHT_BAR);
+    canvas.draw_text(CHAR_WIDTH, 3, text, HIGHLIGHT_TEXT);
+    canvas.into_pixels()
+}
+
+fn draw_registers(canvas: &mut Canvas, chip8: &Interpreter) {
+    let registers = chip8.cpu.registers();
+    canvas.draw_text(RIGHT_X, MARGIN, "REGISTERS", TITLE);
+    for (line, regs) in registers.v.chunks(4).enumerate() {
+        let text: Vec<String> = regs
+            .iter()
+            .enumerate()
+            .map(|(idx, val)| format!("V{:X}={:02X}", line * 4 + idx, val))
+            .collect();
+        canvas.draw_text(
+            RIGHT_X,
+            MARGIN + (line + 1) * LINE_HEIGHT,
+            &text.join(" "),
+            TEXT,
+        );
+    }
+    let lines = [
+        format!("I={:04X} PC={:04X}", registers.i, registers.pc),
+        format!(
+            "SP={} DT={:02X} ST={:02X}",
+            chip8.cpu.stack().len(),
+            registers.dt,
+            registers.st
+        ),
+    ];
+    for (idx, text) in lines.iter().enumerate() {
+        canvas.draw_text(RIGHT_X, MARGIN + (idx + 5) * LINE_HEIGHT, text, TEXT);
+    }
+}
+
+fn draw_stack(canvas: &mut Canvas, chip8: &Interpreter) {
+    let top = MARGIN + 8 * LINE_HEIGHT;
+    canvas.draw_text(RIGHT_X, top, "STACK", TITLE);
+    let stack = chip8.cpu.stack().as_slice();
+    for (depth, addr) in stack.iter().rev().enumerate() {
+        let text = format!("#{:<2} {:04X}", depth, addr);
+        canvas.draw_text(RIGHT_X, top + (depth + 1) * LINE_HEIGHT, &text, TEXT);
+    }
+}
+
+fn draw_disasm(canvas: &mut Canvas, chip8: &Interpreter) {
+    canvas.draw_text(MARGIN, BOTTOM_Y, "DISASSEMBLY", TITLE);
+    let pc = chip8.cpu.registers().pc as usize;
+    // Instructions are read on the same alignment as pc, half of them before it
+    let first = pc.saturating_sub(PANEL_LINES / 2 * 2);
+    let first = first - (first % 2) + (pc % 2);
+    for line in 0..PANEL_LINES {
+        let addr = first + line * 2;
+        let text = match chip8.cpu.mem().read_word(addr) {
+            Some(word) => format!("{:04X}: {:04X} {}", addr, word, disasm::mnemonic(word)),
+            None => break,
+        };
+        canvas.draw_line(
+            MARGIN,
+            BOTTOM_Y + (line + 1) * LINE_HEIGHT,
+            &text,
+            addr == pc,
+        );
+    }
+}
+
+fn draw_mem(canvas: &mut Canvas, chip8: &Interpreter) {
+    canvas.draw_text(MEM_X, BOTTOM_Y, "MEMORY AT I", TITLE);
+    let i = chip8.cpu.registers().i as usize;
+    let last_row = RAM_SIZE - PANEL_LINES * MEM_ROW_LEN;
+    let first = (i - i % MEM_ROW_LEN)
+        .saturating_sub(PANEL_LINES / 2 * MEM_ROW_LEN)
+        .min(last_row);
+    for line in 0..PANEL_LINES {
+        let addr = first + line * MEM_ROW_LEN;
+        let bytes = chip8
+            .cpu
+            .mem()
+            .read_segment(MEM_ROW_LEN, addr)
+            .unwrap_or_default();
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let y = BOTTOM_Y + (line + 1) * LINE_HEIGHT;
+        canvas.draw_text(MEM_X, y, &format!("{:04X}: {}", addr, hex.join(" ")), TEXT);
+        if (addr..addr + MEM_ROW_LEN).contains(&i) {
+            // Redraws the byte at I on top, "AAAA: " being 6 characters wide
+            let x = MEM_X + (6 + (i - addr) * 3) * CHAR_WIDTH;
+            canvas.draw_text(x, y, &hex[i - addr], HIGHLIGHT_TEXT);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Canvas, CHAR_WIDTH, DEBUG_HEIGHT, DEBUG_WIDTH};
+    use crate::chip8::Interpreter;
+
+    #[test]
+    fn draw_text() {
+        let mut canvas = Canvas::new(4 * CHAR_WIDTH, 12, 0);
+        canvas.draw_text(0, 0, "-", 1);
+        // '-' is the middle row of the glyph: 3 pixels wide, each 2x2
+        let lit: Vec<usize> = (0..canvas.pixels().len())
+            .filter(|idx| canvas.pixels()[*idx] == 1)
+            .collect();
+        assert_eq!(lit.len(), 12);
+        assert!(lit.iter().all(|idx| (4..6).contains(&(idx / canvas.width))));
+    }
+
+    #[test]
+    fn clipped_drawing() {
+        let mut canvas = Canvas::new(10, 10, 0);
+        canvas.draw_text(8, 8, "WW", 1);
+        canvas.fill_rect(5, 5, 100, 100, 2);
+        assert_eq!(canvas.pixels().len(), 100);
+        assert_eq!(canvas.pixels()[99], 2);
+    }
+
+    #[test]
+    fn layout() {
+        let mut chip8 = Interpreter::new();
+        chip8.load_rom(vec![0x63, 0x10]).unwrap();
+        let buffer = render(&chip8, "PAUSED");
+        assert_eq!(buffer.len(), DEBUG_WIDTH * DEBUG_HEIGHT);
+    }
+}
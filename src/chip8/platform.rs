@@ -0,0 +1,479 @@
+//! Static rom analysis, guessing the platform a rom was written for
+//!
+//! Code is explored from `ROM_BASE_ADDR` like `Flow` does, also knowing the
+//! SUPER-CHIP and XO-CHIP opcodes, so that extended roms are followed past them.
+//! Reachable code then tells the platform, and which quirks it may depend on: the
+//! platforms disagree on how shifts, `Fx55`/`Fx65` and `Bnnn` behave.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Write;
+
+use super::flow::{successors, Successor};
+use super::instruction::Instruction;
+use super::memory::{MAX_ROM_SIZE, ROM_BASE_ADDR};
+
+const RELIANCE_WINDOW: usize = 8; // Instructions after Fx55/Fx65 looked at for a use of I
+const ADDRESSES_SHOWN: usize = 8; // Addresses listed per finding
+
+/// Platforms, each extending the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        })
+    }
+}
+
+impl Platform {
+    /// Quirks of the platform's reference interpreter
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks {
+                name: "vip",
+                shift_vx: false,
+                memory_increments_i: true,
+                jump_vx: false,
+                vf_reset: true,
+                clip: true,
+                display_wait: true,
+            },
+            Platform::SuperChip => Quirks {
+                name: "schip",
+                shift_vx: true,
+                memory_increments_i: false,
+                jump_vx: true,
+                vf_reset: false,
+                clip: true,
+                display_wait: false,
+            },
+            Platform::XoChip => Quirks {
+                name: "xo-chip",
+                shift_vx: false,
+                memory_increments_i: true,
+                jump_vx: false,
+                vf_reset: false,
+                clip: false,
+                display_wait: false,
+            },
+        }
+    }
+
+    /// Largest rom the platform can load
+    pub fn max_rom_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => MAX_ROM_SIZE,
+            Platform::XoChip => 0x10000 - ROM_BASE_ADDR,
+        }
+    }
+}
+
+/// Behaviours the platforms disagree on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub name: &'static str,
+    pub shift_vx: bool,            // 8xy6/8xyE shift vx, rather than vy into vx
+    pub memory_increments_i: bool, // Fx55/Fx65 leave I past the last register
+    pub jump_vx: bool,             // Bxnn jumps to xnn + vx, rather than nnn + v0
+    pub vf_reset: bool,            // 8xy1/8xy2/8xy3 reset vF
+    pub clip: bool,                // Sprites are clipped at the edges, not wrapped
+    pub display_wait: bool,        // Dxyn waits for the next frame
+}
+
+/// Quirks of chipper's own cpu
+pub const CHIPPER_QUIRKS: Quirks = Quirks {
+    name: "chipper",
+    shift_vx: true,
+    memory_increments_i: true,
+    jump_vx: false,
+    vf_reset: false,
+    clip: false,
+    display_wait: false,
+};
+
+impl Quirks {
+    /// Each quirk, as `shift vx` or `clip: yes`
+    pub fn settings(&self) -> [String; 6] {
+        let yes_no = |flag| if flag { "yes" } else { "no" };
+        [
+            format!("shift {}", if self.shift_vx { "vx" } else { "vy" }),
+            format!(
+                "Fx55/Fx65 increment I: {}",
+                yes_no(self.memory_increments_i)
+            ),
+            format!(
+                "jump {}",
+                if self.jump_vx { "xnn + vx" } else { "nnn + v0" }
+            ),
+            format!("vF reset: {}", yes_no(self.vf_reset)),
+            format!("clip: {}", yes_no(self.clip)),
+            format!("display wait: {}", yes_no(self.display_wait)),
+        ]
+    }
+
+    /// Settings of the quirks other sets differently
+    pub fn differences(&self, other: &Quirks) -> Vec<String> {
+        self.settings()
+            .into_iter()
+            .zip(other.settings())
+            .filter(|(setting, other)| setting != other)
+            .map(|(setting, _)| setting)
+            .collect()
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.settings().join(", "))
+    }
+}
+
+/// Opcode only existing on an extended platform, with its pattern
+fn extension(word: u16) -> Option<(Platform, &'static str)> {
+    let (x, kk, n) = ((word >> 8) & 0xF, word & 0xFF, word & 0xF);
+    let found = match word >> 12 {
+        0x0 => match word {
+            0x00C1..=0x00CF => (Platform::SuperChip, "00Cn"),
+            0x00D1..=0x00DF => (Platform::XoChip, "00Dn"),
+            0x00FB => (Platform::SuperChip, "00FB"),
+            0x00FC => (Platform::SuperChip, "00FC"),
+            0x00FD => (Platform::SuperChip, "00FD"),
+            0x00FE => (Platform::SuperChip, "00FE"),
+            0x00FF => (Platform::SuperChip, "00FF"),
+            _ => return None,
+        },
+        0x5 if n == 2 => (Platform::XoChip, "5xy2"),
+        0x5 if n == 3 => (Platform::XoChip, "5xy3"),
+        0xD if n == 0 => (Platform::SuperChip, "Dxy0"),
+        0xF => match kk {
+            0x00 if x == 0 => (Platform::XoChip, "F000 nnnn"),
+            0x01 => (Platform::XoChip, "Fn01"),
+            0x02 if x == 0 => (Platform::XoChip, "F002"),
+            0x30 => (Platform::SuperChip, "Fx30"),
+            0x3A => (Platform::XoChip, "Fx3A"),
+            0x75 => (Platform::SuperChip, "Fx75"),
+            0x85 => (Platform::SuperChip, "Fx85"),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(found)
+}
+
+/// Pattern of a CHIP-8 opcode, for the histogram
+fn pattern(instruction: Instruction) -> &'static str {
+    use Instruction::*;
+    match instruction {
+        Cls => "00E0",
+        Ret => "00EE",
+        Jump(_) => "1nnn",
+        Call(_) => "2nnn",
+        SkipEqByte { .. } => "3xkk",
+        SkipNeByte { .. } => "4xkk",
+        SkipEqReg { .. } => "5xy0",
+        LoadByte { .. } => "6xkk",
+        AddByte { .. } => "7xkk",
+        LoadReg { .. } => "8xy0",
+        Or { .. } => "8xy1",
+        And { .. } => "8xy2",
+        Xor { .. } => "8xy3",
+        AddReg { .. } => "8xy4",
+        Sub { .. } => "8xy5",
+        ShiftRight { .. } => "8xy6",
+        SubN { .. } => "8xy7",
+        ShiftLeft { .. } => "8xyE",
+        SkipNeReg { .. } => "9xy0",
+        LoadI(_) => "Annn",
+        JumpV0(_) => "Bnnn",
+        Random { .. } => "Cxkk",
+        Draw { .. } => "Dxyn",
+        SkipKey(_) => "Ex9E",
+        SkipNotKey(_) => "ExA1",
+        LoadDelay(_) => "Fx07",
+        WaitKey(_) => "Fx0A",
+        SetDelay(_) => "Fx15",
+        SetSound(_) => "Fx18",
+        AddI(_) => "Fx1E",
+        LoadFont(_) => "Fx29",
+        Bcd(_) => "Fx33",
+        Store(_) => "Fx55",
+        Load(_) => "Fx65",
+        Data(_) => "0nnn", // Only machine code calls are followed
+    }
+}
+
+/// What the reachable code of a rom tells about its platform
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub size: usize,
+    pub sha1: String,
+    pub instructions: usize,                                 // Reachable ones
+    pub histogram: BTreeMap<&'static str, usize>,            // Per opcode pattern
+    pub extensions: BTreeMap<u16, (Platform, &'static str)>, // By address
+    pub unknown: BTreeMap<u16, u16>,                         // Words exploration stopped on
+    pub shifts: Vec<u16>,                                    // 8xy6/8xyE with x != y
+    pub memory: Vec<u16>,                                    // Fx55/Fx65 soon followed by using I
+    pub jumps: Vec<u16>,                                     // Bnnn with x != 0
+}
+
+impl Analysis {
+    pub fn analyse(rom: &[u8]) -> Self {
+        let mut analysis = Analysis {
+            size: rom.len(),
+            sha1: sha1_smol::Sha1::from(rom).digest().to_string(),
+            ..Default::default()
+        };
+        let word = |addr: u16| {
+            let offset = (addr as usize).checked_sub(ROM_BASE_ADDR)?;
+            let bytes = rom.get(offset..offset + 2)?;
+            Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![ROM_BASE_ADDR as u16];
+        while let Some(pc) = pending.pop() {
+            let op = match word(pc) {
+                Some(op) if seen.insert(pc) => op,
+                _ => continue,
+            };
+            if let Some((platform, name)) = extension(op) {
+                analysis.extensions.insert(pc, (platform, name));
+                analysis.count(name);
+                match op {
+                    0x00FD => (),                   // Exits the interpreter
+                    0xF000 => pending.push(pc + 4), // Followed by a 16 bits address
+                    _ => pending.push(pc + 2),
+                }
+                continue;
+            }
+            let instruction = Instruction::decode(op);
+            match instruction {
+                Instruction::Data(op) if op >> 12 != 0 || op == 0 => {
+                    analysis.unknown.insert(pc, op);
+                    continue;
+                }
+                Instruction::Data(_) => pending.push(pc + 2), // Machine code subroutine
+                _ => {
+                    for successor in successors(pc, instruction) {
+                        pending.push(match successor {
+                            // XO-CHIP skips the whole of a long `F000 nnnn`
+                            Successor::Skip(addr) if word(pc + 2) == Some(0xF000) => addr + 2,
+                            successor => successor.addr(),
+                        });
+                    }
+                }
+            }
+            analysis.count(pattern(instruction));
+            match instruction {
+                Instruction::ShiftRight { x, y } | Instruction::ShiftLeft { x, y } if x != y => {
+                    analysis.shifts.push(pc)
+                }
+                Instruction::JumpV0(nnn) if nnn >> 8 != 0 => analysis.jumps.push(pc),
+                Instruction::Store(_) | Instruction::Load(_) if relies_on_i(pc + 2, &word) => {
+                    analysis.memory.push(pc)
+                }
+                _ => (),
+            }
+        }
+        analysis
+    }
+
+    fn count(&mut self, pattern: &'static str) {
+        *self.histogram.entry(pattern).or_default() += 1;
+        self.instructions += 1;
+    }
+
+    /// The least extended platform running the rom
+    pub fn platform(&self) -> Platform {
+        let by_size = [Platform::Chip8, Platform::XoChip]
+            .into_iter()
+            .find(|platform| self.size <= platform.max_rom_size())
+            .unwrap_or(Platform::XoChip);
+        self.extensions
+            .values()
+            .map(|(platform, _)| *platform)
+            .fold(by_size, Platform::max)
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = format!("Size: {} bytes\nSHA-1: {}\n", self.size, self.sha1);
+        writeln!(out, "Reachable instructions: {}", self.instructions).unwrap();
+        let mut histogram: Vec<(&str, usize)> =
+            self.histogram.iter().map(|(k, v)| (*k, *v)).collect();
+        histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (pattern, count) in histogram {
+            writeln!(out, "  {:<10} {:>5}", pattern, count).unwrap();
+        }
+
+        out.push_str("\nExtended opcodes:");
+        if self.extensions.is_empty() {
+            out.push_str(" none\n");
+        } else {
+            out.push('\n');
+            let mut found: BTreeMap<(Platform, &str), Vec<u16>> = BTreeMap::new();
+            for (addr, found_at) in &self.extensions {
+                found.entry(*found_at).or_default().push(*addr);
+            }
+            for ((platform, pattern), addrs) in found {
+                writeln!(
+                    out,
+                    "  {:<10} {:<10} {}",
+                    platform,
+                    pattern,
+                    addresses(&addrs)
+                )
+                .unwrap();
+            }
+        }
+        if !self.unknown.is_empty() {
+            let words: Vec<String> = self
+                .unknown
+                .iter()
+                .take(ADDRESSES_SHOWN)
+                .map(|(addr, op)| format!("{:04X} at 0x{:03X}", op, addr))
+                .collect();
+            writeln!(
+                out,
+                "Unknown opcodes, not explored past: {}",
+                words.join(", ")
+            )
+            .unwrap();
+        }
+
+        let platform = self.platform();
+        let quirks = platform.quirks();
+        out.push_str("\nQuirk-sensitive code:");
+        let findings = [
+            (
+                &self.shifts,
+                "shifts with x != y",
+                quirks.shift_vx != CHIPPER_QUIRKS.shift_vx,
+            ),
+            (
+                &self.memory,
+                "Fx55/Fx65 followed by a use of I",
+                quirks.memory_increments_i != CHIPPER_QUIRKS.memory_increments_i,
+            ),
+            (
+                &self.jumps,
+                "Bnnn with x != 0",
+                quirks.jump_vx != CHIPPER_QUIRKS.jump_vx,
+            ),
+        ];
+        if findings.iter().all(|(addrs, _, _)| addrs.is_empty()) {
+            out.push_str(" none\n");
+        } else {
+            out.push('\n');
+        }
+        let mut mismatches = Vec::new();
+        for (addrs, what, differs) in findings {
+            if addrs.is_empty() {
+                continue;
+            }
+            writeln!(out, "  {} {}: {}", addrs.len(), what, addresses(addrs)).unwrap();
+            if differs {
+                mismatches.push(what);
+            }
+        }
+
+        writeln!(out, "\nSuggested platform: {}", platform).unwrap();
+        writeln!(out, "Suggested quirks: {}", quirks).unwrap();
+        if !mismatches.is_empty() {
+            writeln!(
+                out,
+                "Warning: {} behave differently in chipper ({})",
+                mismatches.join(" and "),
+                CHIPPER_QUIRKS
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+// Whether straight-line code from pc uses I before setting it again
+fn relies_on_i(mut pc: u16, word: &dyn Fn(u16) -> Option<u16>) -> bool {
+    use Instruction::*;
+    for _ in 0..RELIANCE_WINDOW {
+        let op = match word(pc) {
+            Some(op) => op,
+            None => return false,
+        };
+        match extension(op) {
+            Some((_, "5xy2" | "5xy3" | "Dxy0")) => return true,
+            Some((_, "Fx30" | "F000 nnnn")) => return false,
+            Some(_) => (),
+            None => match Instruction::decode(op) {
+                Draw { .. } | Bcd(_) | Store(_) | Load(_) | AddI(_) => return true,
+                LoadI(_) | LoadFont(_) => return false,
+                instruction if successors(pc, instruction) != [Successor::Next(pc + 2)] => {
+                    return false
+                }
+                _ => (),
+            },
+        }
+        pc += 2;
+    }
+    false
+}
+
+fn addresses(addrs: &[u16]) -> String {
+    let mut shown: Vec<String> = addrs
+        .iter()
+        .take(ADDRESSES_SHOWN)
+        .map(|addr| format!("0x{:03X}", addr))
+        .collect();
+    if addrs.len() > ADDRESSES_SHOWN {
+        shown.push(String::from("..."));
+    }
+    shown.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Analysis, Platform};
+
+    #[test]
+    fn chip8_quirks() {
+        // SHR V0, V1; LD I, 0x300; LD [I], V1; DRW V0, V1, 1; B 0x310; (data) 0x00FF
+        let rom = [
+            0x80, 0x16, 0xA3, 0x00, 0xF1, 0x55, 0xD0, 0x11, 0xB3, 0x10, 0x00, 0xFF,
+        ];
+        let analysis = Analysis::analyse(&rom);
+        assert_eq!(analysis.platform(), Platform::Chip8);
+        assert_eq!(analysis.instructions, 5);
+        assert_eq!(analysis.histogram["Fx55"], 1);
+        assert_eq!(analysis.shifts, [0x200]);
+        assert_eq!(analysis.memory, [0x204]);
+        assert_eq!(analysis.jumps, [0x208]);
+        assert!(analysis.extensions.is_empty()); // 00FF is never reached
+        assert_eq!(analysis.sha1.len(), 40);
+        let report = analysis.report();
+        assert!(report.contains("Suggested platform: CHIP-8\nSuggested quirks: vip:"));
+        assert!(report.contains("Warning: shifts with x != y behave differently in chipper"));
+    }
+
+    #[test]
+    fn extended_platforms() {
+        // HIGH; DRW V0, V1, 0; EXIT
+        let analysis = Analysis::analyse(&[0x00, 0xFF, 0xD0, 0x10, 0x00, 0xFD]);
+        assert_eq!(analysis.platform(), Platform::SuperChip);
+        assert_eq!(analysis.extensions.len(), 3);
+
+        // SE V0, 0; LD I, long 0x1234; PLANE 3; EXIT
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0xF3, 0x01, 0x00, 0xFD];
+        let analysis = Analysis::analyse(&rom);
+        assert_eq!(analysis.platform(), Platform::XoChip);
+        // 0x1234 is never mistaken for a jump, the skip going over the whole of F000
+        assert!(analysis.unknown.is_empty());
+        assert_eq!(analysis.instructions, 4);
+        assert!(analysis.report().contains("XO-CHIP    Fn01       0x206"));
+    }
+}
@@ -0,0 +1,247 @@
+//! Profiler attributing executed instructions to subroutines
+//!
+//! A shadow call stack of `2nnn` targets is kept in sync with the depth of the cpu
+//! `Stack`: frames are pushed when it grows (pc then being the call target) and
+//! dropped when it shrinks. Counts are kept per distinct call stack, from which
+//! inclusive and exclusive counts derive, and which export as folded stacks
+//! (`main;sub_2A0;sub_31C 42` lines) for flamegraph tools. Subroutines are named
+//! after their label when debug symbols are loaded.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::cpu::CPU;
+use super::disasm;
+use super::memory::{Mem, RAM_SIZE};
+use super::symbols::Symbols;
+
+const SUMMARY_LINES: usize = 10; // Entries of each summary table
+
+/// Instruction counts per call stack and per address
+pub struct Profiler {
+    frames: Vec<u16>,                // Call targets, outermost first
+    stacks: BTreeMap<Vec<u16>, u64>, // Instructions executed with each call stack
+    pcs: Vec<u64>,                   // Instructions executed at each address
+    total: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts of a subroutine
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SubroutineCounts {
+    pub inclusive: u64, // Including the subroutines it calls
+    pub exclusive: u64,
+}
+
+/// Name of a subroutine in reports, `None` being the code outside of any call
+pub fn subroutine_name(target: Option<u16>, symbols: &Symbols) -> String {
+    match target {
+        Some(addr) => match symbols.label_at(addr) {
+            Some(label) => label.to_string(),
+            None => format!("sub_{:03X}", addr),
+        },
+        None => String::from("main"),
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            stacks: BTreeMap::new(),
+            pcs: vec![0; RAM_SIZE],
+            total: 0,
+        }
+    }
+
+    /// Counts the instruction the cpu is about to execute
+    pub fn record(&mut self, cpu: &CPU) {
+        let pc = cpu.registers().pc;
+        if pc as usize >= RAM_SIZE {
+            return; // Nothing gets executed
+        }
+        let depth = cpu.stack().len();
+        self.frames.truncate(depth);
+        while self.frames.len() < depth {
+            self.frames.push(pc);
+        }
+        match self.stacks.get_mut(self.frames.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.frames.clone(), 1);
+            }
+        }
+        self.pcs[pc as usize] += 1;
+        self.total += 1;
+    }
+
+    /// Instructions recorded so far
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Counts per subroutine, `None` being the code outside of any call
+    pub fn subroutines(&self) -> BTreeMap<Option<u16>, SubroutineCounts> {
+        let mut subroutines: BTreeMap<Option<u16>, SubroutineCounts> = BTreeMap::new();
+        for (stack, count) in &self.stacks {
+            subroutines
+                .entry(stack.last().copied())
+                .or_default()
+                .exclusive += count;
+            // Recursive subroutines only count once per stack
+            let mut seen: Vec<Option<u16>> = vec![None];
+            seen.extend(stack.iter().map(|target| Some(*target)));
+            seen.sort_unstable();
+            seen.dedup();
+            for target in seen {
+                subroutines.entry(target).or_default().inclusive += count;
+            }
+        }
+        subroutines
+    }
+
+    /// Addresses executed the most, with their counts
+    pub fn hottest(&self, len: usize) -> Vec<(u16, u64)> {
+        let mut pcs: Vec<(u16, u64)> = (0..RAM_SIZE)
+            .filter(|pc| self.pcs[*pc] > 0)
+            .map(|pc| (pc as u16, self.pcs[pc]))
+            .collect();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pcs.truncate(len);
+        pcs
+    }
+
+    /// One `frame;frame;... count` line per call stack
+    pub fn folded(&self, symbols: &Symbols) -> String {
+        let mut out = String::new();
+        for (stack, count) in &self.stacks {
+            let mut frames = vec![subroutine_name(None, symbols)];
+            frames.extend(
+                stack
+                    .iter()
+                    .map(|target| subroutine_name(Some(*target), symbols)),
+            );
+            writeln!(out, "{} {}", frames.join(";"), count).unwrap();
+        }
+        out
+    }
+
+    /// Tables of the heaviest subroutines and hottest addresses, mem giving the mnemonics
+    pub fn summary(&self, mem: &Mem, symbols: &Symbols) -> String {
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+        let mut out = format!("Profile: {} instruction(s) executed\n", self.total);
+        let mut subroutines: Vec<(Option<u16>, SubroutineCounts)> =
+            self.subroutines().into_iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+        writeln!(out, "\n{:>20} {:>20}  subroutine", "inclusive", "exclusive").unwrap();
+        for (target, counts) in subroutines.iter().take(SUMMARY_LINES) {
+            writeln!(
+                out,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                counts.inclusive,
+                percent(counts.inclusive),
+                counts.exclusive,
+                percent(counts.exclusive),
+                subroutine_name(*target, symbols)
+            )
+            .unwrap();
+        }
+        writeln!(out, "\n{:>20}  address", "count").unwrap();
+        for (pc, count) in self.hottest(SUMMARY_LINES) {
+            let mnemonic = mem
+                .read_word(pc as usize)
+                .map(disasm::mnemonic)
+                .unwrap_or_default();
+            write!(
+                out,
+                "{:>12} {:>6.2}%  0x{:03X}  {}",
+                count,
+                percent(count),
+                pc,
+                mnemonic
+            )
+            .unwrap();
+            match symbols.locate(pc) {
+                Some(location) => writeln!(out, "  ; {}", location).unwrap(),
+                None => out.push('\n'),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profiler, SubroutineCounts};
+    use crate::chip8::cpu::CPU;
+    use crate::chip8::input::KeyBoard;
+    use crate::chip8::memory::Mem;
+    use crate::chip8::symbols::Symbols;
+
+    fn profile(rom: Vec<u8>, ticks: usize) -> Profiler {
+        let kb = KeyBoard::new();
+        let mut cpu = CPU::new(Mem::new(rom));
+        let mut profiler = Profiler::new();
+        for _ in 0..ticks {
+            profiler.record(&cpu);
+            cpu.tick(&kb);
+        }
+        profiler
+    }
+
+    #[test]
+    fn call_stacks() {
+        // 0x200: CALL 0x206; JP 0x200; (pad)
+        // 0x206: ADD V0, 1; CALL 0x20C; RET
+        // 0x20C: ADD V1, 1; RET
+        let rom = vec![
+            0x22, 0x06, 0x12, 0x00, 0x00, 0x00, 0x70, 0x01, 0x22, 0x0C, 0x00, 0xEE, 0x71, 0x01,
+            0x00, 0xEE,
+        ];
+        // One loop is 2 instructions in main, 3 in 0x206 and 2 in 0x20C
+        let profiler = profile(rom, 14);
+        assert_eq!(profiler.total(), 14);
+        let subroutines = profiler.subroutines();
+        assert_eq!(
+            subroutines[&None],
+            SubroutineCounts {
+                inclusive: 14,
+                exclusive: 4
+            }
+        );
+        assert_eq!(
+            subroutines[&Some(0x206)],
+            SubroutineCounts {
+                inclusive: 10,
+                exclusive: 6
+            }
+        );
+        assert_eq!(subroutines[&Some(0x20C)].exclusive, 4);
+        assert_eq!(
+            profiler.folded(&Symbols::new()),
+            "main 4\nmain;sub_206 6\nmain;sub_206;sub_20C 4\n"
+        );
+        let symbols = Symbols::parse("label 0x206 update\nline 0x200 game.8o:1").unwrap();
+        assert_eq!(
+            profiler.folded(&symbols),
+            "main 4\nmain;update 6\nmain;update;sub_20C 4\n"
+        );
+        assert_eq!(profiler.hottest(1), vec![(0x200, 2)]);
+    }
+
+    #[test]
+    fn recursion_counts_once() {
+        // 0x200: CALL 0x202; 0x202: CALL 0x202 ...
+        let profiler = profile(vec![0x22, 0x02, 0x22, 0x02], 5);
+        let subroutines = profiler.subroutines();
+        assert_eq!(subroutines[&Some(0x202)].inclusive, 4);
+        assert_eq!(subroutines[&None].inclusive, 5);
+        assert!(profiler
+            .summary(&Mem::new(vec![]), &Symbols::new())
+            .contains("sub_202"));
+    }
+}
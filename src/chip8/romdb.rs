@@ -0,0 +1,270 @@
+//! Metadata of known roms, keyed by the SHA-1 of their content
+//!
+//! Entries follow the `programs.json` schema of the community chip-8-database
+//! (https://github.com/chip-8/chip-8-database): programs list their roms by hash,
+//! each with the platforms it runs on, quirk overrides, speed, keys and colours.
+//! A database of the bundled roms is embedded, local files can be merged over it.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Deserialize;
+
+use super::platform::Quirks;
+
+const EMBEDDED: &str = include_str!("../../data/programs.json");
+
+/// A game or program, possibly released as several roms
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Program {
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub roms: BTreeMap<String, RomInfo>, // By SHA-1
+}
+
+/// How to run one rom of a program
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RomInfo {
+    pub file: Option<String>,
+    pub tickrate: Option<u32>, // Instructions per frame
+    #[serde(default)]
+    pub platforms: Vec<String>, // Platform ids, preferred first
+    #[serde(default)]
+    pub quirky_platforms: BTreeMap<String, BTreeMap<String, bool>>, // Quirks per platform id
+    #[serde(default)]
+    pub keys: BTreeMap<String, u8>, // Hexadecimal key of each action, as `"left": 5`
+    pub colors: Option<Colors>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Colors {
+    #[serde(default)]
+    pub pixels: Vec<String>, // `#rrggbb` of unset then set pixels (more for XO-CHIP planes)
+    pub buzzer: Option<String>,
+    pub silence: Option<String>,
+}
+
+/// Programs, and which of their roms each hash belongs to
+#[derive(Debug, Default)]
+pub struct RomDb {
+    programs: Vec<Program>,
+    hashes: BTreeMap<String, usize>, // Index of the program of each rom
+}
+
+/// What the database knows about a rom
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    pub program: &'a Program,
+    pub rom: &'a RomInfo,
+}
+
+impl RomDb {
+    /// The database of the bundled roms
+    pub fn embedded() -> Self {
+        Self::parse(EMBEDDED).expect("Embedded rom database is valid")
+    }
+
+    /// Reads a `programs.json` array
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let mut db = RomDb::default();
+        for program in programs {
+            for (hash, rom) in &program.roms {
+                let colors = rom.colors.iter().flat_map(|colors| &colors.pixels);
+                if let Some(color) = colors.clone().find(|color| parse_color(color).is_none()) {
+                    return Err(format!("Invalid colour {} for {}", color, program.title));
+                }
+                db.hashes.insert(hash.to_lowercase(), db.programs.len());
+            }
+            db.programs.push(program);
+        }
+        Ok(db)
+    }
+
+    /// Adds other's programs, its roms taking precedence over those already known
+    pub fn merge(&mut self, other: RomDb) {
+        let offset = self.programs.len();
+        self.programs.extend(other.programs);
+        for (hash, index) in other.hashes {
+            self.hashes.insert(hash, index + offset);
+        }
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<Entry<'_>> {
+        self.lookup_hash(&sha1_smol::Sha1::from(rom).digest().to_string())
+    }
+
+    pub fn lookup_hash(&self, sha1: &str) -> Option<Entry<'_>> {
+        let sha1 = sha1.to_lowercase();
+        let program = &self.programs[*self.hashes.get(&sha1)?];
+        let rom = program
+            .roms
+            .iter()
+            .find(|(hash, _)| hash.to_lowercase() == sha1)
+            .map(|(_, rom)| rom)?;
+        Some(Entry { program, rom })
+    }
+}
+
+impl Entry<'_> {
+    /// Id of the platform the rom is best run as
+    pub fn platform(&self) -> Option<&str> {
+        self.rom.platforms.first().map(String::as_str)
+    }
+
+    /// Quirks of the rom's platform, with the rom's own overrides: advisory, chipper's cpu
+    /// always running with `CHIPPER_QUIRKS`
+    pub fn quirks(&self) -> Option<Quirks> {
+        let platform = self.platform()?;
+        let mut quirks = platform_quirks(platform)?.0;
+        for (quirk, on) in self
+            .rom
+            .quirky_platforms
+            .get(platform)
+            .into_iter()
+            .flatten()
+        {
+            match quirk.as_str() {
+                "shift" => quirks.shift_vx = *on,
+                "memoryLeaveIUnchanged" => quirks.memory_increments_i = !on,
+                "jump" => quirks.jump_vx = *on,
+                "logic" => quirks.vf_reset = *on,
+                "wrap" => quirks.clip = !on,
+                "vblank" => quirks.display_wait = *on,
+                _ => (), // memoryIncrementByX: I still gets incremented
+            }
+        }
+        Some(quirks)
+    }
+
+    /// Instructions per frame, the platform's usual one unless the rom has a valid one
+    pub fn tickrate(&self) -> Option<u32> {
+        let valid = |tickrate: &u32| (1..=MAX_TICKRATE).contains(tickrate);
+        let platform = || platform_quirks(self.platform()?).map(|(_, tickrate)| tickrate);
+        self.rom.tickrate.filter(valid).or_else(platform)
+    }
+
+    /// Colours of unset and set pixels
+    pub fn palette(&self) -> Option<[u32; 2]> {
+        let pixels = &self.rom.colors.as_ref()?.pixels;
+        match pixels.as_slice() {
+            [off, on, ..] => Some([parse_color(off)?, parse_color(on)?]),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program.title)?;
+        if let Some(release) = &self.program.release {
+            write!(f, " ({})", release)?;
+        }
+        if !self.program.authors.is_empty() {
+            write!(f, " by {}", self.program.authors.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// Highest tickrate taken from a database, still a period of over 10 ns per instruction
+const MAX_TICKRATE: u32 = 1_000_000;
+
+// Community database platforms: id, tickrate, then whether they shift vx, increment I,
+// jump to xnn + vx, reset vF, clip sprites and wait for the display
+const PLATFORMS: [(&str, u32, [bool; 6]); 7] = [
+    ("originalChip8", 15, [false, true, false, true, true, true]),
+    ("hybridVIP", 15, [false, true, false, true, true, true]),
+    ("modernChip8", 12, [false, true, false, false, true, false]),
+    ("chip48", 30, [true, true, true, false, true, false]),
+    ("superchip1", 30, [true, false, true, false, true, false]),
+    ("superchip", 30, [true, false, true, false, true, false]),
+    ("xochip", 100, [false, true, false, false, false, false]),
+];
+
+fn platform_quirks(id: &str) -> Option<(Quirks, u32)> {
+    let (name, tickrate, flags) = PLATFORMS.iter().find(|(name, ..)| *name == id)?;
+    let [shift_vx, memory_increments_i, jump_vx, vf_reset, clip, display_wait] = *flags;
+    let quirks = Quirks {
+        name,
+        shift_vx,
+        memory_increments_i,
+        jump_vx,
+        vf_reset,
+        clip,
+        display_wait,
+    };
+    Some((quirks, *tickrate))
+}
+
+/// `#rrggbb` as a 0RGB pixel
+fn parse_color(color: &str) -> Option<u32> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RomDb;
+    use crate::chip8::platform::CHIPPER_QUIRKS;
+
+    #[test]
+    fn embedded_roms() {
+        let db = RomDb::embedded();
+        let tetris = std::fs::read("roms/TETRIS").unwrap();
+        let entry = db.lookup(&tetris).expect("Tetris is known");
+        assert_eq!(entry.to_string(), "Tetris (1991) by Fran Dachille");
+        assert_eq!(entry.rom.keys["left"], 5);
+        assert_eq!(entry.tickrate(), Some(15));
+        assert_eq!(entry.quirks().unwrap().name, "originalChip8");
+        assert!(db.lookup(&[0x12, 0x00]).is_none());
+    }
+
+    #[test]
+    fn local_overrides() {
+        let json = r##"[{
+            "title": "Tetris deluxe",
+            "authors": ["someone"],
+            "roms": {
+                "5F518084744BF3CB8733F6E5454DFD1634320563": {
+                    "platforms": ["superchip"],
+                    "tickrate": 40,
+                    "quirkyPlatforms": { "superchip": { "memoryLeaveIUnchanged": false } },
+                    "colors": { "pixels": ["#000000", "#ffaa00"], "buzzer": "#990000" },
+                    "unknownField": 3
+                }
+            }
+        }]"##;
+        let mut db = RomDb::embedded();
+        db.merge(RomDb::parse(json).unwrap());
+        let entry = db
+            .lookup_hash("5f518084744bf3cb8733f6e5454dfd1634320563")
+            .unwrap();
+        assert_eq!(entry.to_string(), "Tetris deluxe by someone");
+        assert_eq!(entry.tickrate(), Some(40));
+        assert_eq!(entry.palette(), Some([0x000000, 0xFFAA00]));
+        let quirks = entry.quirks().unwrap();
+        assert!(quirks.shift_vx && quirks.memory_increments_i);
+        assert_eq!(
+            quirks.differences(&CHIPPER_QUIRKS),
+            ["jump xnn + vx", "clip: yes"]
+        );
+
+        let fast = r#"[{ "title": "t", "roms": { "00": {
+            "platforms": ["chip48"], "tickrate": 4294967295 } } }]"#;
+        db.merge(RomDb::parse(fast).unwrap());
+        assert_eq!(db.lookup_hash("00").unwrap().tickrate(), Some(30));
+
+        let invalid =
+            r#"[{ "title": "t", "roms": { "00": { "colors": { "pixels": ["red"] } } } }]"#;
+        assert_eq!(
+            RomDb::parse(invalid).unwrap_err(),
+            "Invalid colour red for t"
+        );
+        assert!(RomDb::parse("{}").is_err());
+    }
+}
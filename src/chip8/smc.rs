@@ -0,0 +1,169 @@
+//! Self-modifying code detection
+//!
+//! Flags `Fx33`/`Fx55` writes to addresses that were executed before, and fetches of
+//! instructions whose bytes were written at runtime, with the pc of the writer. Each
+//! finding is reported once, in the order it was first seen.
+use std::collections::HashSet;
+use std::fmt;
+
+use super::cpu::CPU;
+use super::memory::{Access, RAM_SIZE};
+
+/// Code and data overlapping at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Finding {
+    /// The instruction at writer wrote over addr, which had been executed
+    CodeWritten { writer: u16, addr: u16 },
+    /// The instruction at pc was fetched from bytes written by the instruction at writer
+    WrittenCodeExecuted { pc: u16, writer: u16 },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::CodeWritten { writer, addr } => write!(
+                f,
+                "0x{:03X} wrote to 0x{:03X}, which was executed before",
+                writer, addr
+            ),
+            Finding::WrittenCodeExecuted { pc, writer } => write!(
+                f,
+                "0x{:03X} executed an instruction written by 0x{:03X}",
+                pc, writer
+            ),
+        }
+    }
+}
+
+/// Watches executed instructions for code and data overlaps
+pub struct SmcDetector {
+    executed: Vec<bool>,
+    writers: Vec<Option<u16>>, // Pc of the last instruction writing each address
+    findings: Vec<Finding>,
+    seen: HashSet<Finding>,
+}
+
+impl Default for SmcDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SmcDetector {
+    pub fn new() -> Self {
+        Self {
+            executed: vec![false; RAM_SIZE],
+            writers: vec![None; RAM_SIZE],
+            findings: Vec::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Pc of the instruction that last wrote addr at runtime, if any
+    pub fn writer(&self, addr: usize) -> Option<u16> {
+        self.writers.get(addr).copied().flatten()
+    }
+
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    fn report(&mut self, finding: Finding) {
+        if self.seen.insert(finding) {
+            self.findings.push(finding);
+        }
+    }
+
+    /// Checks the instruction the cpu is about to execute
+    pub fn record(&mut self, cpu: &CPU) {
+        let pc = cpu.registers().pc;
+        let instruction = match cpu.fetch(pc) {
+            Some(instruction) => instruction,
+            None => return, // Nothing gets executed
+        };
+        let fetched = pc as usize..pc as usize + 2;
+        if let Some(writer) = fetched.clone().find_map(|addr| self.writers[addr]) {
+            self.report(Finding::WrittenCodeExecuted { pc, writer });
+        }
+        for addr in fetched {
+            self.executed[addr] = true;
+        }
+        if let Some((addr, len, Access::Write)) = cpu.data_access(instruction) {
+            for addr in (addr..addr + len).take_while(|addr| *addr < RAM_SIZE) {
+                if self.executed[addr] {
+                    self.report(Finding::CodeWritten {
+                        writer: pc,
+                        addr: addr as u16,
+                    });
+                }
+                self.writers[addr] = Some(pc);
+            }
+        }
+    }
+
+    /// Lists the findings, one per line
+    pub fn report_text(&self) -> String {
+        if self.findings.is_empty() {
+            return String::from("No self-modifying code detected\n");
+        }
+        let mut out = format!("{} self-modifying code finding(s):\n", self.findings.len());
+        for finding in &self.findings {
+            out.push_str(&format!("  {}\n", finding));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Finding, SmcDetector};
+    use crate::chip8::cpu::CPU;
+    use crate::chip8::input::KeyBoard;
+    use crate::chip8::memory::Mem;
+
+    #[test]
+    fn patched_instruction() {
+        // LD V0, 0x12; LD V1, 0x0A; LD I, 0x208; LD [I], V1; (patched to JP 0x20A); JP 0x20A
+        let rom = vec![
+            0x60, 0x12, 0x61, 0x0A, 0xA2, 0x08, 0xF1, 0x55, 0x00, 0x00, 0x12, 0x0A,
+        ];
+        let kb = KeyBoard::new();
+        let mut cpu = CPU::new(Mem::new(rom));
+        let mut detector = SmcDetector::new();
+        for _ in 0..6 {
+            detector.record(&cpu);
+            cpu.tick(&kb);
+        }
+        assert_eq!(detector.writer(0x208), Some(0x206));
+        assert_eq!(cpu.registers().pc, 0x20A);
+        assert_eq!(
+            detector.findings(),
+            [Finding::WrittenCodeExecuted {
+                pc: 0x208,
+                writer: 0x206
+            }]
+        );
+
+        // Writing over already executed code: LD I, 0x200 then LD [I], V1
+        let mut detector = SmcDetector::new();
+        let mut cpu = CPU::new(Mem::new(vec![0xA2, 0x00, 0xF1, 0x55]));
+        for _ in 0..2 {
+            detector.record(&cpu);
+            cpu.tick(&kb);
+        }
+        assert_eq!(
+            detector.findings(),
+            [
+                Finding::CodeWritten {
+                    writer: 0x202,
+                    addr: 0x200
+                },
+                Finding::CodeWritten {
+                    writer: 0x202,
+                    addr: 0x201
+                }
+            ]
+        );
+        assert!(detector.report_text().contains("0x202 wrote to 0x200"));
+    }
+}
@@ -0,0 +1,165 @@
+//! Debug symbols: labels, source lines and breakpoints of assembled roms
+//!
+//! `chipper asm --symbols` writes them one entry per line (`#` starts a comment):
+//!
+//! ```text
+//! label 0x2A0 draw-piece
+//! line 0x2A4 tetris.8o:120
+//! break 0x2A4 draw-piece
+//! ```
+//!
+//! Bare `<addr> <file>:<line>` lines are read as `line` entries, as older line maps
+//! were written. Addresses get described relative to the nearest label at or before
+//! them, e.g. `draw-piece+0x4 (tetris.8o:120)`.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::debugger::parse_number;
+
+/// Labels, source locations and breakpoints, keyed by address
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+    lines: BTreeMap<u16, String>, // `file:line` of the source of each address
+    breakpoints: BTreeMap<u16, String>, // `:breakpoint` directives, with their names
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty() && self.breakpoints.is_empty()
+    }
+
+    /// Names addr, the first label given for an address being kept
+    pub fn add_label(&mut self, addr: u16, name: &str) {
+        self.labels.entry(addr).or_insert_with(|| name.to_string());
+    }
+
+    pub fn add_line(&mut self, addr: u16, location: &str) {
+        self.lines.insert(addr, location.to_string());
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16, name: &str) {
+        self.breakpoints.insert(addr, name.to_string());
+    }
+
+    /// The label defined exactly at addr
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// The nearest label at or before addr, with the offset from it
+    pub fn label(&self, addr: u16) -> Option<(&str, u16)> {
+        let (base, name) = self.labels.range(..=addr).next_back()?;
+        Some((name, addr - base))
+    }
+
+    /// Source location of addr, from the nearest line at or before it
+    pub fn line(&self, addr: u16) -> Option<&str> {
+        self.lines
+            .range(..=addr)
+            .next_back()
+            .map(|(_, location)| location.as_str())
+    }
+
+    /// Source locations, keyed by their first address
+    pub fn lines(&self) -> &BTreeMap<u16, String> {
+        &self.lines
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<u16, String> {
+        &self.breakpoints
+    }
+
+    /// Symbolic description of addr, if any symbol covers it
+    pub fn locate(&self, addr: u16) -> Option<String> {
+        let label = self.label(addr).map(|(name, offset)| match offset {
+            0 => name.to_string(),
+            _ => format!("{}+0x{:X}", name, offset),
+        });
+        match (label, self.line(addr)) {
+            (Some(label), Some(line)) => Some(format!("{} ({})", label, line)),
+            (Some(label), None) => Some(label),
+            (None, Some(line)) => Some(format!("0x{:03X} ({})", addr, line)),
+            (None, None) => None,
+        }
+    }
+
+    /// addr as `label+0x4 (file:line)`, or in hexadecimal without symbols
+    pub fn describe(&self, addr: u16) -> String {
+        self.locate(addr)
+            .unwrap_or_else(|| format!("0x{:03X}", addr))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Self::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("Invalid symbols entry: {}", line);
+            let mut fields = line.split_whitespace();
+            let kind = fields.next().ok_or_else(invalid)?;
+            let (kind, addr) = match kind {
+                "label" | "line" | "break" => (kind, fields.next().ok_or_else(invalid)?),
+                _ => ("line", kind),
+            };
+            let addr = parse_number(addr)?;
+            let value = fields.next();
+            if fields.next().is_some() {
+                return Err(invalid());
+            }
+            match (kind, value) {
+                ("label", Some(name)) => symbols.add_label(addr, name),
+                ("line", Some(location)) => symbols.add_line(addr, location),
+                ("break", name) => symbols.add_breakpoint(addr, name.unwrap_or_default()),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Text parsed back by `parse`
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for (addr, name) in &self.labels {
+            writeln!(out, "label 0x{:03X} {}", addr, name).unwrap();
+        }
+        for (addr, location) in &self.lines {
+            writeln!(out, "line 0x{:03X} {}", addr, location).unwrap();
+        }
+        for (addr, name) in &self.breakpoints {
+            writeln!(out, "break 0x{:03X} {}", addr, name).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Symbols;
+
+    #[test]
+    fn describes_addresses() {
+        let text = "label 0x200 main\nlabel 0x2A0 draw-piece\n\
+                    line 0x2A0 tetris.8o:118\nline 0x2A4 tetris.8o:120 # v0 := 1\n\
+                    break 0x2A4 check\n";
+        let symbols = Symbols::parse(text).unwrap();
+        assert_eq!(symbols.describe(0x2A6), "draw-piece+0x6 (tetris.8o:120)");
+        assert_eq!(symbols.describe(0x2A0), "draw-piece (tetris.8o:118)");
+        assert_eq!(symbols.describe(0x202), "main+0x2");
+        assert_eq!(symbols.describe(0x1FE), "0x1FE");
+        assert_eq!(symbols.label_at(0x2A0), Some("draw-piece"));
+        assert_eq!(symbols.breakpoints()[&0x2A4], "check");
+        assert_eq!(Symbols::parse(&symbols.to_text()).unwrap(), symbols);
+
+        let legacy = Symbols::parse("0x200 game.8o:3").unwrap();
+        assert_eq!(legacy.describe(0x200), "0x200 (game.8o:3)");
+        assert!(Symbols::parse("0x200").is_err());
+        assert!(Symbols::parse("label 0x200").is_err());
+    }
+}
@@ -0,0 +1,273 @@
+//! Execution trace logging, one line per executed instruction
+//!
+//! Each line holds the machine state *before* the instruction runs:
+//!
+//! ```text
+//! cycle=1 PC=0200 OP=6310 V0=00 V1=00 .. VF=00 I=0000 SP=0 DT=00 ST=00 ; LD V3, 0x10
+//! cycle=9 PC=0210 OP=F155 V0=01 .. VF=00 I=0300 SP=0 DT=00 ST=00 W=0300:01,0301:02 ; LD [I], V1
+//! ```
+//!
+//! Fields are space separated `KEY=value` pairs in that order, values are hexadecimal
+//! except for the decimal cycle count and call depth. `W` is only present for
+//! instructions writing to ram (`Fx33`, `Fx55`) and lists the written `addr:byte`
+//! pairs. Everything after ` ; ` is the disassembled mnemonic, informative only,
+//! followed by ` @ draw-piece+0x4 (tetris.8o:120)` when debug symbols cover the pc.
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use super::cpu::{CpuState, CPU};
+use super::disasm;
+use super::memory::Access;
+use super::symbols::Symbols;
+
+// Line of the instruction being executed, completed once its writes are known
+struct Pending {
+    fields: String,
+    mnemonic: String,
+    writes: Option<(usize, usize)>, // Address and length of the ram written to
+}
+
+/// Writes a trace line for every instruction executed by the cpu it is fed
+pub struct Tracer<W: Write = Box<dyn Write + Send>> {
+    out: W,
+    cycle: u64,
+    range: Option<RangeInclusive<u16>>, // Only instructions at these addresses are traced
+    ring: Option<(usize, VecDeque<String>)>, // Last lines, only written on errors
+    symbols: Symbols,
+    pending: Option<Pending>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            cycle: 0,
+            range: None,
+            ring: None,
+            symbols: Symbols::new(),
+            pending: None,
+        }
+    }
+
+    /// Only traces instructions whose address is within range
+    pub fn set_range(&mut self, range: RangeInclusive<u16>) {
+        self.range = Some(range);
+    }
+
+    /// Keeps the last len lines in memory, writing them only when execution fails
+    pub fn set_ring(&mut self, len: usize) {
+        self.ring = Some((len, VecDeque::with_capacity(len)));
+    }
+
+    /// Names the traced addresses
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Instructions seen so far, traced or not
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Records the instruction the cpu is about to execute
+    pub fn record(&mut self, cpu: &CPU) {
+        let pc = cpu.registers().pc;
+        let instruction = match cpu.fetch(pc) {
+            Some(instruction) => instruction,
+            None => return, // Nothing gets executed
+        };
+        self.cycle += 1;
+        if let Some(range) = &self.range {
+            if !range.contains(&pc) {
+                return;
+            }
+        }
+        let writes = match cpu.data_access(instruction) {
+            Some((addr, len, Access::Write)) => Some((addr, len)),
+            _ => None,
+        };
+        let mut mnemonic = disasm::mnemonic(instruction);
+        if let Some(location) = self.symbols.locate(pc) {
+            mnemonic.push_str(&format!(" @ {}", location));
+        }
+        self.pending = Some(Pending {
+            fields: trace_fields(self.cycle, cpu, instruction),
+            mnemonic,
+            writes,
+        });
+    }
+
+    /// Writes the line of the recorded instruction once executed, then the ring and the error
+    /// on errors, flushed for the process may exit right after
+    pub fn after(&mut self, cpu: &CPU, state: &CpuState) -> io::Result<()> {
+        if let Some(pending) = self.pending.take() {
+            let mut line = pending.fields;
+            if let Some((addr, len)) = pending.writes {
+                let writes: Vec<String> = (addr..addr + len)
+                    .filter_map(|addr| {
+                        Some(format!("{:04X}:{:02X}", addr, cpu.mem().read_byte(addr)?))
+                    })
+                    .collect();
+                line.push_str(&format!(" W={}", writes.join(",")));
+            }
+            line.push_str(&format!(" ; {}", pending.mnemonic));
+            self.emit(line)?;
+        }
+        if let CpuState::Error(err) = state {
+            if let Some((_, ring)) = &mut self.ring {
+                for line in ring.drain(..) {
+                    writeln!(self.out, "{}", line)?;
+                }
+            }
+            writeln!(self.out, "error: {}", err)?;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, line: String) -> io::Result<()> {
+        match &mut self.ring {
+            Some((len, ring)) => {
+                if ring.len() == *len {
+                    ring.pop_front();
+                }
+                if *len > 0 {
+                    ring.push_back(line);
+                }
+                Ok(())
+            }
+            None => writeln!(self.out, "{}", line),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Formats the state fields of an instruction about to be executed by the cpu
+fn trace_fields(cycle: u64, cpu: &CPU, instruction: u16) -> String {
+    let registers = cpu.registers();
+    let v: Vec<String> = registers
+        .v
+        .iter()
+        .enumerate()
+        .map(|(idx, val)| format!("V{:X}={:02X}", idx, val))
+        .collect();
+    format!(
+        "cycle={} PC={:04X} OP={:04X} {} I={:04X} SP={} DT={:02X} ST={:02X}",
+        cycle,
+        registers.pc,
+        instruction,
+        v.join(" "),
+        registers.i,
+        cpu.stack().len(),
+        registers.dt,
+        registers.st
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tracer;
+    use crate::chip8::cpu::{CpuState, CPU};
+    use crate::chip8::input::KeyBoard;
+    use crate::chip8::memory::Mem;
+    use crate::chip8::symbols::Symbols;
+    use std::io::BufWriter;
+
+    // Traces every tick of the rom until it errors or max ticks are done
+    fn run(rom: Vec<u8>, mut tracer: Tracer<Vec<u8>>, max: usize) -> Vec<String> {
+        let kb = KeyBoard::new();
+        let mut cpu = CPU::new(Mem::new(rom));
+        for _ in 0..max {
+            tracer.record(&cpu);
+            let state = cpu.tick(&kb);
+            tracer.after(&cpu, &state).unwrap();
+            if let CpuState::Error(_) = state {
+                break;
+            }
+        }
+        let out = String::from_utf8(tracer.into_inner()).unwrap();
+        out.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn trace_lines() {
+        // LD V3, 0x10; LD I, 0x300
+        let lines = run(vec![0x63, 0x10, 0xA3, 0x00], Tracer::new(Vec::new()), 2);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("cycle=1 PC=0200 OP=6310 V0=00"));
+        assert!(lines[0].ends_with("I=0000 SP=0 DT=00 ST=00 ; LD V3, 0x10"));
+        assert!(lines[1].starts_with("cycle=2 PC=0202 OP=A300"));
+        assert!(lines[1].contains("V3=10"));
+
+        let mut tracer = Tracer::new(Vec::new());
+        tracer.set_symbols(Symbols::parse("label 0x200 main\nline 0x202 game.8o:2").unwrap());
+        let lines = run(vec![0x63, 0x10, 0xA3, 0x00], tracer, 2);
+        assert!(lines[0].ends_with("; LD V3, 0x10 @ main"));
+        assert!(lines[1].ends_with("; LD I, 0x300 @ main+0x2 (game.8o:2)"));
+    }
+
+    #[test]
+    fn memory_writes() {
+        // LD V0, 1; LD V1, 2; LD I, 0x300; LD [I], V1
+        let rom = vec![0x60, 0x01, 0x61, 0x02, 0xA3, 0x00, 0xF1, 0x55];
+        let lines = run(rom, Tracer::new(Vec::new()), 4);
+        assert!(!lines[2].contains("W="));
+        assert!(lines[3].ends_with("I=0300 SP=0 DT=00 ST=00 W=0300:01,0301:02 ; LD [I], V1"));
+    }
+
+    #[test]
+    fn range_filter() {
+        // JP 0x204; (skipped); LD V0, 1; JP 0x200
+        let mut tracer = Tracer::new(Vec::new());
+        tracer.set_range(0x204..=0x205);
+        let lines = run(
+            vec![0x12, 0x04, 0x00, 0x00, 0x60, 0x01, 0x12, 0x00],
+            tracer,
+            6,
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("cycle=2 PC=0204"));
+        assert!(lines[1].starts_with("cycle=5 PC=0204"));
+    }
+
+    #[test]
+    fn ring_only_flushed_on_error() {
+        // ADD V0, 1 x3; invalid opcode
+        let rom = vec![0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x01, 0x23];
+        let mut tracer = Tracer::new(Vec::new());
+        tracer.set_ring(2);
+        let lines = run(rom.clone(), tracer, 10);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("cycle=3 PC=0204"));
+        assert!(lines[1].starts_with("cycle=4 PC=0206 OP=0123"));
+        assert!(lines[2].starts_with("error: "));
+
+        let mut tracer = Tracer::new(Vec::new());
+        tracer.set_ring(2);
+        assert!(run(rom, tracer, 3).is_empty());
+    }
+
+    #[test]
+    fn flushed_on_error() {
+        // ADD V0, 1; invalid opcode
+        let kb = KeyBoard::new();
+        let mut cpu = CPU::new(Mem::new(vec![0x70, 0x01, 0x01, 0x23]));
+        let mut tracer = Tracer::new(BufWriter::new(Vec::new()));
+        for _ in 0..2 {
+            tracer.record(&cpu);
+            let state = cpu.tick(&kb);
+            tracer.after(&cpu, &state).unwrap();
+        }
+        let out = tracer.into_inner();
+        assert!(out.buffer().is_empty());
+        let out = String::from_utf8(out.get_ref().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("cycle=2 PC=0202 OP=0123"));
+        assert!(lines[2].starts_with("error: "));
+    }
+}
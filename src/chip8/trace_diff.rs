@@ -0,0 +1,284 @@
+//! Comparison of two execution traces, finding the first instruction where two runs diverge
+//!
+//! Traces are expected in the format written by `trace::Tracer`, which is also how
+//! logs from other interpreters can be compared: one line per executed instruction,
+//! made of space separated `KEY=value` fields starting with `cycle=<decimal>`.
+//! Only the fields present in both lines among `PC`, `OP`, `V0`-`VF`, `I`, `SP`,
+//! `DT`, `ST` and `W` (ram writes) are compared, numbers being hexadecimal except
+//! for SP. When both traces log writes, a line without `W` means nothing was
+//! written. Lines not starting with `cycle=` and anything after ` ; ` are ignored.
+use std::fmt;
+
+/// Fields compared between two lines of the same cycle, in reporting order
+pub const COMPARED_FIELDS: [&str; 23] = [
+    "PC", "OP", "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD",
+    "VE", "VF", "I", "SP", "DT", "ST", "W",
+];
+
+/// One executed instruction of a trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub cycle: u64,
+    pub line: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Entry {
+    /// Parses a trace line, `None` for lines that aren't instructions
+    pub fn parse(line: &str) -> Option<Self> {
+        let state = line.split(" ; ").next().unwrap_or_default();
+        let mut fields = state
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.to_ascii_uppercase(), value.to_string()));
+        let cycle = match fields.next() {
+            Some((key, value)) if key == "CYCLE" => value.parse().ok()?,
+            _ => return None,
+        };
+        Some(Self {
+            cycle,
+            line: line.trim_end().to_string(),
+            fields: fields.collect(),
+        })
+    }
+
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parses every instruction line of a trace
+pub fn parse_trace(trace: &str) -> Vec<Entry> {
+    trace.lines().filter_map(Entry::parse).collect()
+}
+
+// Values are compared as numbers when they are, so that `0A` matches `a`
+fn same_value(a: &str, b: &str) -> bool {
+    match (u64::from_str_radix(a, 16), u64::from_str_radix(b, 16)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.eq_ignore_ascii_case(b),
+    }
+}
+
+/// A field holding different values in both traces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub field: String,
+    pub a: String,
+    pub b: String,
+}
+
+/// The first point where two traces stop agreeing
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub cycle: u64,
+    /// Differing fields, empty when one of the traces ended before the other
+    pub differences: Vec<Difference>,
+    pub a_context: Vec<String>,
+    pub b_context: Vec<String>,
+    // Index of the divergent line in each context, if it has one
+    a_at: usize,
+    b_at: usize,
+}
+
+fn compare(a: &Entry, b: &Entry, writes: bool) -> Vec<Difference> {
+    COMPARED_FIELDS
+        .iter()
+        .filter_map(|field| {
+            let (va, vb) = match (a.field(field), b.field(field), *field) {
+                (Some(va), Some(vb), _) => (va, vb),
+                (va, vb, "W") if writes => (va.unwrap_or("-"), vb.unwrap_or("-")),
+                _ => return None,
+            };
+            (!same_value(va, vb)).then(|| Difference {
+                field: field.to_string(),
+                a: va.to_string(),
+                b: vb.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Aligns both traces by cycle and finds their first divergence, if any
+///
+/// Cycles missing from one of the traces (e.g. filtered out) are skipped.
+/// `context` lines of each trace are kept around the divergence.
+pub fn first_divergence(a: &[Entry], b: &[Entry], context: usize) -> Option<Divergence> {
+    let logs_writes = |trace: &[Entry]| trace.iter().any(|entry| entry.field("W").is_some());
+    let writes = logs_writes(a) && logs_writes(b);
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].cycle < b[j].cycle {
+            i += 1;
+        } else if a[i].cycle > b[j].cycle {
+            j += 1;
+        } else {
+            let differences = compare(&a[i], &b[j], writes);
+            if !differences.is_empty() {
+                return Some(divergence(a, b, i, j, a[i].cycle, differences, context));
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    if i == a.len() && j == b.len() {
+        return None;
+    }
+    // One trace goes on while the other stopped
+    let cycle = a.get(i).or_else(|| b.get(j)).map(|entry| entry.cycle)?;
+    Some(divergence(a, b, i, j, cycle, Vec::new(), context))
+}
+
+fn divergence(
+    a: &[Entry],
+    b: &[Entry],
+    i: usize,
+    j: usize,
+    cycle: u64,
+    differences: Vec<Difference>,
+    context: usize,
+) -> Divergence {
+    let lines = |trace: &[Entry], idx: usize| -> Vec<String> {
+        trace[idx.saturating_sub(context)..(idx + context + 1).min(trace.len())]
+            .iter()
+            .map(|entry| entry.line.clone())
+            .collect()
+    };
+    Divergence {
+        cycle,
+        differences,
+        a_context: lines(a, i),
+        b_context: lines(b, j),
+        a_at: context.min(i),
+        b_at: context.min(j),
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.differences.is_empty() {
+            writeln!(
+                f,
+                "Traces diverge at cycle {}: one of them ended",
+                self.cycle
+            )?;
+        } else {
+            writeln!(f, "Traces diverge at cycle {}:", self.cycle)?;
+            for difference in &self.differences {
+                writeln!(
+                    f,
+                    "  {}: {} != {}",
+                    difference.field, difference.a, difference.b
+                )?;
+            }
+        }
+        for (name, lines, at) in [
+            ("a", &self.a_context, self.a_at),
+            ("b", &self.b_context, self.b_at),
+        ] {
+            writeln!(f, "Context ({}):", name)?;
+            for (idx, line) in lines.iter().enumerate() {
+                let marker = if idx == at { '>' } else { ' ' };
+                writeln!(f, "{} {}", marker, line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{first_divergence, parse_trace, Difference, Entry};
+
+    fn line(cycle: u64, pc: u16, v0: u8, writes: &str) -> String {
+        format!(
+            "cycle={} PC={:04X} OP=6001 V0={:02X} I=0000 SP=0 DT=00 ST=00{} ; LD V0, 0x01",
+            cycle, pc, v0, writes
+        )
+    }
+
+    #[test]
+    fn parse_entries() {
+        let entry = Entry::parse(&line(12, 0x200, 1, "")).unwrap();
+        assert_eq!(entry.cycle, 12);
+        assert_eq!(entry.field("PC"), Some("0200"));
+        assert_eq!(entry.field("W"), None);
+        assert!(Entry::parse("error: Received an invalid opcode").is_none());
+        assert!(Entry::parse("").is_none());
+    }
+
+    #[test]
+    fn identical_traces() {
+        let trace = [line(1, 0x200, 0, ""), line(2, 0x202, 1, "")].join("\n");
+        let a = parse_trace(&trace);
+        assert!(first_divergence(&a, &a, 3).is_none());
+    }
+
+    #[test]
+    fn register_divergence() {
+        let a = parse_trace(
+            &[
+                line(1, 0x200, 0, ""),
+                line(2, 0x202, 1, ""),
+                line(3, 0x204, 1, ""),
+            ]
+            .join("\n"),
+        );
+        let b = parse_trace(
+            &[
+                line(1, 0x200, 0, ""),
+                line(2, 0x202, 2, ""),
+                line(3, 0x206, 1, ""),
+            ]
+            .join("\n"),
+        );
+        let divergence = first_divergence(&a, &b, 1).unwrap();
+        assert_eq!(divergence.cycle, 2);
+        assert_eq!(
+            divergence.differences,
+            vec![Difference {
+                field: String::from("V0"),
+                a: String::from("01"),
+                b: String::from("02")
+            }]
+        );
+        assert_eq!(divergence.a_context.len(), 3);
+        assert!(divergence.to_string().contains("> cycle=2 PC=0202"));
+    }
+
+    #[test]
+    fn write_divergence_and_other_formats() {
+        let a = parse_trace(&line(1, 0x200, 0, " W=0300:01"));
+        let b = parse_trace(&line(1, 0x200, 0, " W=0300:02"));
+        let divergence = first_divergence(&a, &b, 0).unwrap();
+        assert_eq!(divergence.differences[0].field, "W");
+
+        // Writes aren't compared with a log that has none, nor missing registers
+        let other = parse_trace("cycle=1 pc=200\ncycle=2 pc=20a");
+        let a = parse_trace(&[line(1, 0x200, 0, " W=0300:01"), line(2, 0x202, 0, "")].join("\n"));
+        let divergence = first_divergence(&a, &other, 0).unwrap();
+        assert_eq!(divergence.cycle, 2);
+        assert_eq!(divergence.differences.len(), 1);
+    }
+
+    #[test]
+    fn aligned_by_cycle() {
+        // b only traced odd cycles, then stopped
+        let a = parse_trace(
+            &[
+                line(1, 0x200, 0, ""),
+                line(2, 0x202, 0, ""),
+                line(3, 0x204, 0, ""),
+                line(4, 0x206, 0, ""),
+            ]
+            .join("\n"),
+        );
+        let b = parse_trace(&[line(1, 0x200, 0, ""), line(3, 0x204, 0, "")].join("\n"));
+        let divergence = first_divergence(&a, &b, 2).unwrap();
+        assert_eq!(divergence.cycle, 4);
+        assert!(divergence.differences.is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+//! Watching a rom file, to reload the rom as it gets rebuilt
+//!
+//! The file is polled: its modification time and length are checked first, the rom
+//! only being read again when they changed, and reported when its content did.
+use std::fs;
+use std::time::SystemTime;
+
+use super::loader;
+
+/// A rom file, with the rom last read from it
+#[derive(Debug)]
+pub struct Watcher {
+    path: String,
+    stamp: Option<(SystemTime, u64)>, // Modification time and length of the file
+    rom: Vec<u8>,
+}
+
+impl Watcher {
+    /// Watches the file at path (or archive, for an `archive.zip:entry`), rom being loaded
+    pub fn new(path: &str, rom: Vec<u8>) -> Self {
+        Self {
+            path: path.to_string(),
+            stamp: stamp(path),
+            rom,
+        }
+    }
+
+    /// The new rom when the file's content changed since the last poll
+    pub fn poll(&mut self) -> Option<Result<Vec<u8>, String>> {
+        let stamp = stamp(&self.path);
+        if stamp == self.stamp {
+            return None;
+        }
+        self.stamp = stamp;
+        match loader::read_rom(&self.path) {
+            Ok(rom) if rom == self.rom => None, // Only touched
+            Ok(rom) => {
+                self.rom = rom.clone();
+                Some(Ok(rom))
+            }
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+fn stamp(path: &str) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(loader::file_path(path)).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Watcher;
+    use std::fs;
+
+    #[test]
+    fn detects_changes() {
+        let path = std::env::temp_dir().join(format!("chipper-{}-watch.ch8", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, [0x12, 0x00]).unwrap();
+        let mut watcher = Watcher::new(path_str, vec![0x12, 0x00]);
+        assert_eq!(watcher.poll(), None);
+
+        // Lengths differ, the change being seen even within the mtime's resolution
+        fs::write(&path, [0x00, 0xE0, 0x12, 0x02]).unwrap();
+        assert_eq!(watcher.poll(), Some(Ok(vec![0x00, 0xE0, 0x12, 0x02])));
+        assert_eq!(watcher.poll(), None);
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(watcher.poll(), Some(Err(_))));
+        fs::write(&path, [0x00, 0xE0, 0x12, 0x02]).unwrap();
+        assert_eq!(watcher.poll(), None); // Back as it was
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,47 +1,653 @@
 use chipper::chip8::{
+    assembler::{cowgod, octo},
+    cfg::Cfg,
     cpu::CpuState,
-    display::{SCREEN_HEIGHT, SCREEN_WIDTH},
-    input::get_key_opcode,
+    debugger::{parse_number, Command, Debugger},
+    decompile::decompile,
+    disasm::{disassemble, disassemble_recursive, Syntax},
+    display::{DEFAULT_PALETTE, SCREEN_HEIGHT, SCREEN_WIDTH},
+    gdb::{GdbStub, Session},
+    heatmap::{Heatmap, HEATMAP_SIDE},
+    input::{get_key_opcode, keyboard_key},
+    launcher::Launcher,
+    loader::read_rom,
+    overlay::{self, DEBUG_HEIGHT, DEBUG_WIDTH},
+    platform::{Analysis, CHIPPER_QUIRKS},
+    profile::Profiler,
+    romdb::RomDb,
+    smc::SmcDetector,
+    symbols::Symbols,
+    trace::Tracer,
+    trace_diff::{first_divergence, parse_trace},
+    watch::Watcher,
     Interpreter,
 };
 use minifb::{Key, KeyRepeat, Window, WindowOptions};
-use std::time::{Duration, Instant};
-use std::{env, fs::File, io::Read};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-fn main() {
-    let filename: String = match env::args().nth(1) {
-        Some(filename) => filename,
-        None => String::from("roms/TETRIS"),
+const USAGE: &str = "\
+Usage: chipper [options] [rom | archive.zip[:rom] | directory, roms/ by default]
+       chipper trace-diff <a.log> <b.log> [--context <n>]
+       chipper disasm <rom> [--syntax cowgod|octo] [--range <a>-<b>] [--recursive]
+       chipper decompile <rom> [--octo]
+       chipper info <rom>
+       chipper cfg <rom> [-o <file.dot>] [--syntax cowgod|octo]
+       chipper asm <source> [-o <rom>] [--syntax cowgod|octo] [--symbols <file>]
+Options:
+  --debug                 start paused with a command-line debugger on stdin
+  --gdb <port>            wait for a gdb remote connection on a local port
+  --overlay               show registers, stack, memory and disassembly in the window
+  --trace <file>          write one line per executed instruction to file, the games
+                          played from the menu one after the other
+  --trace-range <a>-<b>   only trace instructions between addresses a and b
+  --trace-ring <n>        only write the last n lines, when execution fails
+  --profile               print the instructions executed per subroutine on exit
+  --profile-folded <file> also write folded call stacks for flamegraph tools
+  --heatmap               show ram reads, writes and executes in a second window
+  --heatmap-out <file>    write the session's heatmap as a PPM image on exit
+  --smc                   report self-modifying code on exit
+  --watch                 reload the rom and restart it whenever its file changes
+  --coverage <file>       write a coverage report on exit (HTML if file ends in .html)
+  --symbols <file>        debug symbols written by `chipper asm --symbols`, naming
+                          addresses in traces, the debugger, profiles and errors
+  --rom-db <file>         chip-8-database programs.json whose roms take precedence
+                          over the embedded database of known roms";
+
+const HEATMAP_SCALE: usize = 6; // Pixels per side of a heatmap cell
+const NOTICE_DURATION: Duration = Duration::from_secs(1); // Of "RELOADED" in the window
+const MENU_FRAME: Duration = Duration::from_millis(16); // Between redraws of the launcher
+const WINDOW_TITLE: &str = "CHIP-8 Emulator";
+
+/// Command-line options of the emulator
+struct Options {
+    filename: String,
+    debug: bool,
+    gdb_port: Option<u16>,
+    overlay: bool,
+    trace: Option<String>,
+    trace_range: Option<RangeInclusive<u16>>,
+    trace_ring: Option<usize>,
+    profile: bool,
+    profile_folded: Option<String>,
+    smc: bool,
+    watch: bool,
+    heatmap: bool,
+    heatmap_out: Option<String>,
+    coverage: Option<String>,
+    symbols: Option<String>,
+    rom_db: Option<String>,
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            filename: String::from("roms"),
+            debug: false,
+            gdb_port: None,
+            overlay: false,
+            trace: None,
+            trace_range: None,
+            trace_ring: None,
+            profile: false,
+            profile_folded: None,
+            smc: false,
+            watch: false,
+            heatmap: false,
+            heatmap_out: None,
+            coverage: None,
+            symbols: None,
+            rom_db: None,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--debug" => options.debug = true,
+                "--gdb" => {
+                    let port = args.next().ok_or("Missing port after --gdb")?;
+                    let port = port
+                        .parse()
+                        .map_err(|_| format!("Invalid port: {}", port))?;
+                    options.gdb_port = Some(port);
+                }
+                "--overlay" => options.overlay = true,
+                "--trace" => options.trace = Some(args.next().ok_or("Missing file after --trace")?),
+                "--trace-range" => {
+                    let range = args.next().ok_or("Missing range after --trace-range")?;
+                    options.trace_range = Some(parse_range(&range)?);
+                }
+                "--trace-ring" => {
+                    let len = args.next().ok_or("Missing length after --trace-ring")?;
+                    options.trace_ring = Some(parse_number(&len)? as usize);
+                }
+                "--profile" => options.profile = true,
+                "--profile-folded" => {
+                    let path = args.next().ok_or("Missing file after --profile-folded")?;
+                    options.profile = true;
+                    options.profile_folded = Some(path);
+                }
+                "--smc" => options.smc = true,
+                "--watch" => options.watch = true,
+                "--heatmap" => options.heatmap = true,
+                "--heatmap-out" => {
+                    options.heatmap_out =
+                        Some(args.next().ok_or("Missing file after --heatmap-out")?)
+                }
+                "--coverage" => {
+                    options.coverage = Some(args.next().ok_or("Missing file after --coverage")?)
+                }
+                "--symbols" => {
+                    options.symbols = Some(args.next().ok_or("Missing file after --symbols")?)
+                }
+                "--rom-db" => {
+                    options.rom_db = Some(args.next().ok_or("Missing file after --rom-db")?)
+                }
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+                _ => options.filename = arg,
+            }
+        }
+        if options.debug && options.gdb_port.is_some() {
+            return Err(String::from("--debug and --gdb can't be used together"));
+        }
+        if options.trace.is_none()
+            && (options.trace_range.is_some() || options.trace_ring.is_some())
+        {
+            return Err(String::from("--trace-range and --trace-ring need --trace"));
+        }
+        Ok(options)
+    }
+}
+
+/// Parses an inclusive `<a>-<b>` address range
+fn parse_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| format!("Invalid range: {}", range))?;
+    Ok(parse_number(start)?..=parse_number(end)?)
+}
+
+/// Reads debugger commands from stdin on its own thread, so the window keeps refreshing
+fn spawn_command_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break; // Frontend is gone
+            }
+        }
+    });
+    receiver
+}
+
+fn prompt() {
+    print!("(chipper) ");
+    io::stdout().flush().unwrap();
+}
+
+/// Why a subcommand failed, the usage being printed only for bad arguments
+enum Failure {
+    Usage(String),
+    Runtime(String), // Unreadable files, assembly errors...
+}
+
+impl From<String> for Failure {
+    fn from(err: String) -> Self {
+        Failure::Runtime(err)
+    }
+}
+
+fn usage(err: impl Into<String>) -> Failure {
+    Failure::Usage(err.into())
+}
+
+/// Compares two execution traces, exiting with 1 when they diverge
+fn trace_diff(args: &[String]) -> Result<i32, Failure> {
+    let (paths, context) = match args {
+        [a, b] => ([a, b], 3),
+        [a, b, flag, n] if flag == "--context" => {
+            ([a, b], parse_number(n).map_err(usage)? as usize)
+        }
+        _ => return Err(usage("trace-diff takes two trace files")),
     };
+    let [a, b] = paths.map(|path| {
+        fs::read_to_string(path)
+            .map(|trace| parse_trace(&trace))
+            .map_err(|err| format!("Couldn't read {}: {}", path, err))
+    });
+    let (a, b) = (a?, b?);
+    match first_divergence(&a, &b, context) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            Ok(1)
+        }
+        None => {
+            println!("Traces match over {} instruction(s)", a.len().min(b.len()));
+            Ok(0)
+        }
+    }
+}
 
-    let mut file = File::open(filename).unwrap();
-    let mut rom = Vec::new();
-    if let Err(err) = file.read_to_end(&mut rom) {
-        // Fit in ram is checked in rom loading
-        panic!("An error occured: {}", err);
+/// Entry point of a subcommand, returning the exit code
+type Subcommand = fn(&[String]) -> Result<i32, Failure>;
+
+/// Prints the disassembly of a rom
+fn disasm(args: &[String]) -> Result<i32, Failure> {
+    let mut args = args.iter();
+    let path = args
+        .next()
+        .ok_or_else(|| usage("disasm takes a rom file"))?;
+    let (mut syntax, mut range, mut recursive) = (Syntax::default(), None, false);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                syntax = args
+                    .next()
+                    .ok_or_else(|| usage("Missing syntax after --syntax"))?
+                    .parse()
+                    .map_err(usage)?
+            }
+            "--range" => {
+                let range_arg = args
+                    .next()
+                    .ok_or_else(|| usage("Missing range after --range"))?;
+                range = Some(parse_range(range_arg).map_err(usage)?)
+            }
+            "--recursive" => recursive = true,
+            _ => return Err(usage(format!("Unknown disasm argument: {}", arg))),
+        }
+    }
+    let rom = read_rom(path)?;
+    if recursive {
+        print!("{}", disassemble_recursive(&rom, syntax, range));
+    } else {
+        print!("{}", disassemble(&rom, syntax, range));
     }
+    Ok(0)
+}
+
+/// Prints a rom as structured pseudo-code, or Octo source
+fn decompile_rom(args: &[String]) -> Result<i32, Failure> {
+    let mut args = args.iter();
+    let path = args
+        .next()
+        .ok_or_else(|| usage("decompile takes a rom file"))?;
+    let mut octo = false;
+    for arg in args {
+        match arg.as_str() {
+            "--octo" => octo = true,
+            _ => return Err(usage(format!("Unknown decompile argument: {}", arg))),
+        }
+    }
+    let rom = read_rom(path)?;
+    let decompiled = decompile(&rom);
+    if octo {
+        print!("{}", decompiled.to_octo());
+    } else {
+        print!("{}", decompiled.to_pseudo_code());
+    }
+    Ok(0)
+}
+
+/// Prints the size, hash and opcodes of a rom, with the platform and quirks it seems made for,
+/// and its entry of the embedded database
+fn info(args: &[String]) -> Result<i32, Failure> {
+    let path = match args {
+        [path] => path,
+        _ => return Err(usage("info takes a rom file")),
+    };
+    let rom = read_rom(path)?;
+    print!("{}", Analysis::analyse(&rom).report());
+    if let Some(entry) = RomDb::embedded().lookup(&rom) {
+        println!(
+            "
+Database entry: {}",
+            entry
+        );
+        if let Some(quirks) = entry.quirks() {
+            // The cpu has fixed quirks, only the database's speed, keys and colours get used
+            println!("Database quirks (advisory, not applied): {}", quirks);
+        }
+    }
+    Ok(0)
+}
+
+/// Writes the control flow graph of a rom as a Graphviz graph, to stdout by default
+fn cfg(args: &[String]) -> Result<i32, Failure> {
+    let mut args = args.iter();
+    let path = args.next().ok_or_else(|| usage("cfg takes a rom file"))?;
+    let (mut syntax, mut out) = (Syntax::default(), None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out = Some(args.next().ok_or_else(|| usage("Missing file after -o"))?),
+            "--syntax" => {
+                syntax = args
+                    .next()
+                    .ok_or_else(|| usage("Missing syntax after --syntax"))?
+                    .parse()
+                    .map_err(usage)?
+            }
+            _ => return Err(usage(format!("Unknown cfg argument: {}", arg))),
+        }
+    }
+    let rom = read_rom(path)?;
+    let dot = Cfg::build(&rom)
+        .map_err(|err| format!("{}: {}", path, err))?
+        .to_dot(syntax);
+    match out {
+        Some(out) => {
+            fs::write(out, dot).map_err(|err| format!("Couldn't write {}: {}", out, err))?
+        }
+        None => print!("{}", dot),
+    }
+    Ok(0)
+}
+
+fn asm(args: &[String]) -> Result<i32, Failure> {
+    let mut args = args.iter();
+    let path = args
+        .next()
+        .ok_or_else(|| usage("asm takes a source file"))?;
+    let mut out = Path::new(path).with_extension("ch8");
+    let mut symbols = None;
+    // Octo sources are recognized by their extension unless told otherwise
+    let mut syntax = if path.ends_with(".8o") {
+        Syntax::Octo
+    } else {
+        Syntax::Cowgod
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out = PathBuf::from(args.next().ok_or_else(|| usage("Missing file after -o"))?),
+            "--symbols" => {
+                symbols = Some(
+                    args.next()
+                        .ok_or_else(|| usage("Missing file after --symbols"))?,
+                )
+            }
+            "--syntax" => {
+                syntax = args
+                    .next()
+                    .ok_or_else(|| usage("Missing syntax after --syntax"))?
+                    .parse()
+                    .map_err(usage)?
+            }
+            _ => return Err(usage(format!("Unknown asm argument: {}", arg))),
+        }
+    }
+    let source =
+        fs::read_to_string(path).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+    let program = match syntax {
+        Syntax::Cowgod => cowgod::assemble(&source),
+        Syntax::Octo => octo::assemble(&source),
+    }
+    .map_err(|err| format!("{}: {}", path, err))?;
+    fs::write(&out, &program.rom)
+        .map_err(|err| format!("Couldn't write {}: {}", out.display(), err))?;
+    println!("Wrote {} bytes to {}", program.rom.len(), out.display());
+    if let Some(symbols) = symbols {
+        // Locations name the source as given, e.g. `tetris.8o:120`
+        let file = Path::new(path)
+            .file_name()
+            .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+        fs::write(symbols, program.symbols(&file).to_text())
+            .map_err(|err| format!("Couldn't write {}: {}", symbols, err))?;
+        println!("Wrote symbols to {}", symbols);
+    }
+    Ok(0)
+}
+
+/// How a rom is run, from what the rom database knows about it
+struct RomSettings {
+    palette: [u32; 2],
+    instruction_period: Duration,
+}
+
+/// Looks a rom up in the database, printing what's known of it
+fn rom_settings(rom: &[u8], db: &RomDb) -> RomSettings {
+    let mut settings = RomSettings {
+        palette: DEFAULT_PALETTE,
+        instruction_period: Duration::from_millis(1),
+    };
+    let entry = match db.lookup(rom) {
+        Some(entry) => entry,
+        None => return settings,
+    };
+
+    println!("{}", entry);
+    let keys: Vec<String> = entry
+        .rom
+        .keys
+        .iter()
+        .map(|(action, key)| match keyboard_key(*key) {
+            Some(pressed) => format!("{} {:?} ({:X})", action, pressed, key),
+            None => format!("{} ({:X})", action, key),
+        })
+        .collect();
+    if !keys.is_empty() {
+        println!("Keys: {}", keys.join(", "));
+    }
+    if let Some(quirks) = entry.quirks() {
+        let differences = quirks.differences(&CHIPPER_QUIRKS);
+        if !differences.is_empty() {
+            println!(
+                "Quirks of {} not emulated: {}",
+                quirks.name,
+                differences.join(", ")
+            );
+        }
+    }
+    if let Some(tickrate) = entry.tickrate() {
+        settings.instruction_period = Duration::from_secs(1) / (60 * tickrate);
+    }
+    if let Some(palette) = entry.palette() {
+        settings.palette = palette;
+    }
+    settings
+}
+
+/// A rom loaded into an interpreter, with the tools the options ask for
+struct Game {
+    path: String,
+    chip8: Interpreter,
+    settings: RomSettings, // Kept on reloads, rebuilt roms not being in the database
+    symbols_modified: Option<SystemTime>, // Of the symbols file when last read
+}
+
+/// How playing a rom ended
+enum Ending {
+    Quit,          // Window closed, Escape, or quit from the debugger or gdb
+    Finished,      // The program exited
+    Menu,          // Backspace, back to the launcher
+    Error(String), // The cpu stopped on an instruction it can't run
+}
+
+/// Loads a rom, setting up symbols, tracing and the other tools asked for
+fn load_game(options: &Options, path: &str, db: &RomDb) -> Result<Game, String> {
+    let rom = read_rom(path)?;
+    let settings = rom_settings(&rom, db);
 
     let mut chip8 = Interpreter::new();
-    chip8.load_rom(rom); // Each byte is loaded as is, the cpu then assembles words
+    // Each byte is loaded as is, the cpu then assembles words
+    let warnings = chip8
+        .load_rom(rom)
+        .map_err(|err| format!("Couldn't load rom {}: {}", path, err))?;
+    for warning in warnings {
+        eprintln!("Warning: {}: {}", path, warning);
+    }
+
+    let symbols_modified = options.symbols.as_deref().and_then(modified);
+    if let Some(path) = &options.symbols {
+        chip8.symbols = read_symbols(path)?;
+    }
+
+    // Emptied once on startup, games of the menu not erasing the traces of the previous ones
+    if let Some(path) = &options.trace {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("Couldn't open trace file {}: {}", path, err))?;
+        let mut tracer: Tracer = Tracer::new(Box::new(BufWriter::new(file)));
+        if let Some(range) = options.trace_range.clone() {
+            tracer.set_range(range);
+        }
+        if let Some(len) = options.trace_ring {
+            tracer.set_ring(len);
+        }
+        tracer.set_symbols(chip8.symbols.clone());
+        chip8.tracer = Some(tracer);
+    }
+
+    if options.coverage.is_some() {
+        chip8.cpu.enable_coverage();
+    }
+    if options.profile {
+        chip8.profiler = Some(Profiler::new());
+    }
+    if options.smc {
+        chip8.smc = Some(SmcDetector::new());
+    }
+    if options.heatmap || options.heatmap_out.is_some() {
+        chip8.heatmap = Some(Heatmap::new());
+    }
+
+    Ok(Game {
+        path: path.to_string(),
+        chip8,
+        settings,
+        symbols_modified,
+    })
+}
 
-    let mut window = Window::new(
-        "CHIP-8 Emulator",
-        SCREEN_WIDTH,
-        SCREEN_HEIGHT,
-        WindowOptions::default(),
-    )
-    .unwrap_or_else(|_| panic!("Couldn't create window"));
-    window.set_title("CHIP-8 Emulator");
+fn read_symbols(path: &str) -> Result<Symbols, String> {
+    fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| Symbols::parse(&text))
+        .map_err(|err| format!("Couldn't load symbols {}: {}", path, err))
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Reads the symbols file again when it was rebuilt since it was last read, with a reloaded rom
+fn refresh_symbols(options: &Options, game: &mut Game) {
+    let path = match &options.symbols {
+        Some(path) => path,
+        None => return,
+    };
+    let symbols_modified = modified(path);
+    if symbols_modified <= game.symbols_modified {
+        return;
+    }
+    match read_symbols(path) {
+        Ok(symbols) => {
+            if let Some(tracer) = &mut game.chip8.tracer {
+                tracer.set_symbols(symbols.clone());
+            }
+            game.chip8.symbols = symbols;
+            game.symbols_modified = symbols_modified;
+            println!("Reloaded symbols {}", path);
+        }
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+fn window_size(options: &Options) -> (usize, usize) {
+    if options.overlay {
+        (DEBUG_WIDTH, DEBUG_HEIGHT)
+    } else {
+        (SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+}
+
+fn open_window(options: &Options) -> Window {
+    let (width, height) = window_size(options);
+    let mut window = Window::new(WINDOW_TITLE, width, height, WindowOptions::default())
+        .unwrap_or_else(|_| panic!("Couldn't create window"));
+    window.set_title(WINDOW_TITLE);
+    window
+}
+
+/// Waits for gdb to connect on the port
+fn connect_gdb(port: u16) -> Result<GdbStub, String> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|err| format!("Couldn't listen on port {}: {}", port, err))?;
+    println!("Waiting for a gdb connection on 127.0.0.1:{}", port);
+    GdbStub::accept(&listener).map_err(|err| format!("Couldn't accept gdb: {}", err))
+}
+
+/// Runs a game in the window until it ends, Backspace leaving it when started from the menu
+fn play(
+    options: &Options,
+    game: &mut Game,
+    window: &mut Window,
+    menu: bool,
+    mut gdb: Option<GdbStub>,
+) -> Ending {
+    let mut debugger = if options.debug {
+        println!("Debugger attached, execution is paused. Type `help` for commands.");
+        let mut debugger = Debugger::new();
+        // `:breakpoint` directives of the source
+        for (addr, name) in game.chip8.symbols.breakpoints() {
+            debugger.set_breakpoint(*addr);
+            println!(
+                "Breakpoint {} set at {}",
+                name,
+                game.chip8.symbols.describe(*addr)
+            );
+        }
+        prompt();
+        Some((debugger, spawn_command_reader()))
+    } else {
+        None
+    };
+
+    let (width, height) = window_size(options);
+    let heatmap_side = HEATMAP_SIDE * HEATMAP_SCALE;
+    let mut heatmap_window = if options.heatmap {
+        let window = Window::new(
+            "CHIP-8 Memory Heatmap",
+            heatmap_side,
+            heatmap_side,
+            WindowOptions::default(),
+        )
+        .unwrap_or_else(|_| panic!("Couldn't create heatmap window"));
+        Some(window)
+    } else {
+        None
+    };
 
     let mut last_keyboard_instant = Instant::now();
     let kb_epsilon = 50;
     let mut last_instruction_instant = Instant::now();
-    let instruction_epsilon = 1;
     let mut last_display_instant = Instant::now();
     let display_epsilon = 10;
+    let mut watcher = options
+        .watch
+        .then(|| Watcher::new(&game.path, game.chip8.cpu.mem().rom().to_vec()));
+    let mut last_watch_instant = Instant::now();
+    let watch_epsilon = 250;
+    let mut notice: Option<(Instant, &str)> = None;
+    // When watching, a program that stopped waits for the next reload rather than closing
+    let mut stopped: Option<&str> = None;
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        // Escape to exit
+    let mut ending = Ending::Quit;
+    'main: while window.is_open() && !window.is_key_down(Key::Escape) {
+        // Escape to exit, Backspace to get back to the menu
+        if menu && window.is_key_down(Key::Backspace) {
+            ending = Ending::Menu;
+            break;
+        }
         let keys_pressed = window.get_keys_pressed(KeyRepeat::Yes); // get all the presently pressed keys
         let key = if !keys_pressed.is_empty() {
             Some(keys_pressed[0]) // Interest only for the first one
@@ -53,33 +659,317 @@ fn main() {
         if key.is_some()
             || Instant::now() - last_keyboard_instant >= Duration::from_millis(kb_epsilon)
         {
-            chip8.feed_key(get_key_opcode(key)); // We feed into KeyBoard the just (valid) got key
+            game.chip8.feed_key(get_key_opcode(key)); // We feed into KeyBoard the just (valid) got key
             last_keyboard_instant = Instant::now(); // Instant refresh
         }
 
+        //debugger commands, handled as soon as they are typed
+        if let Some((debugger, commands)) = &mut debugger {
+            while let Ok(line) = commands.try_recv() {
+                if !line.trim().is_empty() {
+                    match line.parse::<Command>() {
+                        Ok(Command::Quit) => break 'main,
+                        Ok(command) => println!("{}", debugger.execute(command, &mut game.chip8)),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+                prompt();
+            }
+        }
+
+        //gdb packets, handled as soon as they are received
+        if let Some(stub) = &mut gdb {
+            match stub.poll(&mut game.chip8) {
+                Ok(Session::Attached) => (),
+                Ok(Session::Detached) => gdb = None, // The program keeps running on its own
+                Ok(Session::Killed) => break,
+                Err(err) => {
+                    eprintln!("gdb connection failed, detaching: {}", err);
+                    gdb = None;
+                }
+            }
+        }
+
+        //rom file watching clock, the program restarting when the rom gets rebuilt
+        if let Some(watcher) = &mut watcher {
+            if Instant::now() - last_watch_instant > Duration::from_millis(watch_epsilon) {
+                let reloaded = watcher
+                    .poll()
+                    .map(|rom| rom.and_then(|rom| game.chip8.reload_rom(rom)));
+                match reloaded {
+                    Some(Ok(warnings)) => {
+                        for warning in warnings {
+                            eprintln!("Warning: {}: {}", game.path, warning);
+                        }
+                        refresh_symbols(options, game);
+                        if let Some((debugger, _)) = &mut debugger {
+                            debugger.restart();
+                        }
+                        stopped = None;
+                        println!("Reloaded {}", game.path);
+                        notice = Some((Instant::now(), "RELOADED"));
+                    }
+                    Some(Err(err)) => {
+                        eprintln!("Couldn't reload {}: {}", game.path, err);
+                        notice = Some((Instant::now(), "RELOAD FAILED"));
+                    }
+                    None => (),
+                }
+                last_watch_instant = Instant::now(); // Instant refresh
+            }
+        }
+
         //instruction executing clock
-        if Instant::now() - last_instruction_instant > Duration::from_millis(instruction_epsilon) {
-            match chip8.tick() {
-                // get cpu state
-                CpuState::Error(err) => panic!("{}", err),
-                CpuState::Finished => break,
-                _ => (),
+        if stopped.is_none()
+            && Instant::now() - last_instruction_instant > game.settings.instruction_period
+        {
+            match (&mut debugger, &mut gdb) {
+                (_, Some(stub)) => {
+                    if let Err(err) = stub.tick(&mut game.chip8) {
+                        eprintln!("gdb connection failed, detaching: {}", err);
+                        gdb = None;
+                    }
+                }
+                (Some((debugger, _)), None) => {
+                    let stop = debugger.tick(&mut game.chip8);
+                    for log in debugger.drain_logs() {
+                        println!("{}", log);
+                    }
+                    if let Some(stop) = stop {
+                        println!("\n{}", stop.describe(&game.chip8.symbols));
+                        prompt();
+                    }
+                }
+                (None, None) => match game.chip8.tick() {
+                    // get cpu state
+                    CpuState::Error(err) if watcher.is_some() => {
+                        eprintln!("{}: {}", game.path, err);
+                        stopped = Some("ERROR");
+                    }
+                    CpuState::Error(err) => {
+                        ending = Ending::Error(err);
+                        break;
+                    }
+                    CpuState::Finished if watcher.is_some() => stopped = Some("FINISHED"),
+                    CpuState::Finished => {
+                        ending = Ending::Finished;
+                        break;
+                    }
+                    _ => (),
+                },
             }
             last_instruction_instant = Instant::now(); // Instant refresh
         }
 
         //display clock
         if Instant::now() - last_display_instant > Duration::from_millis(display_epsilon) {
-            window
-                .update_with_buffer(
-                    &chip8.cpu.vram().to_screen_buffer(),
-                    SCREEN_WIDTH,
-                    SCREEN_HEIGHT,
-                )
-                .unwrap();
+            let buffer = if options.overlay {
+                let paused = match (&debugger, &gdb) {
+                    (Some((debugger, _)), _) => debugger.is_paused(),
+                    (_, Some(stub)) => !stub.is_running(),
+                    _ => false,
+                };
+                overlay::render(&game.chip8, if paused { "PAUSED" } else { "RUNNING" })
+            } else {
+                game.chip8
+                    .cpu
+                    .vram()
+                    .to_screen_buffer_with(game.settings.palette)
+            };
+            let text = match notice {
+                Some((since, text)) if since.elapsed() < NOTICE_DURATION => Some(text),
+                _ => stopped,
+            };
+            let buffer = match text {
+                Some(text) => overlay::notice(buffer, width, height, text),
+                None => buffer,
+            };
+            window.update_with_buffer(&buffer, width, height).unwrap();
+            if let (Some(heatmap_window), Some(heatmap)) =
+                (&mut heatmap_window, &mut game.chip8.heatmap)
+            {
+                heatmap_window
+                    .update_with_buffer(
+                        &heatmap.to_buffer(HEATMAP_SCALE),
+                        heatmap_side,
+                        heatmap_side,
+                    )
+                    .unwrap();
+                heatmap.decay();
+            }
+            if heatmap_window
+                .as_ref()
+                .is_some_and(|window| !window.is_open())
+            {
+                heatmap_window = None; // Closing it doesn't stop the emulator
+            }
             last_display_instant = Instant::now(); // Instant refresh
         }
     }
 
+    ending
+}
+
+/// Writes the reports asked for on the session of chip8
+fn write_reports(options: &Options, chip8: &Interpreter) {
+    if let (Some(path), Some(coverage)) = (&options.coverage, chip8.cpu.coverage()) {
+        let rom_len = chip8.cpu.mem().rom().len();
+        let line_map = options.symbols.as_ref().map(|_| chip8.symbols.lines());
+        let report = if path.ends_with(".html") {
+            coverage.html_report(rom_len, line_map)
+        } else {
+            coverage.text_report(rom_len, line_map)
+        };
+        if let Err(err) = fs::write(path, report) {
+            eprintln!("Couldn't write coverage report {}: {}", path, err);
+        }
+    }
+
+    if let Some(profiler) = &chip8.profiler {
+        print!("{}", profiler.summary(chip8.cpu.mem(), &chip8.symbols));
+        if let Some(path) = &options.profile_folded {
+            if let Err(err) = fs::write(path, profiler.folded(&chip8.symbols)) {
+                eprintln!("Couldn't write folded stacks {}: {}", path, err);
+            }
+        }
+    }
+
+    if let (Some(path), Some(heatmap)) = (&options.heatmap_out, &chip8.heatmap) {
+        let written = File::create(path)
+            .and_then(|file| heatmap.write_ppm(&mut BufWriter::new(file), HEATMAP_SCALE));
+        if let Err(err) = written {
+            eprintln!("Couldn't write heatmap {}: {}", path, err);
+        }
+    }
+
+    if let Some(smc) = &chip8.smc {
+        print!("{}", smc.report_text());
+    }
+}
+
+/// Shows the menu of a directory's roms until Escape, playing those picked
+fn launch(options: &Options, db: &RomDb, launcher: &mut Launcher, window: &mut Window) {
+    let (width, height) = window_size(options);
+    let page = Launcher::page_len(height);
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let mut picked = None;
+        for key in window.get_keys_pressed(KeyRepeat::Yes) {
+            picked = picked.or(launcher.handle_key(key, page));
+        }
+        if let Some(path) = picked {
+            match load_game(options, &path.to_string_lossy(), db) {
+                Ok(mut game) => {
+                    launcher.set_status("");
+                    if let Some(item) = launcher.selected() {
+                        window.set_title(&format!("{} - {}", WINDOW_TITLE, item.title));
+                    }
+                    let ending = play(options, &mut game, window, true, None);
+                    write_reports(options, &game.chip8);
+                    window.set_title(WINDOW_TITLE);
+                    match ending {
+                        Ending::Quit => return,
+                        Ending::Error(err) => launcher.set_status(&err),
+                        Ending::Finished | Ending::Menu => (),
+                    }
+                }
+                Err(err) => launcher.set_status(&err),
+            }
+        }
+        window
+            .update_with_buffer(&launcher.render(width, height), width, height)
+            .unwrap();
+        thread::sleep(MENU_FRAME);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let subcommand: Option<Subcommand> = match args.first().map(String::as_str) {
+        Some("trace-diff") => Some(trace_diff),
+        Some("disasm") => Some(disasm),
+        Some("decompile") => Some(decompile_rom),
+        Some("cfg") => Some(cfg),
+        Some("info") => Some(info),
+        Some("asm") => Some(asm),
+        _ => None,
+    };
+    if let Some(subcommand) = subcommand {
+        let code = match subcommand(&args[1..]) {
+            Ok(code) => code,
+            Err(Failure::Usage(err)) => {
+                eprintln!("{}\n{}", err, USAGE);
+                2
+            }
+            Err(Failure::Runtime(err)) => {
+                eprintln!("{}", err);
+                1
+            }
+        };
+        process::exit(code);
+    }
+
+    let options = Options::parse(args).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let mut db = RomDb::embedded();
+    if let Some(path) = &options.rom_db {
+        let local = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| RomDb::parse(&json));
+        match local {
+            Ok(local) => db.merge(local),
+            Err(err) => {
+                eprintln!("Couldn't load rom database {}: {}", path, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = &options.trace {
+        if let Err(err) = File::create(path) {
+            eprintln!("Couldn't create trace file {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+
+    let dir = Path::new(&options.filename);
+    if dir.is_dir() {
+        if options.debug || options.gdb_port.is_some() {
+            eprintln!(
+                "--debug and --gdb need a rom file, not a directory\n{}",
+                USAGE
+            );
+            process::exit(2);
+        }
+        let mut launcher = Launcher::scan(dir, &db).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        let mut window = open_window(&options);
+        launch(&options, &db, &mut launcher, &mut window);
+    } else {
+        let mut game = load_game(&options, &options.filename, &db).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        let gdb = options
+            .gdb_port
+            .map(connect_gdb)
+            .transpose()
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        let mut window = open_window(&options);
+        let ending = play(&options, &mut game, &mut window, false, gdb);
+        write_reports(&options, &game.chip8);
+        if let Ending::Error(err) = ending {
+            eprintln!("{}: {}", game.path, err);
+            process::exit(1);
+        }
+    }
+
     println!("Program finished was that cool?\nYessir.");
 }
@@ -0,0 +1,152 @@
+//! Speaks the GDB remote serial protocol to a headless stub over localhost
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+use chipper::chip8::gdb::{GdbStub, Session};
+use chipper::chip8::Interpreter;
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.stream.write_all(packet.as_bytes()).unwrap();
+        assert_eq!(self.read_byte(), b'+');
+    }
+
+    fn recv(&mut self) -> String {
+        while self.read_byte() != b'$' {}
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(
+            checksum,
+            data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.recv()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+}
+
+fn start(rom: Vec<u8>) -> (Client, JoinHandle<Session>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut chip8 = Interpreter::new();
+        chip8.load_rom(rom).unwrap();
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        stub.serve(&mut chip8).unwrap()
+    });
+    let stream = TcpStream::connect(addr).unwrap();
+    (Client { stream }, server)
+}
+
+// LD V0, 0x12; LD I, 0x300; ADD V0, 1; JP 0x204
+const LOOP_ROM: [u8; 8] = [0x60, 0x12, 0xA3, 0x00, 0x70, 0x01, 0x12, 0x04];
+
+#[test]
+fn handshake_and_registers() {
+    let (mut client, server) = start(LOOP_ROM.to_vec());
+    assert!(client
+        .request("qSupported:multiprocess+")
+        .contains("qXfer:features:read+"));
+    let xml = client.request("qXfer:features:read:target.xml:0,fff");
+    assert!(xml.starts_with('l'));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16""#));
+    assert_eq!(client.request("?"), "S05");
+
+    // V0-VF, then I and PC little endian, then SP, DT and ST
+    let regs = client.request("g");
+    assert_eq!(regs.len(), 46);
+    assert_eq!(&regs[32..40], "00000002");
+
+    assert_eq!(client.request("P3=7f"), "OK");
+    assert_eq!(client.request("p3"), "7f");
+    assert_eq!(client.request("p11"), "0002");
+
+    client.send("k");
+    assert_eq!(server.join().unwrap(), Session::Killed);
+}
+
+#[test]
+fn memory_access() {
+    let (mut client, server) = start(LOOP_ROM.to_vec());
+    assert_eq!(client.request("m200,4"), "6012a300");
+    assert_eq!(client.request("M300,3:abcdef"), "OK");
+    assert_eq!(client.request("m300,3"), "abcdef");
+    assert_eq!(client.request("mfff,2"), "E01");
+
+    assert_eq!(client.request("D"), "OK");
+    assert_eq!(server.join().unwrap(), Session::Detached);
+}
+
+#[test]
+fn step_breakpoint_and_continue() {
+    let (mut client, server) = start(LOOP_ROM.to_vec());
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p0"), "12");
+    assert_eq!(client.request("p11"), "0202");
+
+    assert_eq!(client.request("Z0,204,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p11"), "0402");
+    assert_eq!(client.request("p10"), "0003");
+
+    // The loop comes back to the breakpoint after one iteration
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p0"), "13");
+
+    assert_eq!(client.request("z0,204,2"), "OK");
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.recv(), "S02");
+
+    client.send("k");
+    assert_eq!(server.join().unwrap(), Session::Killed);
+}
+
+#[test]
+fn stops_on_error() {
+    let (mut client, server) = start(LOOP_ROM.to_vec());
+    // A pc with no word to fetch stops the program like an invalid opcode would
+    assert_eq!(client.request("P11=ff0f"), "OK");
+    assert_eq!(client.request("s"), "S04");
+    assert_eq!(client.request("p11"), "ff0f");
+
+    client.send("k");
+    assert_eq!(server.join().unwrap(), Session::Killed);
+}
+
+#[test]
+fn watchpoints() {
+    // LD I, 0x300; LD [I], V1; JP 0x204
+    let (mut client, server) = start(vec![0xA3, 0x00, 0xF1, 0x55, 0x12, 0x04]);
+    assert_eq!(client.request("Z2,301,1"), "OK");
+    assert_eq!(client.request("c"), "T05watch:301;");
+    assert_eq!(client.request("p11"), "0402");
+    assert_eq!(client.request("z2,301,1"), "OK");
+
+    client.send("k");
+    assert_eq!(server.join().unwrap(), Session::Killed);
+}